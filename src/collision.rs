@@ -1,5 +1,6 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufReader, Read};
+use std::mem;
 use crate::io;
 
 #[derive(Clone, Debug)]
@@ -50,6 +51,9 @@ pub struct PlaneBoundaries {
 }
 
 //Axis-aligned bounding box
+//position is the box's center, with w = 1.0 so it can be multiplied by a transform directly
+//width, depth and height are the full extents along x, y and z respectively
+#[derive(Clone, Debug, PartialEq)]
 pub struct AABB {
     pub position: glm::TVec4<f32>,
     pub width: f32,
@@ -57,17 +61,154 @@ pub struct AABB {
     pub height: f32
 }
 
+impl AABB {
+    pub fn new(center: glm::TVec3<f32>, width: f32, depth: f32, height: f32) -> Self {
+        AABB {
+            position: glm::vec4(center.x, center.y, center.z, 1.0),
+            width,
+            depth,
+            height
+        }
+    }
+
+    pub fn from_min_max(min: &glm::TVec3<f32>, max: &glm::TVec3<f32>) -> Self {
+        let size = max - min;
+        AABB::new(0.5 * (min + max), size.x, size.y, size.z)
+    }
+
+    //None when there are no points
+    pub fn from_points(points: &[glm::TVec3<f32>]) -> Option<Self> {
+        let first = points.first()?;
+        let (min, max) = points.iter().fold((*first, *first), |(min, max), p| { (glm::min2(&min, p), glm::max2(&max, p)) });
+        Some(AABB::from_min_max(&min, &max))
+    }
+
+    pub fn from_triangle(triangle: &Triangle) -> Self {
+        let min = glm::min2(&triangle.a, &glm::min2(&triangle.b, &triangle.c));
+        let max = glm::max2(&triangle.a, &glm::max2(&triangle.b, &triangle.c));
+        AABB::from_min_max(&min, &max)
+    }
+
+    //Bounds the vertices of the mesh, or None if it has none
+    pub fn from_mesh(mesh: &MeshCollision) -> Option<Self> {
+        AABB::from_points(&mesh.vertices)
+    }
+
+    pub fn center(&self) -> glm::TVec3<f32> {
+        self.position.xyz()
+    }
+
+    pub fn half_extents(&self) -> glm::TVec3<f32> {
+        0.5 * glm::vec3(self.width, self.depth, self.height)
+    }
+
+    pub fn min(&self) -> glm::TVec3<f32> {
+        self.center() - self.half_extents()
+    }
+
+    pub fn max(&self) -> glm::TVec3<f32> {
+        self.center() + self.half_extents()
+    }
+
+    //Smallest box containing both
+    pub fn union(&self, other: &AABB) -> Self {
+        AABB::from_min_max(&glm::min2(&self.min(), &other.min()), &glm::max2(&self.max(), &other.max()))
+    }
+
+    //Grows the box by margin on every side
+    pub fn expanded(&self, margin: f32) -> Self {
+        AABB::new(self.center(), self.width + 2.0 * margin, self.depth + 2.0 * margin, self.height + 2.0 * margin)
+    }
+
+    //Boxes that only touch count as intersecting
+    pub fn intersects(&self, other: &AABB) -> bool {
+        boxes_overlap(&self.min(), &self.max(), &other.min(), &other.max())
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        glm::distance2(&self.closest_point(&sphere.focus), &sphere.focus) <= sphere.radius * sphere.radius
+    }
+
+    //Slab test returning the ray's entry and exit t, where t is in units of ray.direction
+    //Entry is negative when the ray starts inside the box. Boxes entirely behind the ray are a miss
+    pub fn ray_intersection(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let min = self.min();
+        let max = self.max();
+        let mut t_enter = -f32::INFINITY;
+        let mut t_exit = f32::INFINITY;
+        for i in 0..3 {
+            if ray.direction[i] == 0.0 {
+                if ray.origin[i] < min[i] || ray.origin[i] > max[i] { return None; }
+            } else {
+                let t0 = (min[i] - ray.origin[i]) / ray.direction[i];
+                let t1 = (max[i] - ray.origin[i]) / ray.direction[i];
+                t_enter = f32::max(t_enter, f32::min(t0, t1));
+                t_exit = f32::min(t_exit, f32::max(t0, t1));
+            }
+        }
+
+        if t_enter <= t_exit && t_exit >= 0.0 {
+            Some((t_enter, t_exit))
+        } else {
+            None
+        }
+    }
+
+    //The point itself when it's inside the box
+    pub fn closest_point(&self, point: &glm::TVec3<f32>) -> glm::TVec3<f32> {
+        glm::clamp_vec(point, &self.min(), &self.max())
+    }
+
+    //Points on the boundary are contained
+    pub fn contains(&self, point: &glm::TVec3<f32>) -> bool {
+        let min = self.min();
+        let max = self.max();
+        point.x >= min.x && point.x <= max.x &&
+        point.y >= min.y && point.y <= max.y &&
+        point.z >= min.z && point.z <= max.z
+    }
+
+    //The axis-aligned box bounding this box after the transformation, from Graphics Gems' "Transforming Axis-Aligned Bounding Boxes"
+    pub fn transformed(&self, transform: &glm::TMat4<f32>) -> Self {
+        let center = transform * self.position;
+        let half_extents = self.half_extents();
+        let mut new_half_extents = glm::vec3(0.0, 0.0, 0.0);
+        for row in 0..3 {
+            for column in 0..3 {
+                new_half_extents[row] += f32::abs(transform[(row, column)]) * half_extents[column];
+            }
+        }
+        let center = center.xyz() / center.w;
+        AABB::new(center, 2.0 * new_half_extents.x, 2.0 * new_half_extents.y, 2.0 * new_half_extents.z)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Sphere {
     pub focus: glm::TVec3<f32>,
     pub radius: f32
 }
 
-#[derive(Debug)]
+//The radius is swept along the segment, so p0 and p1 are the centers of the end caps
+#[derive(Clone, Debug)]
 pub struct Capsule {
     pub segment: LineSegment,
     pub radius: f32
 }
 
+impl Capsule {
+    pub fn new(p0: glm::TVec3<f32>, p1: glm::TVec3<f32>, radius: f32) -> Self {
+        Capsule {
+            segment: LineSegment { p0, p1 },
+            radius
+        }
+    }
+
+    pub fn translated(&self, offset: &glm::TVec3<f32>) -> Self {
+        Capsule::new(self.segment.p0 + offset, self.segment.p1 + offset, self.radius)
+    }
+}
+
 pub struct Triangle {
     pub a: glm::TVec3<f32>,
     pub b: glm::TVec3<f32>,
@@ -83,88 +224,46 @@ pub struct MeshCollision {
 }
 
 impl MeshCollision {
+    //Panicking convenience wrapper around MeshCollision::load()
     pub fn from_ozt(path: &str) -> Self {
-        let mut terrain_file = match File::open(path) {
-            Ok(file) => { file }
-            Err(e) => {
-                panic!("Error reading {}: {}", path, e);
-            }
-        };
-
-        let vertices = {
-            let byte_count = match io::read_u32(&mut terrain_file) {
-                Ok(count) => { count as usize }
-                Err(e) => {
-                     panic!("Couldn't read byte count: {}", e);
-                }
-            };
+        match Self::load(path) {
+            Ok(terrain) => { terrain }
+            Err(e) => { panic!("Error reading {}: {}", path, e); }
+        }
+    }
 
-            let mut bytes = vec![0; byte_count];
-            if let Err(e) = terrain_file.read_exact(bytes.as_mut_slice()) {
-                panic!("Error reading vertex data from file: {}", e);
-            }
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let terrain_file = File::open(path)?;
+        Self::from_reader(&mut BufReader::new(terrain_file))
+    }
 
-            let byte_step = 12; // One f32 for each of x,y,z
-            let mut vertices = Vec::with_capacity(byte_count / byte_step);            
-            for i in (0..bytes.len()).step_by(byte_step) {
-                let x_bytes = [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
-                let y_bytes = [bytes[i + 4], bytes[i + 5], bytes[i + 6], bytes[i + 7]];
-                let z_bytes = [bytes[i + 8], bytes[i + 9], bytes[i + 10], bytes[i + 11]];
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
+        Self::from_reader(&mut &bytes[..])
+    }
 
-                let x = f32::from_le_bytes(x_bytes);
-                let y = f32::from_le_bytes(y_bytes);
-                let z = f32::from_le_bytes(z_bytes);
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R) -> std::io::Result<Self> {
+        //Both the vertex and face normal blocks are prefixed by their size in bytes and made of one f32 for each of x,y,z
+        fn read_vec3_block<R: Read + ?Sized>(reader: &mut R) -> std::io::Result<Vec<glm::TVec3<f32>>> {
+            let byte_count = io::read_u32(reader)? as usize;
+            let floats = io::read_f32_data(reader, byte_count / mem::size_of::<f32>())?;
+            Ok(floats.chunks_exact(3).map(|v| { glm::vec3(v[0], v[1], v[2]) }).collect())
+        }
 
-                vertices.push(glm::vec3(x, y, z));
-            }
-            vertices
-        };
+        let vertices = read_vec3_block(reader)?;
         
         let indices = {
-            let index_count = match io::read_u32(&mut terrain_file) {
-                Ok(n) => { (n / 2) as usize }
-                Err(e) => { panic!("Couldn't read byte count: {}", e); }
-            };
-            
-            let indices = match io::read_u16_data(&mut terrain_file, index_count) {
-                Ok(n) => { n }
-                Err(e) => { panic!("Couldn't read byte count: {}", e); }
-            };
+            let index_count = io::read_u32(reader)? as usize / mem::size_of::<u16>();
+            let indices = io::read_u16_data(reader, index_count)?;
             indices.iter().map(|&n|{n as u32}).collect()
         };
 
-        let face_normals = {
-            let byte_count = match io::read_u32(&mut terrain_file) {
-                Ok(n) => { n as usize }
-                Err(e) => { panic!("Couldn't read byte count: {}", e); }
-            };
-
-            let mut bytes = vec![0; byte_count];
-            if let Err(e) = terrain_file.read_exact(bytes.as_mut_slice()) {
-                panic!("Error reading face normal data from file: {}", e);
-            }
-
-            let byte_step = 12;
-            let mut normals = Vec::with_capacity(byte_count / byte_step);            
-            for i in (0..bytes.len()).step_by(byte_step) {
-                let x_bytes = [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
-                let y_bytes = [bytes[i + 4], bytes[i + 5], bytes[i + 6], bytes[i + 7]];
-                let z_bytes = [bytes[i + 8], bytes[i + 9], bytes[i + 10], bytes[i + 11]];
-
-                let x = f32::from_le_bytes(x_bytes);
-                let y = f32::from_le_bytes(y_bytes);
-                let z = f32::from_le_bytes(z_bytes);
-
-                normals.push(glm::vec3(x, y, z));
-            }
-            normals
-        };
+        let face_normals = read_vec3_block(reader)?;
         
-        Self {
+        Ok(Self {
             vertices,
             indices,
             face_normals
-        }
+        })
     }
 }
 
@@ -401,4 +500,1723 @@ pub fn triangle_sphere_collision_point(sphere: &Sphere, triangle: &Triangle, tri
     } else {
         None
     }
-}
\ No newline at end of file
+}
+
+//Smallest sphere centered on the triangle's centroid that contains all three vertices
+//Suitable as the triangle_sphere argument of triangle_collide_sphere()
+pub fn triangle_bounding_sphere(triangle: &Triangle) -> Sphere {
+    let focus = (triangle.a + triangle.b + triangle.c) / 3.0;
+    let radius = f32::max(
+        glm::distance(&focus, &triangle.a),
+        f32::max(glm::distance(&focus, &triangle.b), glm::distance(&focus, &triangle.c))
+    );
+    Sphere { focus, radius }
+}
+
+//Returns the point on the triangle (including its interior) closest to an arbitrary point
+//From Real-Time Collision Detection 5.1.5
+pub fn closest_point_in_triangle(point: &glm::TVec3<f32>, triangle: &Triangle) -> glm::TVec3<f32> {
+    let (a, b, c) = (triangle.a, triangle.b, triangle.c);
+    let ab = b - a;
+    let ac = c - a;
+
+    let ap = point - a;
+    let d1 = glm::dot(&ab, &ap);
+    let d2 = glm::dot(&ac, &ap);
+    if d1 <= 0.0 && d2 <= 0.0 { return a; }
+
+    let bp = point - b;
+    let d3 = glm::dot(&ab, &bp);
+    let d4 = glm::dot(&ac, &bp);
+    if d3 >= 0.0 && d4 <= d3 { return b; }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + d1 / (d1 - d3) * ab;
+    }
+
+    let cp = point - c;
+    let d5 = glm::dot(&ab, &cp);
+    let d6 = glm::dot(&ac, &cp);
+    if d6 >= 0.0 && d5 <= d6 { return c; }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + d2 / (d2 - d6) * ac;
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (d4 - d3) / ((d4 - d3) + (d5 - d6)) * (c - b);
+    }
+
+    //Degenerate triangles end up here with a zero denominator
+    let denominator = va + vb + vc;
+    if denominator == 0.0 {
+        return closest_point_on_triangle(point, triangle).1;
+    }
+    a + (vb / denominator) * ab + (vc / denominator) * ac
+}
+
+//Returns the closest points between segments (p0, p1) and (q0, q1), in that order
+//From Real-Time Collision Detection 5.1.9
+pub fn closest_points_between_segments(p0: &glm::TVec3<f32>, p1: &glm::TVec3<f32>, q0: &glm::TVec3<f32>, q1: &glm::TVec3<f32>) -> (glm::TVec3<f32>, glm::TVec3<f32>) {
+    const EPSILON: f32 = 0.000001;
+    let d1 = p1 - p0;
+    let d2 = q1 - q0;
+    let r = p0 - q0;
+    let a = glm::dot(&d1, &d1);
+    let e = glm::dot(&d2, &d2);
+    let f = glm::dot(&d2, &r);
+
+    let (s, t) = if a <= EPSILON && e <= EPSILON {
+        (0.0, 0.0)
+    } else if a <= EPSILON {
+        (0.0, f32::clamp(f / e, 0.0, 1.0))
+    } else {
+        let c = glm::dot(&d1, &r);
+        if e <= EPSILON {
+            (f32::clamp(-c / a, 0.0, 1.0), 0.0)
+        } else {
+            let b = glm::dot(&d1, &d2);
+            let denominator = a * e - b * b;
+            let mut s = if denominator != 0.0 { f32::clamp((b * f - c * e) / denominator, 0.0, 1.0) } else { 0.0 };
+            let mut t = (b * s + f) / e;
+            if t < 0.0 {
+                t = 0.0;
+                s = f32::clamp(-c / a, 0.0, 1.0);
+            } else if t > 1.0 {
+                t = 1.0;
+                s = f32::clamp((b - c) / a, 0.0, 1.0);
+            }
+            (s, t)
+        }
+    };
+
+    (p0 + s * d1, q0 + t * d2)
+}
+
+//Returns the segment parameter in [0, 1] and the point where the segment crosses the triangle
+pub fn segment_hit_triangle(segment: &LineSegment, triangle: &Triangle) -> Option<(f32, glm::TVec3<f32>)> {
+    const EPSILON: f32 = 0.0000001;
+    let direction = segment.p1 - segment.p0;
+    let ab = triangle.b - triangle.a;
+    let ac = triangle.c - triangle.a;
+    let h = glm::cross(&direction, &ac);
+    let det = glm::dot(&ab, &h);
+    if f32::abs(det) < EPSILON { return None; }
+
+    let inv_det = 1.0 / det;
+    let s = segment.p0 - triangle.a;
+    let u = inv_det * glm::dot(&s, &h);
+    if !(0.0..=1.0).contains(&u) { return None; }
+
+    let q = glm::cross(&s, &ab);
+    let v = inv_det * glm::dot(&direction, &q);
+    if v < 0.0 || u + v > 1.0 { return None; }
+
+    let t = inv_det * glm::dot(&ac, &q);
+    if (0.0..=1.0).contains(&t) {
+        Some((t, segment.p0 + t * direction))
+    } else {
+        None
+    }
+}
+
+//Returns the distance between a segment and a triangle, then the closest point on the segment and on the triangle
+pub fn segment_triangle_closest_points(segment: &LineSegment, triangle: &Triangle) -> (f32, glm::TVec3<f32>, glm::TVec3<f32>) {
+    if let Some((_, point)) = segment_hit_triangle(segment, triangle) {
+        return (0.0, point, point);
+    }
+
+    let mut best = {
+        let on_triangle = closest_point_in_triangle(&segment.p0, triangle);
+        (glm::distance(&segment.p0, &on_triangle), segment.p0, on_triangle)
+    };
+    let mut update_best = |on_segment: glm::TVec3<f32>, on_triangle: glm::TVec3<f32>| {
+        let dist = glm::distance(&on_segment, &on_triangle);
+        if dist < best.0 {
+            best = (dist, on_segment, on_triangle);
+        }
+    };
+
+    update_best(segment.p1, closest_point_in_triangle(&segment.p1, triangle));
+    for (e0, e1) in [(triangle.a, triangle.b), (triangle.b, triangle.c), (triangle.c, triangle.a)].iter() {
+        let (on_segment, on_edge) = closest_points_between_segments(&segment.p0, &segment.p1, e0, e1);
+        update_best(on_segment, on_edge);
+    }
+    best
+}
+
+//Separating axis test between a triangle and the box spanning min to max
+//From Akenine-Möller's "Fast 3D Triangle-Box Overlap Testing"
+pub fn triangle_overlaps_box(triangle: &Triangle, min: &glm::TVec3<f32>, max: &glm::TVec3<f32>) -> bool {
+    let center = 0.5 * (min + max);
+    let half_size = 0.5 * (max - min);
+    let v = [triangle.a - center, triangle.b - center, triangle.c - center];
+
+    let separated_on = |axis: &glm::TVec3<f32>| {
+        let p0 = glm::dot(&v[0], axis);
+        let p1 = glm::dot(&v[1], axis);
+        let p2 = glm::dot(&v[2], axis);
+        let r = half_size.x * f32::abs(axis.x) + half_size.y * f32::abs(axis.y) + half_size.z * f32::abs(axis.z);
+        f32::min(p0, f32::min(p1, p2)) > r || f32::max(p0, f32::max(p1, p2)) < -r
+    };
+
+    //The box's face normals
+    for i in 0..3 {
+        let lo = f32::min(v[0][i], f32::min(v[1][i], v[2][i]));
+        let hi = f32::max(v[0][i], f32::max(v[1][i], v[2][i]));
+        if lo > half_size[i] || hi < -half_size[i] { return false; }
+    }
+
+    //The triangle's normal
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+    if separated_on(&glm::cross(&edges[0], &edges[1])) { return false; }
+
+    //Cross products of the box's axes with the triangle's edges
+    let box_axes = [glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0), glm::vec3(0.0, 0.0, 1.0)];
+    for edge in edges.iter() {
+        for axis in box_axes.iter() {
+            if separated_on(&glm::cross(axis, edge)) { return false; }
+        }
+    }
+    true
+}
+
+//A single triangle touched by a sphere or capsule query
+#[derive(Clone, Debug)]
+pub struct TerrainOverlap {
+    pub triangle_index: usize,
+    pub distance: f32,
+    pub point: glm::TVec3<f32>     //On the triangle
+}
+
+//Every triangle for which triangle_sphere_collision_point() reports a hit, in triangle order
+pub fn sphere_hit_terrain(terrain: &MeshCollision, sphere: &Sphere) -> Vec<TerrainOverlap> {
+    let mut overlaps = Vec::new();
+    for i in 0..terrain.indices.len() / 3 {
+        if let Some(overlap) = sphere_overlaps_terrain_triangle(terrain, sphere, i) {
+            overlaps.push(overlap);
+        }
+    }
+    overlaps
+}
+
+//Every triangle closer than the capsule's radius to its segment, in triangle order
+pub fn capsule_hit_terrain(terrain: &MeshCollision, capsule: &Capsule) -> Vec<TerrainOverlap> {
+    let mut overlaps = Vec::new();
+    for i in 0..terrain.indices.len() / 3 {
+        if let Some(overlap) = capsule_overlaps_terrain_triangle(terrain, capsule, i) {
+            overlaps.push(overlap);
+        }
+    }
+    overlaps
+}
+
+//Indices of every triangle that overlaps the box spanning min to max, in triangle order
+pub fn box_hit_terrain(terrain: &MeshCollision, min: &glm::TVec3<f32>, max: &glm::TVec3<f32>) -> Vec<usize> {
+    (0..terrain.indices.len() / 3).filter(|&i| {
+        triangle_overlaps_box(&get_terrain_triangle(terrain, i * 3), min, max)
+    }).collect()
+}
+
+//Indices of every triangle that overlaps the AABB, in triangle order
+pub fn aabb_hit_terrain(terrain: &MeshCollision, aabb: &AABB) -> Vec<usize> {
+    box_hit_terrain(terrain, &aabb.min(), &aabb.max())
+}
+
+fn sphere_overlaps_terrain_triangle(terrain: &MeshCollision, sphere: &Sphere, triangle_id: usize) -> Option<TerrainOverlap> {
+    let triangle = get_terrain_triangle(terrain, triangle_id * 3);
+    let triangle_sphere = triangle_bounding_sphere(&triangle);
+    triangle_sphere_collision_point(sphere, &triangle, &triangle_sphere).map(|(distance, point)| {
+        TerrainOverlap { triangle_index: triangle_id, distance, point }
+    })
+}
+
+fn capsule_overlaps_terrain_triangle(terrain: &MeshCollision, capsule: &Capsule, triangle_id: usize) -> Option<TerrainOverlap> {
+    let triangle = get_terrain_triangle(terrain, triangle_id * 3);
+    let (distance, _, point) = segment_triangle_closest_points(&capsule.segment, &triangle);
+    if distance < capsule.radius {
+        Some(TerrainOverlap { triangle_index: triangle_id, distance, point })
+    } else {
+        None
+    }
+}
+
+//Tests a single triangle the same way ray_hit_terrain() does
+fn ray_hit_terrain_triangle(terrain: &MeshCollision, ray: &Ray, triangle_id: usize) -> Option<(f32, glm::TVec3<f32>)> {
+    let triangle = get_terrain_triangle(terrain, triangle_id * 3);
+    let plane = Plane::new(triangle.a, terrain.face_normals[triangle_id]);
+    match ray_hit_plane(ray, &plane) {
+        Some((t, intersection)) if t >= 0.0 && robust_point_in_triangle(&intersection, &triangle) => { Some((t, intersection)) }
+        _ => { None }
+    }
+}
+
+//A node of MeshBvh. Leaves have a non-zero count and reference triangles[first..first + count]
+//Interior nodes have their left child immediately after them and their right child at index first
+#[derive(Clone, Debug)]
+pub struct BvhNode {
+    pub min: glm::TVec3<f32>,
+    pub max: glm::TVec3<f32>,
+    pub first: u32,
+    pub count: u32
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+
+    pub fn aabb(&self) -> AABB {
+        AABB::from_min_max(&self.min, &self.max)
+    }
+
+    fn surface_area(&self) -> f32 {
+        box_surface_area(&self.min, &self.max)
+    }
+}
+
+//Bounding volume hierarchy over the triangles of a MeshCollision, built with the surface area heuristic and stored depth-first in one Vec
+//Queries take the MeshCollision the BVH was built from and return the same results as the brute-force *_hit_terrain() functions
+#[derive(Clone, Debug)]
+pub struct MeshBvh {
+    pub nodes: Vec<BvhNode>,
+    pub triangles: Vec<u32>    //Triangle ids, reordered so that every leaf covers a contiguous range
+}
+
+fn box_surface_area(min: &glm::TVec3<f32>, max: &glm::TVec3<f32>) -> f32 {
+    let d = max - min;
+    if d.x < 0.0 { return 0.0; }
+    2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+}
+
+fn boxes_overlap(min0: &glm::TVec3<f32>, max0: &glm::TVec3<f32>, min1: &glm::TVec3<f32>, max1: &glm::TVec3<f32>) -> bool {
+    min0.x <= max1.x && max0.x >= min1.x &&
+    min0.y <= max1.y && max0.y >= min1.y &&
+    min0.z <= max1.z && max0.z >= min1.z
+}
+
+//Entry t of a ray into a box, if it enters before t_max
+fn ray_hit_box(origin: &glm::TVec3<f32>, inverse_direction: &glm::TVec3<f32>, min: &glm::TVec3<f32>, max: &glm::TVec3<f32>, t_max: f32) -> Option<f32> {
+    let mut t_enter = 0.0f32;
+    let mut t_exit = t_max;
+    for i in 0..3 {
+        //A ray parallel to and touching a slab produces NaN here, which f32::min/max ignore
+        let t0 = (min[i] - origin[i]) * inverse_direction[i];
+        let t1 = (max[i] - origin[i]) * inverse_direction[i];
+        t_enter = f32::max(t_enter, f32::min(t0, t1));
+        t_exit = f32::min(t_exit, f32::max(t0, t1));
+    }
+    if t_enter <= t_exit { Some(t_enter) } else { None }
+}
+
+impl MeshBvh {
+    const MAX_LEAF_SIZE: usize = 4;
+    const BIN_COUNT: usize = 16;
+    const TRAVERSAL_COST: f32 = 1.0;
+
+    pub fn new(terrain: &MeshCollision) -> Self {
+        let triangle_count = terrain.indices.len() / 3;
+        let mut bounds = Vec::with_capacity(triangle_count);
+        let mut centroids = Vec::with_capacity(triangle_count);
+        for i in 0..triangle_count {
+            let triangle = get_terrain_triangle(terrain, i * 3);
+            let min = glm::min2(&triangle.a, &glm::min2(&triangle.b, &triangle.c));
+            let max = glm::max2(&triangle.a, &glm::max2(&triangle.b, &triangle.c));
+
+            //Pad so that hits the brute-force tests accept right on a triangle's edge are never culled
+            let padding = glm::vec3(1.0, 1.0, 1.0) * (0.0001 * glm::length(&(max - min)) + 0.00001);
+            bounds.push((min - padding, max + padding));
+            centroids.push((triangle.a + triangle.b + triangle.c) / 3.0);
+        }
+
+        let mut bvh = MeshBvh {
+            nodes: Vec::with_capacity(usize::max(2 * triangle_count, 1)),
+            triangles: (0..triangle_count as u32).collect()
+        };
+        if triangle_count > 0 {
+            bvh.build_node(&bounds, &centroids, 0, triangle_count);
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, bounds: &[(glm::TVec3<f32>, glm::TVec3<f32>)], centroids: &[glm::TVec3<f32>], start: usize, end: usize) -> usize {
+        let mut node = BvhNode {
+            min: glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: glm::vec3(-f32::INFINITY, -f32::INFINITY, -f32::INFINITY),
+            first: start as u32,
+            count: (end - start) as u32
+        };
+        let mut centroid_min = node.min;
+        let mut centroid_max = node.max;
+        for &t in &self.triangles[start..end] {
+            let (min, max) = &bounds[t as usize];
+            node.min = glm::min2(&node.min, min);
+            node.max = glm::max2(&node.max, max);
+            centroid_min = glm::min2(&centroid_min, &centroids[t as usize]);
+            centroid_max = glm::max2(&centroid_max, &centroids[t as usize]);
+        }
+
+        let node_index = self.nodes.len();
+        let count = end - start;
+        if count <= Self::MAX_LEAF_SIZE {
+            self.nodes.push(node);
+            return node_index;
+        }
+
+        let split = match self.find_sah_split(bounds, centroids, start, end, &centroid_min, &centroid_max, node.surface_area()) {
+            Some((axis, position)) => {
+                let mut middle = start;
+                for i in start..end {
+                    if centroids[self.triangles[i] as usize][axis] < position {
+                        self.triangles.swap(i, middle);
+                        middle += 1;
+                    }
+                }
+                if middle == start || middle == end { None } else { Some(middle) }
+            }
+            None => { None }
+        };
+
+        //When the SAH says a leaf is cheaper we still cap the leaf size, falling back to a median split on the widest axis
+        let middle = match split {
+            Some(middle) => { middle }
+            None if count <= 4 * Self::MAX_LEAF_SIZE => {
+                self.nodes.push(node);
+                return node_index;
+            }
+            None => {
+                let extent = centroid_max - centroid_min;
+                let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+                let middle = start + count / 2;
+                self.triangles[start..end].select_nth_unstable_by(count / 2, |a, b| {
+                    centroids[*a as usize][axis].partial_cmp(&centroids[*b as usize][axis]).unwrap_or(std::cmp::Ordering::Equal)
+                });
+                middle
+            }
+        };
+
+        node.count = 0;
+        self.nodes.push(node);
+        self.build_node(bounds, centroids, start, middle);
+        let right = self.build_node(bounds, centroids, middle, end);
+        self.nodes[node_index].first = right as u32;
+        node_index
+    }
+
+    //Binned SAH: returns the axis and centroid position of the cheapest split, if that beats making a leaf
+    #[allow(clippy::too_many_arguments)]
+    fn find_sah_split(&self, bounds: &[(glm::TVec3<f32>, glm::TVec3<f32>)], centroids: &[glm::TVec3<f32>], start: usize, end: usize,
+                      centroid_min: &glm::TVec3<f32>, centroid_max: &glm::TVec3<f32>, parent_area: f32) -> Option<(usize, f32)> {
+        let empty_min = glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let empty_max = -empty_min;
+        let mut best_cost = (end - start) as f32;
+        let mut best_split = None;
+        if parent_area <= 0.0 { return None; }
+
+        for axis in 0..3 {
+            let extent = centroid_max[axis] - centroid_min[axis];
+            if extent <= 0.0 { continue; }
+
+            let mut bin_min = [empty_min; Self::BIN_COUNT];
+            let mut bin_max = [empty_max; Self::BIN_COUNT];
+            let mut bin_count = [0usize; Self::BIN_COUNT];
+            let scale = Self::BIN_COUNT as f32 / extent;
+            for &t in &self.triangles[start..end] {
+                let bin = usize::min(((centroids[t as usize][axis] - centroid_min[axis]) * scale) as usize, Self::BIN_COUNT - 1);
+                let (min, max) = &bounds[t as usize];
+                bin_min[bin] = glm::min2(&bin_min[bin], min);
+                bin_max[bin] = glm::max2(&bin_max[bin], max);
+                bin_count[bin] += 1;
+            }
+
+            //Sweep from the right to get the cost of everything past each plane
+            let mut right_area = [0.0; Self::BIN_COUNT];
+            let mut right_count = [0usize; Self::BIN_COUNT];
+            let (mut min, mut max, mut count) = (empty_min, empty_max, 0);
+            for bin in (1..Self::BIN_COUNT).rev() {
+                min = glm::min2(&min, &bin_min[bin]);
+                max = glm::max2(&max, &bin_max[bin]);
+                count += bin_count[bin];
+                right_area[bin] = box_surface_area(&min, &max);
+                right_count[bin] = count;
+            }
+
+            let (mut min, mut max, mut count) = (empty_min, empty_max, 0);
+            for bin in 1..Self::BIN_COUNT {
+                min = glm::min2(&min, &bin_min[bin - 1]);
+                max = glm::max2(&max, &bin_max[bin - 1]);
+                count += bin_count[bin - 1];
+                if count == 0 || right_count[bin] == 0 { continue; }
+
+                let cost = Self::TRAVERSAL_COST + (box_surface_area(&min, &max) * count as f32 + right_area[bin] * right_count[bin] as f32) / parent_area;
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_split = Some((axis, centroid_min[axis] + bin as f32 / scale));
+                }
+            }
+        }
+        best_split
+    }
+
+    //Depth-first walk calling visit_triangle on the triangles of every leaf whose box passes node_test
+    fn traverse<N: Fn(&BvhNode) -> bool, L: FnMut(u32)>(&self, node_test: N, mut visit_triangle: L) {
+        if self.nodes.is_empty() { return; }
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node_test(node) { continue; }
+            if node.is_leaf() {
+                for &t in &self.triangles[node.first as usize..(node.first + node.count) as usize] {
+                    visit_triangle(t);
+                }
+            } else {
+                stack.push(node.first as usize);
+                stack.push(index + 1);
+            }
+        }
+    }
+
+    //Same result as ray_hit_terrain(), including which triangle wins a tie
+    pub fn ray_cast(&self, terrain: &MeshCollision, ray: &Ray) -> Option<RayTerrainCollision> {
+        if self.nodes.is_empty() { return None; }
+        let inverse_direction = glm::vec3(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut closest: Option<RayTerrainCollision> = None;
+
+        //Visits the nearer child first so that farther subtrees get culled by the best hit so far
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let t_max = closest.as_ref().map_or(f32::INFINITY, |c| c.smallest_t);
+            if ray_hit_box(&ray.origin, &inverse_direction, &node.min, &node.max, t_max).is_none() { continue; }
+
+            if node.is_leaf() {
+                for &t in &self.triangles[node.first as usize..(node.first + node.count) as usize] {
+                    let triangle_id = t as usize;
+                    if let Some((t, point)) = ray_hit_terrain_triangle(terrain, ray, triangle_id) {
+                        let better = match &closest {
+                            Some(c) => { t < c.smallest_t || (t == c.smallest_t && triangle_id < c.triangle_index) }
+                            None => { true }
+                        };
+                        if better {
+                            closest = Some(RayTerrainCollision { smallest_t: t, triangle_index: triangle_id, point });
+                        }
+                    }
+                }
+            } else {
+                let (left, right) = (index + 1, node.first as usize);
+                let t_left = ray_hit_box(&ray.origin, &inverse_direction, &self.nodes[left].min, &self.nodes[left].max, t_max);
+                let t_right = ray_hit_box(&ray.origin, &inverse_direction, &self.nodes[right].min, &self.nodes[right].max, t_max);
+                match (t_left, t_right) {
+                    (Some(l), Some(r)) => {
+                        if l <= r {
+                            stack.push(right);
+                            stack.push(left);
+                        } else {
+                            stack.push(left);
+                            stack.push(right);
+                        }
+                    }
+                    (Some(_), None) => { stack.push(left); }
+                    (None, Some(_)) => { stack.push(right); }
+                    (None, None) => {}
+                }
+            }
+        }
+        closest
+    }
+
+    //Same result as sphere_hit_terrain()
+    pub fn sphere_overlap(&self, terrain: &MeshCollision, sphere: &Sphere) -> Vec<TerrainOverlap> {
+        let mut overlaps = Vec::new();
+        self.traverse(|node| {
+            let closest = glm::clamp_vec(&sphere.focus, &node.min, &node.max);
+            glm::distance2(&closest, &sphere.focus) <= sphere.radius * sphere.radius
+        }, |t| {
+            if let Some(overlap) = sphere_overlaps_terrain_triangle(terrain, sphere, t as usize) {
+                overlaps.push(overlap);
+            }
+        });
+        overlaps.sort_by_key(|o| o.triangle_index);
+        overlaps
+    }
+
+    //Same result as capsule_hit_terrain()
+    pub fn capsule_overlap(&self, terrain: &MeshCollision, capsule: &Capsule) -> Vec<TerrainOverlap> {
+        let radius = glm::vec3(capsule.radius, capsule.radius, capsule.radius);
+        let min = glm::min2(&capsule.segment.p0, &capsule.segment.p1) - radius;
+        let max = glm::max2(&capsule.segment.p0, &capsule.segment.p1) + radius;
+        let mut overlaps = Vec::new();
+        self.traverse(|node| { boxes_overlap(&node.min, &node.max, &min, &max) }, |t| {
+            if let Some(overlap) = capsule_overlaps_terrain_triangle(terrain, capsule, t as usize) {
+                overlaps.push(overlap);
+            }
+        });
+        overlaps.sort_by_key(|o| o.triangle_index);
+        overlaps
+    }
+
+    //Same result as box_hit_terrain()
+    pub fn box_overlap(&self, terrain: &MeshCollision, min: &glm::TVec3<f32>, max: &glm::TVec3<f32>) -> Vec<usize> {
+        let mut hits = Vec::new();
+        self.traverse(|node| { boxes_overlap(&node.min, &node.max, min, max) }, |t| {
+            if triangle_overlaps_box(&get_terrain_triangle(terrain, t as usize * 3), min, max) {
+                hits.push(t as usize);
+            }
+        });
+        hits.sort_unstable();
+        hits
+    }
+
+    //Same result as aabb_hit_terrain()
+    pub fn aabb_overlap(&self, terrain: &MeshCollision, aabb: &AABB) -> Vec<usize> {
+        self.box_overlap(terrain, &aabb.min(), &aabb.max())
+    }
+
+    //Slightly padded bounds of the whole mesh, or None if it has no triangles
+    pub fn bounds(&self) -> Option<AABB> {
+        self.nodes.first().map(|root| { root.aabb() })
+    }
+}
+
+//Uniform 2D grid over the XY footprint of a MeshCollision's triangles, for finding candidates for triangle_collide_sphere()
+//Every triangle is listed in each cell its XY bounds touch. Triangle indices are triangle ids, so pass 3 * id to get_terrain_triangle()
+//The grid has at most MAX_CELLS cells, so cell_size can end up larger than the one asked for
+#[derive(Clone, Debug)]
+pub struct TerrainGrid {
+    pub origin: glm::TVec2<f32>,         //XY of the grid's minimum corner
+    pub cell_size: f32,
+    pub columns: usize,
+    pub rows: usize,
+    pub triangle_spheres: Vec<Sphere>,   //Indexed by triangle id
+    cell_starts: Vec<u32>,               //cell_triangles[cell_starts[c]..cell_starts[c + 1]] are the triangles in cell c
+    cell_triangles: Vec<u32>
+}
+
+impl TerrainGrid {
+    pub const MAX_CELLS: usize = 1 << 22;
+
+    pub fn new(terrain: &MeshCollision, cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "TerrainGrid cell size must be positive");
+        let triangle_count = terrain.indices.len() / 3;
+        let triangles: Vec<Triangle> = (0..triangle_count).map(|i| { get_terrain_triangle(terrain, i * 3) }).collect();
+        let triangle_spheres = triangles.iter().map(triangle_bounding_sphere).collect();
+
+        let mut min = glm::vec2(f32::INFINITY, f32::INFINITY);
+        let mut max = glm::vec2(-f32::INFINITY, -f32::INFINITY);
+        for triangle in &triangles {
+            for v in [triangle.a, triangle.b, triangle.c].iter() {
+                min = glm::min2(&min, &v.xy());
+                max = glm::max2(&max, &v.xy());
+            }
+        }
+        if triangle_count == 0 {
+            min = glm::zero();
+            max = glm::zero();
+        }
+
+        //Doubling the cell size until the grid fits also ends for huge or non-finite extents, since the size eventually overflows to infinity
+        let dimension = |extent: f32, cell_size: f32| { ((extent / cell_size).floor() as usize).saturating_add(1) };
+        let mut cell_size = cell_size;
+        while dimension(max.x - min.x, cell_size).saturating_mul(dimension(max.y - min.y, cell_size)) > Self::MAX_CELLS {
+            cell_size *= 2.0;
+        }
+
+        let mut grid = TerrainGrid {
+            origin: min,
+            cell_size,
+            columns: dimension(max.x - min.x, cell_size),
+            rows: dimension(max.y - min.y, cell_size),
+            triangle_spheres,
+            cell_starts: Vec::new(),
+            cell_triangles: Vec::new()
+        };
+
+        //Count triangles per cell, turn the counts into offsets, then fill
+        let cell_ranges: Vec<_> = triangles.iter().map(|t| {
+            let min = glm::min2(&t.a.xy(), &glm::min2(&t.b.xy(), &t.c.xy()));
+            let max = glm::max2(&t.a.xy(), &glm::max2(&t.b.xy(), &t.c.xy()));
+            grid.cell_range(&min, &max)
+        }).collect();
+        let mut counts = vec![0u32; grid.columns * grid.rows + 1];
+        for range in cell_ranges.iter().flatten() {
+            for row in range.1..=range.3 {
+                for column in range.0..=range.2 {
+                    counts[row * grid.columns + column + 1] += 1;
+                }
+            }
+        }
+        for i in 1..counts.len() {
+            counts[i] += counts[i - 1];
+        }
+
+        let mut cursors = counts.clone();
+        grid.cell_triangles = vec![0; counts[counts.len() - 1] as usize];
+        for (id, range) in cell_ranges.iter().enumerate() {
+            if let Some((x0, y0, x1, y1)) = range {
+                for row in *y0..=*y1 {
+                    for column in *x0..=*x1 {
+                        let cell = row * grid.columns + column;
+                        grid.cell_triangles[cursors[cell] as usize] = id as u32;
+                        cursors[cell] += 1;
+                    }
+                }
+            }
+        }
+        grid.cell_starts = counts;
+        grid
+    }
+
+    //Inclusive (min column, min row, max column, max row) of the cells touched by an XY rectangle, or None if it misses the grid
+    fn cell_range(&self, min: &glm::TVec2<f32>, max: &glm::TVec2<f32>) -> Option<(usize, usize, usize, usize)> {
+        let lo = (min - self.origin) / self.cell_size;
+        let hi = (max - self.origin) / self.cell_size;
+        if hi.x < 0.0 || hi.y < 0.0 || lo.x >= self.columns as f32 || lo.y >= self.rows as f32 { return None; }
+
+        let clamp = |v: f32, count: usize| { usize::min(f32::max(v, 0.0) as usize, count - 1) };
+        Some((clamp(lo.x, self.columns), clamp(lo.y, self.rows), clamp(hi.x, self.columns), clamp(hi.y, self.rows)))
+    }
+
+    pub fn cell_triangles(&self, column: usize, row: usize) -> &[u32] {
+        let cell = row * self.columns + column;
+        &self.cell_triangles[self.cell_starts[cell] as usize..self.cell_starts[cell + 1] as usize]
+    }
+
+    //Sorts and dedupes the triangles of the given cells
+    fn collect_cells<I: Iterator<Item = (usize, usize)>>(&self, cells: I) -> Vec<usize> {
+        let mut candidates = Vec::new();
+        for (column, row) in cells {
+            candidates.extend(self.cell_triangles(column, row).iter().map(|&t| { t as usize }));
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+
+    //Ids of triangles whose cells overlap an XY rectangle
+    pub fn rectangle_candidates(&self, min: &glm::TVec2<f32>, max: &glm::TVec2<f32>) -> Vec<usize> {
+        match self.cell_range(min, max) {
+            Some((x0, y0, x1, y1)) => { self.collect_cells((y0..=y1).flat_map(|row| { (x0..=x1).map(move |column| { (column, row) }) })) }
+            None => { Vec::new() }
+        }
+    }
+
+    //Ids of triangles whose cells overlap the sphere's XY footprint
+    pub fn sphere_candidates(&self, sphere: &Sphere) -> Vec<usize> {
+        let radius = glm::vec2(sphere.radius, sphere.radius);
+        self.rectangle_candidates(&(sphere.focus.xy() - radius), &(sphere.focus.xy() + radius))
+    }
+
+    //Ids of triangles whose cells overlap the AABB's XY footprint
+    pub fn aabb_candidates(&self, aabb: &AABB) -> Vec<usize> {
+        self.rectangle_candidates(&aabb.min().xy(), &aabb.max().xy())
+    }
+
+    //Ids of triangles in the cells crossed by the segment's XY projection
+    pub fn segment_candidates(&self, segment: &LineSegment) -> Vec<usize> {
+        let mut start = (segment.p0.xy() - self.origin) / self.cell_size;
+        let mut end = (segment.p1.xy() - self.origin) / self.cell_size;
+
+        //Clip to the grid so the walk below starts and ends inside it
+        let delta = end - start;
+        let (mut t0, mut t1) = (0.0f32, 1.0f32);
+        for axis in 0..2 {
+            let limit = if axis == 0 { self.columns as f32 } else { self.rows as f32 };
+            if delta[axis] == 0.0 {
+                if start[axis] < 0.0 || start[axis] > limit { return Vec::new(); }
+            } else {
+                let a = -start[axis] / delta[axis];
+                let b = (limit - start[axis]) / delta[axis];
+                t0 = f32::max(t0, f32::min(a, b));
+                t1 = f32::min(t1, f32::max(a, b));
+            }
+        }
+        if t0 > t1 { return Vec::new(); }
+        let clipped_start = start + t0 * delta;
+        end = start + t1 * delta;
+        start = clipped_start;
+
+        //Amanatides-Woo traversal
+        let cell_of = |p: &glm::TVec2<f32>| {
+            (usize::min(f32::max(p.x, 0.0) as usize, self.columns - 1), usize::min(f32::max(p.y, 0.0) as usize, self.rows - 1))
+        };
+        let (mut column, mut row) = cell_of(&start);
+        let (last_column, last_row) = cell_of(&end);
+        let step_x: isize = if delta.x > 0.0 { 1 } else { -1 };
+        let step_y: isize = if delta.y > 0.0 { 1 } else { -1 };
+        let next_boundary = |p: f32, cell: usize, step: isize| { if step > 0 { (cell + 1) as f32 - p } else { p - cell as f32 } };
+        let mut t_max_x = if delta.x != 0.0 { next_boundary(start.x, column, step_x) / f32::abs(delta.x) } else { f32::INFINITY };
+        let mut t_max_y = if delta.y != 0.0 { next_boundary(start.y, row, step_y) / f32::abs(delta.y) } else { f32::INFINITY };
+        let t_delta_x = if delta.x != 0.0 { 1.0 / f32::abs(delta.x) } else { f32::INFINITY };
+        let t_delta_y = if delta.y != 0.0 { 1.0 / f32::abs(delta.y) } else { f32::INFINITY };
+
+        let mut cells = vec![(column, row)];
+        while (column, row) != (last_column, last_row) {
+            if t_max_x < t_max_y {
+                t_max_x += t_delta_x;
+                column = (column as isize + step_x) as usize;
+            } else {
+                t_max_y += t_delta_y;
+                row = (row as isize + step_y) as usize;
+            }
+            if column >= self.columns || row >= self.rows { break; }
+            cells.push((column, row));
+        }
+        self.collect_cells(cells.into_iter())
+    }
+}
+
+//Turns the closest points between two shapes into a (penetration depth, push-out vector) pair for the first shape
+//fallback is used as the push direction when the closest points coincide
+fn separation_push(on_self: &glm::TVec3<f32>, on_other: &glm::TVec3<f32>, radius_sum: f32, fallback: &glm::TVec3<f32>) -> Option<(f32, glm::TVec3<f32>)> {
+    const EPSILON: f32 = 0.00001;
+    let v = on_self - on_other;
+    let dist = glm::length(&v);
+    if dist >= radius_sum { return None; }
+
+    let direction = if dist < EPSILON { *fallback } else { v / dist };
+    let depth = radius_sum - dist;
+    Some((depth, direction * depth))
+}
+
+//Any unit vector perpendicular to the segment, preferring the horizontal
+fn segment_perpendicular(segment: &LineSegment) -> glm::TVec3<f32> {
+    let axis = segment.p1 - segment.p0;
+    let up = glm::vec3(0.0, 0.0, 1.0);
+    let perpendicular = glm::cross(&axis, &up);
+    if glm::length(&perpendicular) > 0.00001 {
+        glm::normalize(&perpendicular)
+    } else if glm::length(&axis) > 0.00001 {
+        glm::normalize(&glm::cross(&axis, &glm::vec3(1.0, 0.0, 0.0)))
+    } else {
+        up
+    }
+}
+
+//Returns the penetration depth and the vector to add to the capsule's position to resolve the collision
+pub fn capsule_collide_triangle(capsule: &Capsule, triangle: &Triangle) -> Option<(f32, glm::TVec3<f32>)> {
+    let (dist, on_segment, on_triangle) = segment_triangle_closest_points(&capsule.segment, triangle);
+    if dist >= capsule.radius { return None; }
+    if dist > 0.0 {
+        return separation_push(&on_segment, &on_triangle, capsule.radius, &triangle.normal);
+    }
+
+    //The segment passes through the triangle, so push it out along the normal to whichever side its midpoint is on
+    let plane = Plane::new(triangle.a, triangle.normal);
+    let side = if point_plane_distance(&midpoint(&capsule.segment.p0, &capsule.segment.p1), &plane) < 0.0 { -1.0 } else { 1.0 };
+    let d0 = side * point_plane_distance(&capsule.segment.p0, &plane);
+    let d1 = side * point_plane_distance(&capsule.segment.p1, &plane);
+    let depth = capsule.radius - f32::min(d0, d1);
+    Some((depth, side * triangle.normal * depth))
+}
+
+pub fn capsule_collide_sphere(capsule: &Capsule, sphere: &Sphere) -> Option<(f32, glm::TVec3<f32>)> {
+    let on_segment = closest_point_on_line_segment(&sphere.focus, &capsule.segment.p0, &capsule.segment.p1);
+    separation_push(&on_segment, &sphere.focus, capsule.radius + sphere.radius, &segment_perpendicular(&capsule.segment))
+}
+
+//The push-out vector moves c1 away from c2
+pub fn capsule_collide_capsule(c1: &Capsule, c2: &Capsule) -> Option<(f32, glm::TVec3<f32>)> {
+    let (on_c1, on_c2) = closest_points_between_segments(&c1.segment.p0, &c1.segment.p1, &c2.segment.p0, &c2.segment.p1);
+    separation_push(&on_c1, &on_c2, c1.radius + c2.radius, &segment_perpendicular(&c2.segment))
+}
+
+//The plane is treated as solid behind its normal
+pub fn capsule_collide_plane(capsule: &Capsule, plane: &Plane) -> Option<(f32, glm::TVec3<f32>)> {
+    let lowest = f32::min(point_plane_distance(&capsule.segment.p0, plane), point_plane_distance(&capsule.segment.p1, plane));
+    if lowest < capsule.radius {
+        let depth = capsule.radius - lowest;
+        Some((depth, plane.normal * depth))
+    } else {
+        None
+    }
+}
+
+//Resolves the capsule against each candidate triangle in turn, moving it after every contact
+//Returns the total vector to add to the capsule's position, or None if nothing was touched
+fn capsule_collide_triangles<I: Iterator<Item = usize>>(terrain: &MeshCollision, capsule: &Capsule, candidates: I) -> Option<glm::TVec3<f32>> {
+    let mut total: Option<glm::TVec3<f32>> = None;
+    let mut moved = capsule.clone();
+    for triangle_id in candidates {
+        let triangle = get_terrain_triangle(terrain, triangle_id * 3);
+        if let Some((_, push)) = capsule_collide_triangle(&moved, &triangle) {
+            moved = moved.translated(&push);
+            total = Some(total.unwrap_or_else(glm::zero) + push);
+        }
+    }
+    total
+}
+
+pub fn capsule_collide_terrain(terrain: &MeshCollision, capsule: &Capsule) -> Option<glm::TVec3<f32>> {
+    let candidates = capsule_hit_terrain(terrain, capsule);
+    capsule_collide_triangles(terrain, capsule, candidates.iter().map(|c| { c.triangle_index }))
+}
+
+impl MeshBvh {
+    //Same as capsule_collide_terrain(), using the BVH to find the touched triangles
+    pub fn capsule_collide(&self, terrain: &MeshCollision, capsule: &Capsule) -> Option<glm::TVec3<f32>> {
+        let candidates = self.capsule_overlap(terrain, capsule);
+        capsule_collide_triangles(terrain, capsule, candidates.iter().map(|c| { c.triangle_index }))
+    }
+}
+
+//First contact of a moving sphere, with t in [0, 1] along the movement
+#[derive(Clone, Debug)]
+pub struct SweptSphereHit {
+    pub t: f32,
+    pub point: glm::TVec3<f32>,     //On the obstacle
+    pub normal: glm::TVec3<f32>     //Points from the contact point toward the sphere's center at time t
+}
+
+//Smallest root of a*t^2 + b*t + c = 0 in [0, max_t]
+fn lowest_root(a: f32, b: f32, c: f32, max_t: f32) -> Option<f32> {
+    if a == 0.0 { return None; }
+    let determinant = b * b - 4.0 * a * c;
+    if determinant < 0.0 { return None; }
+
+    let sqrt = f32::sqrt(determinant);
+    let (mut r1, mut r2) = ((-b - sqrt) / (2.0 * a), (-b + sqrt) / (2.0 * a));
+    if r1 > r2 { std::mem::swap(&mut r1, &mut r2); }
+    if r1 >= 0.0 && r1 <= max_t { return Some(r1); }
+    if r2 >= 0.0 && r2 <= max_t { return Some(r2); }
+    None
+}
+
+//Time of impact between a sphere moving by velocity over t in [0, 1] and a triangle
+//Triangles are double-sided. A sphere that already overlaps the triangle hits at t = 0 only if it's moving further in,
+//so that collide_and_slide() can slide off of resting contacts
+pub fn swept_sphere_hit_triangle(sphere: &Sphere, velocity: &glm::TVec3<f32>, triangle: &Triangle) -> Option<SweptSphereHit> {
+    const EPSILON: f32 = 0.00001;
+    let center = sphere.focus;
+    let radius = sphere.radius;
+
+    //Already touching
+    let closest = closest_point_in_triangle(&center, triangle);
+    let offset = center - closest;
+    let distance = glm::length(&offset);
+    if distance < radius {
+        let normal = if distance > EPSILON { offset / distance } else {
+            let n = triangle.normal;
+            if glm::dot(&n, velocity) > 0.0 { -n } else { n }
+        };
+        return if glm::dot(velocity, &normal) < 0.0 {
+            Some(SweptSphereHit { t: 0.0, point: closest, normal })
+        } else {
+            None
+        };
+    }
+
+    //Against the face. If the sphere touches the plane inside the triangle that's the earliest possible contact
+    let face_normal = glm::cross(&(triangle.b - triangle.a), &(triangle.c - triangle.a));
+    if glm::length(&face_normal) > EPSILON {
+        let mut normal = glm::normalize(&face_normal);
+        let mut plane_distance = glm::dot(&normal, &(center - triangle.a));
+        if plane_distance < 0.0 {
+            normal = -normal;
+            plane_distance = -plane_distance;
+        }
+
+        let approach = -glm::dot(&normal, velocity);
+        if approach > 0.0 {
+            let t = (plane_distance - radius) / approach;
+            if (0.0..=1.0).contains(&t) {
+                let point = center + t * velocity - radius * normal;
+                if glm::distance2(&closest_point_in_triangle(&point, triangle), &point) < EPSILON * EPSILON {
+                    return Some(SweptSphereHit { t, point, normal });
+                }
+            }
+        }
+    }
+
+    //Otherwise the first contact is with a vertex or an edge
+    let speed2 = glm::dot(velocity, velocity);
+    let mut best: Option<(f32, glm::TVec3<f32>)> = None;
+    let mut consider = |t: f32, point: glm::TVec3<f32>| {
+        let better = match best {
+            Some((best_t, _)) => { t < best_t }
+            None => { true }
+        };
+        if better {
+            best = Some((t, point));
+        }
+    };
+
+    for vertex in [triangle.a, triangle.b, triangle.c].iter() {
+        let base = center - vertex;
+        if let Some(t) = lowest_root(speed2, 2.0 * glm::dot(velocity, &base), glm::dot(&base, &base) - radius * radius, 1.0) {
+            consider(t, *vertex);
+        }
+    }
+
+    for (p0, p1) in [(triangle.a, triangle.b), (triangle.b, triangle.c), (triangle.c, triangle.a)].iter() {
+        let edge = p1 - p0;
+        let base = p0 - center;
+        let edge2 = glm::dot(&edge, &edge);
+        if edge2 <= EPSILON * EPSILON { continue; }
+
+        let edge_dot_velocity = glm::dot(&edge, velocity);
+        let edge_dot_base = glm::dot(&edge, &base);
+        let a = edge2 * -speed2 + edge_dot_velocity * edge_dot_velocity;
+        let b = edge2 * 2.0 * glm::dot(velocity, &base) - 2.0 * edge_dot_velocity * edge_dot_base;
+        let c = edge2 * (radius * radius - glm::dot(&base, &base)) + edge_dot_base * edge_dot_base;
+        if let Some(t) = lowest_root(a, b, c, 1.0) {
+            //Only counts if the contact is within the edge rather than on its infinite line
+            let f = (edge_dot_velocity * t - edge_dot_base) / edge2;
+            if (0.0..=1.0).contains(&f) {
+                consider(t, p0 + f * edge);
+            }
+        }
+    }
+
+    best.map(|(t, point)| {
+        let normal = glm::normalize(&(center + t * velocity - point));
+        SweptSphereHit { t, point, normal }
+    })
+}
+
+//Keeps the earliest hit, preferring the lower triangle id on ties
+fn earliest_swept_hit(best: &mut Option<(usize, SweptSphereHit)>, triangle_id: usize, hit: SweptSphereHit) {
+    let better = match best {
+        Some((id, b)) => { hit.t < b.t || (hit.t == b.t && triangle_id < *id) }
+        None => { true }
+    };
+    if better {
+        *best = Some((triangle_id, hit));
+    }
+}
+
+//Earliest hit between a moving sphere and any triangle of the terrain, along with that triangle's id
+pub fn swept_sphere_hit_terrain(terrain: &MeshCollision, sphere: &Sphere, velocity: &glm::TVec3<f32>) -> Option<(usize, SweptSphereHit)> {
+    let mut best = None;
+    for i in 0..terrain.indices.len() / 3 {
+        if let Some(hit) = swept_sphere_hit_triangle(sphere, velocity, &get_terrain_triangle(terrain, i * 3)) {
+            earliest_swept_hit(&mut best, i, hit);
+        }
+    }
+    best
+}
+
+//Moves a sphere by velocity, sliding along whatever it hits instead of stopping
+//velocity is the movement over this step. Returns the final center and the velocity with the components into every contact removed
+//Each contact uses up one of the max_iterations sweeps, and whatever movement is left after the last one is dropped,
+//so a sphere pushed into a crease can stop short of where sliding would have taken it
+fn collide_and_slide_with<F>(sphere: &Sphere, velocity: &glm::TVec3<f32>, max_iterations: usize, sweep: F) -> (glm::TVec3<f32>, glm::TVec3<f32>)
+where F: Fn(&Sphere, &glm::TVec3<f32>) -> Option<(usize, SweptSphereHit)> {
+    //How far to stay off of surfaces so the next sweep doesn't start in contact
+    const SKIN_WIDTH: f32 = 0.001;
+    const EPSILON: f32 = 0.000001;
+    let mut position = sphere.focus;
+    let mut remaining = *velocity;
+    let mut final_velocity = *velocity;
+
+    for _ in 0..max_iterations {
+        if glm::length2(&remaining) < EPSILON { break; }
+
+        let moving = Sphere { focus: position, radius: sphere.radius };
+        match sweep(&moving, &remaining) {
+            Some((_, hit)) => {
+                position += hit.t * remaining + SKIN_WIDTH * hit.normal;
+                remaining *= 1.0 - hit.t;
+                remaining -= hit.normal * glm::dot(&remaining, &hit.normal);
+                final_velocity -= hit.normal * f32::min(glm::dot(&final_velocity, &hit.normal), 0.0);
+            }
+            None => {
+                position += remaining;
+                remaining = glm::zero();
+            }
+        }
+    }
+    (position, final_velocity)
+}
+
+//See collide_and_slide_with() for how max_iterations limits the sliding
+pub fn collide_and_slide(terrain: &MeshCollision, sphere: &Sphere, velocity: &glm::TVec3<f32>, max_iterations: usize) -> (glm::TVec3<f32>, glm::TVec3<f32>) {
+    collide_and_slide_with(sphere, velocity, max_iterations, |s, v| { swept_sphere_hit_terrain(terrain, s, v) })
+}
+
+impl MeshBvh {
+    //Same result as swept_sphere_hit_terrain()
+    pub fn swept_sphere_cast(&self, terrain: &MeshCollision, sphere: &Sphere, velocity: &glm::TVec3<f32>) -> Option<(usize, SweptSphereHit)> {
+        let radius = glm::vec3(sphere.radius, sphere.radius, sphere.radius);
+        let end = sphere.focus + velocity;
+        let min = glm::min2(&sphere.focus, &end) - radius;
+        let max = glm::max2(&sphere.focus, &end) + radius;
+        let mut best = None;
+        self.traverse(|node| { boxes_overlap(&node.min, &node.max, &min, &max) }, |t| {
+            let triangle_id = t as usize;
+            if let Some(hit) = swept_sphere_hit_triangle(sphere, velocity, &get_terrain_triangle(terrain, triangle_id * 3)) {
+                earliest_swept_hit(&mut best, triangle_id, hit);
+            }
+        });
+        best
+    }
+
+    //Same as collide_and_slide(), using the BVH for each sweep
+    pub fn collide_and_slide(&self, terrain: &MeshCollision, sphere: &Sphere, velocity: &glm::TVec3<f32>, max_iterations: usize) -> (glm::TVec3<f32>, glm::TVec3<f32>) {
+        collide_and_slide_with(sphere, velocity, max_iterations, |s, v| { self.swept_sphere_cast(terrain, s, v) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Deterministic LCG so that failures are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        fn range(&mut self, lo: f32, hi: f32) -> f32 {
+            lo + (hi - lo) * self.next()
+        }
+
+        fn point(&mut self, lo: f32, hi: f32) -> glm::TVec3<f32> {
+            glm::vec3(self.range(lo, hi), self.range(lo, hi), self.range(lo, hi))
+        }
+    }
+
+    //Degenerate triangles get a zero normal
+    fn mesh(vertices: Vec<glm::TVec3<f32>>, indices: Vec<u32>) -> MeshCollision {
+        let face_normals = indices.chunks_exact(3).map(|t| {
+            let (a, b, c) = (vertices[t[0] as usize], vertices[t[1] as usize], vertices[t[2] as usize]);
+            let n = glm::cross(&(b - a), &(c - a));
+            if glm::length(&n) > 0.0 { glm::normalize(&n) } else { glm::zero() }
+        }).collect();
+        MeshCollision { vertices, indices, face_normals }
+    }
+
+    fn empty_mesh() -> MeshCollision {
+        mesh(Vec::new(), Vec::new())
+    }
+
+    //Unconnected triangles scattered through a cube
+    fn soup(rng: &mut Rng, count: usize, extent: f32) -> MeshCollision {
+        let mut vertices = Vec::new();
+        for _ in 0..count {
+            let center = rng.point(-extent, extent);
+            for _ in 0..3 {
+                vertices.push(center + rng.point(-1.5, 1.5));
+            }
+        }
+        let indices = (0..vertices.len() as u32).collect();
+        mesh(vertices, indices)
+    }
+
+    //size x size quads on integer XY coordinates with random heights, sharing vertices and edges
+    fn heightfield(rng: &mut Rng, size: u32) -> MeshCollision {
+        let mut vertices = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                vertices.push(glm::vec3(x as f32, y as f32, rng.range(-0.5, 0.5)));
+            }
+        }
+        let mut indices = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let i = y * (size + 1) + x;
+                indices.extend_from_slice(&[i, i + 1, i + size + 2, i, i + size + 2, i + size + 1]);
+            }
+        }
+        mesh(vertices, indices)
+    }
+
+    //Every triangle listed twice, so every hit is a tie
+    fn doubled(terrain: MeshCollision) -> MeshCollision {
+        let mut indices = terrain.indices.clone();
+        indices.extend_from_slice(&terrain.indices);
+        mesh(terrain.vertices, indices)
+    }
+
+    //Points, slivers and duplicates mixed in with ordinary triangles
+    fn degenerate() -> MeshCollision {
+        let vertices = vec![
+            glm::vec3(0.0, 0.0, 0.0), glm::vec3(2.0, 0.0, 0.0), glm::vec3(0.0, 2.0, 0.0),
+            glm::vec3(1.0, 1.0, 1.0), glm::vec3(3.0, 3.0, 3.0), glm::vec3(2.0, 2.0, 2.0),
+            glm::vec3(0.0, 0.0, 1.0), glm::vec3(2.0, 0.0, 1.0), glm::vec3(0.0, 2.0, 1.0)
+        ];
+        let indices = vec![
+            0, 1, 2,
+            3, 3, 3,        //A point
+            3, 4, 5,        //Collinear
+            0, 1, 2,        //Duplicate
+            6, 7, 8,
+            2, 1, 0,        //Flipped duplicate
+            0, 0, 1         //A segment
+        ];
+        mesh(vertices, indices)
+    }
+
+    fn test_meshes() -> Vec<MeshCollision> {
+        let mut rng = Rng(1);
+        vec![
+            soup(&mut rng, 1, 1.0),
+            soup(&mut rng, 300, 8.0),
+            heightfield(&mut rng, 12),
+            doubled(heightfield(&mut rng, 5)),
+            degenerate()
+        ]
+    }
+
+    //A cube around the mesh, with some room to spare
+    fn query_bounds(terrain: &MeshCollision) -> (f32, f32) {
+        let bounds = AABB::from_mesh(terrain).unwrap_or_else(|| AABB::new(glm::zero(), 1.0, 1.0, 1.0));
+        let (min, max) = (bounds.min(), bounds.max());
+        (min.min() - 2.0, max.max() + 2.0)
+    }
+
+    fn overlap_keys(overlaps: &[TerrainOverlap]) -> Vec<(usize, f32, glm::TVec3<f32>)> {
+        overlaps.iter().map(|o| (o.triangle_index, o.distance, o.point)).collect()
+    }
+
+    fn random_rays(rng: &mut Rng, terrain: &MeshCollision, count: usize) -> Vec<Ray> {
+        let (lo, hi) = query_bounds(terrain);
+        let mut rays = Vec::new();
+        for i in 0..count {
+            let origin = rng.point(lo, hi);
+            let direction = match i % 4 {
+                0 => { glm::vec3(0.0, 0.0, -1.0) }
+                1 => { glm::vec3(1.0, 0.0, 0.0) }
+                _ => { rng.point(lo + 2.0, hi - 2.0) - origin }
+            };
+            rays.push(Ray { origin, direction });
+        }
+
+        //Straight down through the vertices and edges of a heightfield, where neighboring triangles both report hits
+        for x in 0..6 {
+            rays.push(Ray { origin: glm::vec3(x as f32, 2.0, 5.0), direction: glm::vec3(0.0, 0.0, -1.0) });
+            rays.push(Ray { origin: glm::vec3(x as f32 + 0.5, 2.5, 5.0), direction: glm::vec3(0.0, 0.0, -1.0) });
+        }
+        rays
+    }
+
+    #[test]
+    fn bvh_ray_cast_matches_brute_force() {
+        let mut rng = Rng(2);
+        for terrain in test_meshes().iter() {
+            let bvh = MeshBvh::new(terrain);
+            for ray in random_rays(&mut rng, terrain, 400) {
+                let expected = ray_hit_terrain(terrain, &ray).map(|c| (c.triangle_index, c.smallest_t, c.point));
+                let actual = bvh.ray_cast(terrain, &ray).map(|c| (c.triangle_index, c.smallest_t, c.point));
+                assert_eq!(actual, expected, "ray {:?}", ray.origin);
+            }
+        }
+    }
+
+    #[test]
+    fn bvh_ray_cast_prefers_the_lower_triangle_on_ties() {
+        let mut rng = Rng(3);
+        let single = heightfield(&mut rng, 5);
+        let triangle_count = single.indices.len() / 3;
+        let terrain = doubled(single);
+        let bvh = MeshBvh::new(&terrain);
+
+        let mut hits = 0;
+        for ray in random_rays(&mut rng, &terrain, 200) {
+            if let Some(hit) = bvh.ray_cast(&terrain, &ray) {
+                assert!(hit.triangle_index < triangle_count);
+                hits += 1;
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn bvh_sphere_and_capsule_overlaps_match_brute_force() {
+        let mut rng = Rng(4);
+        for terrain in test_meshes().iter() {
+            let bvh = MeshBvh::new(terrain);
+            let (lo, hi) = query_bounds(terrain);
+            for _ in 0..200 {
+                let sphere = Sphere { focus: rng.point(lo, hi), radius: rng.range(0.05, 3.0) };
+                assert_eq!(overlap_keys(&bvh.sphere_overlap(terrain, &sphere)), overlap_keys(&sphere_hit_terrain(terrain, &sphere)));
+
+                let p0 = rng.point(lo, hi);
+                let capsule = Capsule::new(p0, p0 + rng.point(-3.0, 3.0), rng.range(0.05, 2.0));
+                assert_eq!(overlap_keys(&bvh.capsule_overlap(terrain, &capsule)), overlap_keys(&capsule_hit_terrain(terrain, &capsule)));
+            }
+        }
+    }
+
+    #[test]
+    fn bvh_box_overlaps_match_brute_force() {
+        let mut rng = Rng(5);
+        for terrain in test_meshes().iter() {
+            let bvh = MeshBvh::new(terrain);
+            let (lo, hi) = query_bounds(terrain);
+            for _ in 0..200 {
+                let (a, b) = (rng.point(lo, hi), rng.point(lo, hi));
+                let (min, max) = (glm::min2(&a, &b), glm::max2(&a, &b));
+                assert_eq!(bvh.box_overlap(terrain, &min, &max), box_hit_terrain(terrain, &min, &max));
+
+                let aabb = AABB::new(rng.point(lo, hi), rng.range(0.0, 3.0), rng.range(0.0, 3.0), rng.range(0.0, 3.0));
+                assert_eq!(bvh.aabb_overlap(terrain, &aabb), aabb_hit_terrain(terrain, &aabb));
+            }
+        }
+    }
+
+    #[test]
+    fn bvh_of_an_empty_mesh() {
+        let terrain = empty_mesh();
+        let bvh = MeshBvh::new(&terrain);
+        assert!(bvh.nodes.is_empty());
+        assert!(bvh.bounds().is_none());
+
+        let ray = Ray { origin: glm::zero(), direction: glm::vec3(0.0, 0.0, -1.0) };
+        assert!(bvh.ray_cast(&terrain, &ray).is_none());
+        assert!(bvh.sphere_overlap(&terrain, &Sphere { focus: glm::zero(), radius: 10.0 }).is_empty());
+        assert!(bvh.capsule_overlap(&terrain, &Capsule::new(glm::zero(), glm::vec3(0.0, 0.0, 1.0), 10.0)).is_empty());
+        assert!(bvh.aabb_overlap(&terrain, &AABB::new(glm::zero(), 10.0, 10.0, 10.0)).is_empty());
+    }
+
+    #[test]
+    fn bvh_bounds_contain_every_triangle() {
+        for terrain in test_meshes().iter() {
+            let bounds = MeshBvh::new(terrain).bounds().unwrap();
+            assert!(terrain.vertices.iter().all(|v| bounds.contains(v)));
+        }
+    }
+
+    fn assert_covers(candidates: &[usize], hits: &[usize], what: &str) {
+        for hit in hits {
+            assert!(candidates.contains(hit), "{}: triangle {} was hit but isn't a candidate", what, hit);
+        }
+    }
+
+    fn sphere_hits(terrain: &MeshCollision, grid: &TerrainGrid, sphere: &Sphere) -> Vec<usize> {
+        (0..terrain.indices.len() / 3).filter(|&i| {
+            triangle_collide_sphere(sphere, &get_terrain_triangle(terrain, i * 3), &grid.triangle_spheres[i]).is_some()
+        }).collect()
+    }
+
+    fn segment_hits(terrain: &MeshCollision, segment: &LineSegment) -> Vec<usize> {
+        (0..terrain.indices.len() / 3).filter(|&i| { segment_hit_triangle(segment, &get_terrain_triangle(terrain, i * 3)).is_some() }).collect()
+    }
+
+    fn random_segments(rng: &mut Rng, terrain: &MeshCollision, count: usize) -> Vec<LineSegment> {
+        let (lo, hi) = query_bounds(terrain);
+        let mut segments = Vec::new();
+        for i in 0..count {
+            let p0 = rng.point(lo, hi);
+            let p1 = match i % 5 {
+                0 => {
+                    let d = rng.range(-8.0, 8.0);
+                    p0 + glm::vec3(d, d, rng.range(-3.0, 3.0))
+                }
+                1 => { p0 + glm::vec3(0.0, 0.0, rng.range(-6.0, 6.0)) }
+                2 => { p0 + glm::vec3(rng.range(-8.0, 8.0), 0.0, 0.0) }
+                _ => { rng.point(lo, hi) }
+            };
+            segments.push(LineSegment { p0, p1 });
+        }
+        segments
+    }
+
+    #[test]
+    fn grid_sphere_candidates_cover_hits() {
+        let mut rng = Rng(6);
+        for terrain in test_meshes().iter() {
+            let (lo, hi) = query_bounds(terrain);
+            for &cell_size in [0.3, 1.0, 4.0].iter() {
+                let grid = TerrainGrid::new(terrain, cell_size);
+                for _ in 0..200 {
+                    let sphere = Sphere { focus: rng.point(lo, hi), radius: rng.range(0.05, 3.0) };
+                    assert_covers(&grid.sphere_candidates(&sphere), &sphere_hits(terrain, &grid, &sphere), "sphere");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grid_segment_candidates_cover_hits() {
+        let mut rng = Rng(7);
+        for terrain in test_meshes().iter() {
+            for &cell_size in [0.3, 1.0, 4.0].iter() {
+                let grid = TerrainGrid::new(terrain, cell_size);
+                for segment in random_segments(&mut rng, terrain, 300) {
+                    assert_covers(&grid.segment_candidates(&segment), &segment_hits(terrain, &segment), "segment");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grid_segment_candidates_special_cases() {
+        let mut rng = Rng(8);
+        let terrain = heightfield(&mut rng, 8);
+        let grid = TerrainGrid::new(&terrain, 1.0);
+        let segments = [
+            //Vertical, through a shared vertex and along a cell boundary
+            LineSegment { p0: glm::vec3(3.0, 3.0, 5.0), p1: glm::vec3(3.0, 3.0, -5.0) },
+            LineSegment { p0: glm::vec3(2.5, 4.0, 5.0), p1: glm::vec3(2.5, 4.0, -5.0) },
+            //Diagonal through the cell corners
+            LineSegment { p0: glm::vec3(0.0, 0.0, 1.0), p1: glm::vec3(8.0, 8.0, -1.0) },
+            LineSegment { p0: glm::vec3(8.0, 0.0, -1.0), p1: glm::vec3(0.0, 8.0, 1.0) },
+            //Starting and ending outside the grid
+            LineSegment { p0: glm::vec3(-5.0, -3.0, 2.0), p1: glm::vec3(13.0, 11.0, -2.0) },
+            LineSegment { p0: glm::vec3(4.0, -9.0, -7.0), p1: glm::vec3(4.5, 3.0, 1.0) },
+            LineSegment { p0: glm::vec3(20.0, 4.0, 1.0), p1: glm::vec3(-20.0, 4.2, -1.0) }
+        ];
+        for segment in segments.iter() {
+            let hits = segment_hits(&terrain, segment);
+            assert!(!hits.is_empty(), "{:?}", segment);
+            assert_covers(&grid.segment_candidates(segment), &hits, "segment");
+        }
+
+        //Entirely outside
+        let outside = LineSegment { p0: glm::vec3(-5.0, -5.0, 0.0), p1: glm::vec3(-1.0, 20.0, 0.0) };
+        assert!(grid.segment_candidates(&outside).is_empty());
+        let vertical_outside = LineSegment { p0: glm::vec3(20.0, 4.0, 5.0), p1: glm::vec3(20.0, 4.0, -5.0) };
+        assert!(grid.segment_candidates(&vertical_outside).is_empty());
+    }
+
+    #[test]
+    fn grid_of_an_empty_mesh() {
+        let grid = TerrainGrid::new(&empty_mesh(), 1.0);
+        assert_eq!((grid.columns, grid.rows), (1, 1));
+        assert!(grid.cell_triangles(0, 0).is_empty());
+        assert!(grid.sphere_candidates(&Sphere { focus: glm::zero(), radius: 10.0 }).is_empty());
+        assert!(grid.segment_candidates(&LineSegment { p0: glm::vec3(-5.0, -5.0, 0.0), p1: glm::vec3(5.0, 5.0, 0.0) }).is_empty());
+        assert!(grid.segment_candidates(&LineSegment { p0: glm::vec3(0.0, 0.0, 5.0), p1: glm::vec3(0.0, 0.0, -5.0) }).is_empty());
+    }
+
+    #[test]
+    fn grid_cell_count_is_capped() {
+        let mut rng = Rng(9);
+        let terrain = heightfield(&mut rng, 12);
+        let grid = TerrainGrid::new(&terrain, 0.0001);
+        assert!(grid.columns * grid.rows <= TerrainGrid::MAX_CELLS);
+        assert!(grid.cell_size > 0.0001);
+        for segment in random_segments(&mut rng, &terrain, 50) {
+            assert_covers(&grid.segment_candidates(&segment), &segment_hits(&terrain, &segment), "segment");
+        }
+
+        let huge = mesh(vec![
+            glm::vec3(-1.0e30, -1.0e30, 0.0), glm::vec3(-1.0e30 + 1.0e25, -1.0e30, 0.0), glm::vec3(-1.0e30, -1.0e30 + 1.0e25, 0.0),
+            glm::vec3(1.0e30, 1.0e30, 0.0), glm::vec3(1.0e30 - 1.0e25, 1.0e30, 0.0), glm::vec3(1.0e30, 1.0e30 - 1.0e25, 0.0)
+        ], vec![0, 1, 2, 3, 4, 5]);
+        let grid = TerrainGrid::new(&huge, 1.0);
+        assert!(grid.columns * grid.rows <= TerrainGrid::MAX_CELLS);
+        assert_eq!(grid.sphere_candidates(&Sphere { focus: glm::vec3(1.0e30, 1.0e30, 0.0), radius: 1.0 }), vec![1]);
+    }
+
+    fn assert_near(actual: &glm::TVec3<f32>, expected: &glm::TVec3<f32>) {
+        assert!(glm::distance(actual, expected) < 0.0001, "{:?} != {:?}", actual, expected);
+    }
+
+    //Horizontal, facing up, spanning [-5, 5] in x and y
+    fn floor_triangle() -> Triangle {
+        let (a, b, c) = (glm::vec3(-5.0, -5.0, 0.0), glm::vec3(5.0, -5.0, 0.0), glm::vec3(-5.0, 5.0, 0.0));
+        Triangle { a, b, c, normal: glm::vec3(0.0, 0.0, 1.0) }
+    }
+
+    #[test]
+    fn capsule_through_a_triangle_is_pushed_out_the_side_its_middle_is_on() {
+        let triangle = floor_triangle();
+        let (depth, push) = capsule_collide_triangle(&Capsule::new(glm::vec3(0.0, 0.0, -0.2), glm::vec3(0.0, 0.0, 1.0), 0.5), &triangle).unwrap();
+        assert!((depth - 0.7).abs() < 0.0001);
+        assert_near(&push, &glm::vec3(0.0, 0.0, 0.7));
+
+        let (depth, push) = capsule_collide_triangle(&Capsule::new(glm::vec3(0.0, 0.0, 0.2), glm::vec3(0.0, 0.0, -1.0), 0.5), &triangle).unwrap();
+        assert!((depth - 0.7).abs() < 0.0001);
+        assert_near(&push, &glm::vec3(0.0, 0.0, -0.7));
+    }
+
+    #[test]
+    fn capsule_near_a_triangle_is_pushed_away_from_the_closest_point() {
+        let triangle = floor_triangle();
+
+        //Lying flat, parallel to the triangle
+        let (depth, push) = capsule_collide_triangle(&Capsule::new(glm::vec3(-1.0, 0.0, 0.3), glm::vec3(1.0, 0.0, 0.3), 0.5), &triangle).unwrap();
+        assert!((depth - 0.2).abs() < 0.0001);
+        assert_near(&push, &glm::vec3(0.0, 0.0, 0.2));
+
+        //Beside the hypotenuse, pushed diagonally out in the triangle's plane
+        let (depth, push) = capsule_collide_triangle(&Capsule::new(glm::vec3(0.2, 0.2, -1.0), glm::vec3(0.2, 0.2, 1.0), 0.5), &triangle).unwrap();
+        let distance = 0.4 / f32::sqrt(2.0);
+        assert!((depth - (0.5 - distance)).abs() < 0.0001);
+        assert_near(&push, &(glm::normalize(&glm::vec3(1.0, 1.0, 0.0)) * (0.5 - distance)));
+
+        assert!(capsule_collide_triangle(&Capsule::new(glm::vec3(-1.0, 0.0, 0.6), glm::vec3(1.0, 0.0, 0.6), 0.5), &triangle).is_none());
+    }
+
+    #[test]
+    fn parallel_capsules_push_apart() {
+        let c1 = Capsule::new(glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 2.0), 0.4);
+        let c2 = Capsule::new(glm::vec3(0.5, 0.0, 1.0), glm::vec3(0.5, 0.0, 3.0), 0.4);
+        let (depth, push) = capsule_collide_capsule(&c1, &c2).unwrap();
+        assert!((depth - 0.3).abs() < 0.0001);
+        assert_near(&push, &glm::vec3(-0.3, 0.0, 0.0));
+
+        let far = Capsule::new(glm::vec3(0.9, 0.0, 1.0), glm::vec3(0.9, 0.0, 3.0), 0.4);
+        assert!(capsule_collide_capsule(&c1, &far).is_none());
+    }
+
+    #[test]
+    fn coincident_closest_points_use_the_fallback_direction() {
+        //A sphere centered on a vertical capsule's axis, where the perpendicular can't be horizontal-from-up
+        let vertical = Capsule::new(glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 2.0), 0.5);
+        let (depth, push) = capsule_collide_sphere(&vertical, &Sphere { focus: glm::vec3(0.0, 0.0, 1.0), radius: 0.25 }).unwrap();
+        assert!((depth - 0.75).abs() < 0.0001);
+        assert_near(&push, &glm::vec3(0.0, 0.75, 0.0));
+
+        //Crossing capsules push along a horizontal perpendicular of the second one
+        let c1 = Capsule::new(glm::vec3(0.0, -1.0, 0.0), glm::vec3(0.0, 1.0, 0.0), 0.5);
+        let c2 = Capsule::new(glm::vec3(-1.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0), 0.5);
+        let (depth, push) = capsule_collide_capsule(&c1, &c2).unwrap();
+        assert!((depth - 1.0).abs() < 0.0001);
+        assert_near(&push, &glm::vec3(0.0, -1.0, 0.0));
+
+        let fallback = glm::vec3(0.0, 0.0, 1.0);
+        let (depth, push) = separation_push(&glm::vec3(1.0, 2.0, 3.0), &glm::vec3(1.0, 2.0, 3.0), 0.5, &fallback).unwrap();
+        assert_eq!((depth, push), (0.5, fallback * 0.5));
+    }
+
+    #[test]
+    fn bvh_capsule_collide_matches_brute_force() {
+        let mut rng = Rng(10);
+        for terrain in test_meshes().iter() {
+            let bvh = MeshBvh::new(terrain);
+            let (lo, hi) = query_bounds(terrain);
+            let mut touched = 0;
+            for _ in 0..200 {
+                let p0 = rng.point(lo, hi);
+                let capsule = Capsule::new(p0, p0 + rng.point(-3.0, 3.0), rng.range(0.05, 2.0));
+                let expected = capsule_collide_terrain(terrain, &capsule);
+                assert_eq!(bvh.capsule_collide(terrain, &capsule), expected);
+                touched += expected.is_some() as usize;
+            }
+            assert!(touched > 0);
+        }
+    }
+
+    fn unit_box() -> AABB {
+        AABB::from_min_max(&glm::vec3(-1.0, -1.0, -1.0), &glm::vec3(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn aabb_from_points() {
+        assert!(AABB::from_points(&[]).is_none());
+
+        let single = AABB::from_points(&[glm::vec3(1.0, 2.0, 3.0)]).unwrap();
+        assert_eq!((single.center(), single.half_extents()), (glm::vec3(1.0, 2.0, 3.0), glm::zero()));
+
+        let aabb = AABB::from_points(&[glm::vec3(1.0, -2.0, 0.0), glm::vec3(-3.0, 4.0, 1.0), glm::vec3(0.0, 0.0, -5.0)]).unwrap();
+        assert_eq!((aabb.min(), aabb.max()), (glm::vec3(-3.0, -2.0, -5.0), glm::vec3(1.0, 4.0, 1.0)));
+        assert_eq!((aabb.width, aabb.depth, aabb.height), (4.0, 6.0, 6.0));
+        assert_eq!(aabb.position.w, 1.0);
+    }
+
+    #[test]
+    fn aabb_union() {
+        let a = unit_box();
+        let b = AABB::from_min_max(&glm::vec3(2.0, -3.0, 0.0), &glm::vec3(4.0, 0.0, 0.5));
+        let union = a.union(&b);
+        assert_eq!((union.min(), union.max()), (glm::vec3(-1.0, -3.0, -1.0), glm::vec3(4.0, 1.0, 1.0)));
+        assert_eq!(union, b.union(&a));
+
+        //A box inside another doesn't change it
+        let inner = AABB::new(glm::zero(), 0.5, 0.5, 0.5);
+        assert_eq!(a.union(&inner), a);
+    }
+
+    #[test]
+    fn aabb_ray_intersection() {
+        let aabb = unit_box();
+
+        let ray = Ray { origin: glm::vec3(-3.0, -3.0, 0.0), direction: glm::vec3(1.0, 1.0, 0.0) };
+        assert_eq!(aabb.ray_intersection(&ray), Some((2.0, 4.0)));
+
+        //Starting inside gives a negative entry
+        let ray = Ray { origin: glm::zero(), direction: glm::vec3(0.0, 0.0, 2.0) };
+        assert_eq!(aabb.ray_intersection(&ray), Some((-0.5, 0.5)));
+
+        //Behind the ray
+        let ray = Ray { origin: glm::vec3(0.0, 0.0, 3.0), direction: glm::vec3(0.0, 0.0, 1.0) };
+        assert_eq!(aabb.ray_intersection(&ray), None);
+
+        //Axis-parallel rays inside, outside and exactly on a slab boundary
+        let ray = Ray { origin: glm::vec3(0.5, 0.5, -5.0), direction: glm::vec3(0.0, 0.0, 1.0) };
+        assert_eq!(aabb.ray_intersection(&ray), Some((4.0, 6.0)));
+        let ray = Ray { origin: glm::vec3(1.5, 0.5, -5.0), direction: glm::vec3(0.0, 0.0, 1.0) };
+        assert_eq!(aabb.ray_intersection(&ray), None);
+        let ray = Ray { origin: glm::vec3(1.0, -1.0, -5.0), direction: glm::vec3(0.0, 0.0, 1.0) };
+        assert_eq!(aabb.ray_intersection(&ray), Some((4.0, 6.0)));
+        let ray = Ray { origin: glm::vec3(-4.0, 0.0, 0.0), direction: glm::vec3(-1.0, 0.0, 0.0) };
+        assert_eq!(aabb.ray_intersection(&ray), None);
+    }
+
+    #[test]
+    fn aabb_closest_point() {
+        let aabb = unit_box();
+        assert_eq!(aabb.closest_point(&glm::vec3(0.25, -0.5, 0.75)), glm::vec3(0.25, -0.5, 0.75));
+        assert_eq!(aabb.closest_point(&glm::vec3(3.0, 0.5, 0.0)), glm::vec3(1.0, 0.5, 0.0));
+        assert_eq!(aabb.closest_point(&glm::vec3(-3.0, 5.0, -2.0)), glm::vec3(-1.0, 1.0, -1.0));
+        assert!(aabb.intersects_sphere(&Sphere { focus: glm::vec3(2.0, 0.0, 0.0), radius: 1.0 }));
+        assert!(!aabb.intersects_sphere(&Sphere { focus: glm::vec3(2.0, 2.0, 0.0), radius: 1.0 }));
+    }
+
+    #[test]
+    fn aabb_transformed() {
+        let aabb = AABB::new(glm::vec3(1.0, 0.0, 0.0), 4.0, 2.0, 2.0);
+
+        //A quarter turn about z swaps width and depth and moves the center
+        let quarter_turn = glm::rotation(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 0.0, 1.0));
+        let turned = aabb.transformed(&quarter_turn);
+        assert_near(&turned.center(), &glm::vec3(0.0, 1.0, 0.0));
+        assert_near(&turned.half_extents(), &glm::vec3(1.0, 2.0, 1.0));
+
+        //An eighth turn grows the box to fit the rotated corners
+        let eighth_turn = glm::translation(&glm::vec3(0.0, 0.0, 5.0)) * glm::rotation(std::f32::consts::FRAC_PI_4, &glm::vec3(0.0, 0.0, 1.0));
+        let turned = aabb.transformed(&eighth_turn);
+        let half_diagonal = 3.0 / f32::sqrt(2.0);
+        assert_near(&turned.center(), &glm::vec3(1.0 / f32::sqrt(2.0), 1.0 / f32::sqrt(2.0), 5.0));
+        assert_near(&turned.half_extents(), &glm::vec3(half_diagonal, half_diagonal, 1.0));
+
+        let (min, max) = (aabb.min(), aabb.max());
+        let grown = turned.expanded(0.0001);
+        for i in 0..8 {
+            let corner = glm::vec3(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z }
+            );
+            assert!(grown.contains(&(eighth_turn * glm::vec4(corner.x, corner.y, corner.z, 1.0)).xyz()));
+        }
+    }
+
+    fn assert_swept_hit(hit: Option<SweptSphereHit>, t: f32, point: glm::TVec3<f32>, normal: glm::TVec3<f32>) {
+        let hit = hit.expect("expected a hit");
+        assert!((hit.t - t).abs() < 0.0001, "t {} != {}", hit.t, t);
+        assert_near(&hit.point, &point);
+        assert_near(&hit.normal, &normal);
+    }
+
+    //Two triangles covering [-10, 10] in x and y at height 0
+    fn ground() -> MeshCollision {
+        mesh(vec![
+            glm::vec3(-10.0, -10.0, 0.0), glm::vec3(10.0, -10.0, 0.0), glm::vec3(10.0, 10.0, 0.0), glm::vec3(-10.0, 10.0, 0.0)
+        ], vec![0, 1, 2, 0, 2, 3])
+    }
+
+    #[test]
+    fn swept_sphere_hits_a_face() {
+        let sphere = Sphere { focus: glm::vec3(0.0, 0.0, 5.0), radius: 1.0 };
+        let hit = swept_sphere_hit_triangle(&sphere, &glm::vec3(0.0, 0.0, -10.0), &floor_triangle());
+        assert_swept_hit(hit, 0.4, glm::zero(), glm::vec3(0.0, 0.0, 1.0));
+
+        //From below, since triangles are double-sided
+        let sphere = Sphere { focus: glm::vec3(-2.0, -2.0, -2.0), radius: 0.5 };
+        let hit = swept_sphere_hit_triangle(&sphere, &glm::vec3(1.0, 0.0, 3.0), &floor_triangle());
+        assert_swept_hit(hit, 0.5, glm::vec3(-1.5, -2.0, 0.0), glm::vec3(0.0, 0.0, -1.0));
+
+        assert!(swept_sphere_hit_triangle(&sphere, &glm::vec3(0.0, 0.0, 1.0), &floor_triangle()).is_none());
+    }
+
+    #[test]
+    fn swept_sphere_hits_an_edge() {
+        //In the triangle's plane, straight at the edge from (-5, -5) to (5, -5)
+        let sphere = Sphere { focus: glm::vec3(0.0, -10.0, 0.0), radius: 1.0 };
+        let hit = swept_sphere_hit_triangle(&sphere, &glm::vec3(0.0, 10.0, 0.0), &floor_triangle());
+        assert_swept_hit(hit, 0.4, glm::vec3(0.0, -5.0, 0.0), glm::vec3(0.0, -1.0, 0.0));
+
+        //Above the plane, so the edge is reached before the center is 1 away horizontally
+        let sphere = Sphere { focus: glm::vec3(0.0, -10.0, 0.5), radius: 1.0 };
+        let hit = swept_sphere_hit_triangle(&sphere, &glm::vec3(0.0, 10.0, 0.0), &floor_triangle());
+        let reach = f32::sqrt(0.75);
+        assert_swept_hit(hit, (5.0 - reach) / 10.0, glm::vec3(0.0, -5.0, 0.0), glm::normalize(&glm::vec3(0.0, -reach, 0.5)));
+    }
+
+    #[test]
+    fn swept_sphere_hits_a_vertex() {
+        //Diagonally at the corner (-5, -5), which passes both neighboring edges' lines outside of the edges themselves
+        let sphere = Sphere { focus: glm::vec3(-8.0, -8.0, 0.0), radius: 1.0 };
+        let hit = swept_sphere_hit_triangle(&sphere, &glm::vec3(4.0, 4.0, 0.0), &floor_triangle());
+        let t = (3.0 - 1.0 / f32::sqrt(2.0)) / 4.0;
+        assert_swept_hit(hit, t, glm::vec3(-5.0, -5.0, 0.0), glm::normalize(&glm::vec3(-1.0, -1.0, 0.0)));
+
+        assert!(swept_sphere_hit_triangle(&sphere, &glm::vec3(-4.0, 4.0, 0.0), &floor_triangle()).is_none());
+    }
+
+    #[test]
+    fn fast_sphere_does_not_tunnel() {
+        let thin = mesh(vec![glm::vec3(-0.1, -0.1, 0.0), glm::vec3(0.1, -0.1, 0.0), glm::vec3(0.0, 0.1, 0.0)], vec![0, 1, 2]);
+        let sphere = Sphere { focus: glm::vec3(0.0, 0.0, 1.0), radius: 0.05 };
+        let velocity = glm::vec3(0.0, 0.0, -100.0);
+
+        //Neither the start nor the end overlaps the triangle
+        assert!(sphere_hit_terrain(&thin, &Sphere { focus: sphere.focus + velocity, radius: sphere.radius }).is_empty());
+
+        let (id, hit) = swept_sphere_hit_terrain(&thin, &sphere, &velocity).unwrap();
+        assert_eq!(id, 0);
+        assert_swept_hit(Some(hit), 0.0095, glm::zero(), glm::vec3(0.0, 0.0, 1.0));
+
+        let (position, final_velocity) = collide_and_slide(&thin, &sphere, &velocity, 4);
+        assert!(position.z > sphere.radius && position.z < sphere.radius + 0.01);
+        assert_eq!(final_velocity.z, 0.0);
+    }
+
+    #[test]
+    fn resting_contact_slides() {
+        let terrain = ground();
+        let sphere = Sphere { focus: glm::vec3(0.0, 0.0, 0.4999), radius: 0.5 };
+
+        //Moving along the surface doesn't count as a hit
+        assert!(swept_sphere_hit_terrain(&terrain, &sphere, &glm::vec3(1.0, 0.0, 0.0)).is_none());
+
+        for velocity in [glm::vec3(1.0, 0.0, 0.0), glm::vec3(1.0, 0.0, -0.2)].iter() {
+            let (position, final_velocity) = collide_and_slide(&terrain, &sphere, velocity, 4);
+            assert!((position.x - 1.0).abs() < 0.0001 && position.y == 0.0);
+            assert!((position.z - 0.5).abs() < 0.01);
+            assert_near(&final_velocity, &glm::vec3(1.0, 0.0, 0.0));
+        }
+
+        //Moving away from the surface isn't a hit either
+        assert!(swept_sphere_hit_terrain(&terrain, &sphere, &glm::vec3(0.0, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn slide_stops_when_out_of_iterations() {
+        let terrain = ground();
+        let sphere = Sphere { focus: glm::vec3(0.0, 0.0, 1.5), radius: 0.5 };
+        let velocity = glm::vec3(2.0, 0.0, -2.0);
+
+        //One sweep reaches the ground halfway through, and the remaining slide is dropped
+        let (position, _) = collide_and_slide(&terrain, &sphere, &velocity, 1);
+        assert!((position.x - 1.0).abs() < 0.0001);
+
+        let (position, final_velocity) = collide_and_slide(&terrain, &sphere, &velocity, 2);
+        assert!((position.x - 2.0).abs() < 0.0001);
+        assert!((position.z - 0.5).abs() < 0.01);
+        assert_near(&final_velocity, &glm::vec3(2.0, 0.0, 0.0));
+
+        let (position, _) = collide_and_slide(&terrain, &sphere, &velocity, 0);
+        assert_eq!(position, sphere.focus);
+    }
+
+    #[test]
+    fn bvh_swept_sphere_cast_matches_brute_force() {
+        let mut rng = Rng(11);
+        for terrain in test_meshes().iter() {
+            let bvh = MeshBvh::new(terrain);
+            let (lo, hi) = query_bounds(terrain);
+            let mut hits = 0;
+            for _ in 0..200 {
+                let sphere = Sphere { focus: rng.point(lo, hi), radius: rng.range(0.05, 1.5) };
+                let velocity = rng.point(-6.0, 6.0);
+                let expected = swept_sphere_hit_terrain(terrain, &sphere, &velocity).map(|(id, h)| (id, h.t, h.point, h.normal));
+                let actual = bvh.swept_sphere_cast(terrain, &sphere, &velocity).map(|(id, h)| (id, h.t, h.point, h.normal));
+                assert_eq!(actual, expected);
+                hits += expected.is_some() as usize;
+
+                assert_eq!(bvh.collide_and_slide(terrain, &sphere, &velocity, 4), collide_and_slide(terrain, &sphere, &velocity, 4));
+            }
+            assert!(hits > 0);
+        }
+    }
+}
@@ -0,0 +1,1340 @@
+#![allow(non_camel_case_types)]
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem::size_of;
+use crate::{io::{read_u32, read_u32_data, read_u32_from_le_bytes, write_u32_data}, routines};
+
+//DDS Programming Guide
+//https://learn.microsoft.com/en-us/windows/win32/direct3ddds/dx-graphics-dds-pguide
+
+//Packs a four character code the way it's stored in a DDS file
+pub const fn four_cc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+#[derive(Debug)]
+pub enum DDSError {
+    Io(io::Error),
+    Truncated,
+    BadMagic(u32),
+    InvalidHeader(&'static str),
+    UnsupportedFormat(String)
+}
+
+impl DDSError {
+    fn from_read(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            DDSError::Truncated
+        } else {
+            DDSError::Io(e)
+        }
+    }
+}
+
+impl fmt::Display for DDSError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DDSError::Io(e) => { write!(f, "I/O error: {}", e) }
+            DDSError::Truncated => { write!(f, "file ended before all of the image data was read") }
+            DDSError::BadMagic(magic) => { write!(f, "expected magic word 0x{:08X} but found 0x{:08X}", DDSHeader::MAGIC_WORD, magic) }
+            DDSError::InvalidHeader(reason) => { write!(f, "invalid header: {}", reason) }
+            DDSError::UnsupportedFormat(format) => { write!(f, "unsupported pixel format: {}", format) }
+        }
+    }
+}
+
+impl std::error::Error for DDSError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DDSError::Io(e) => { Some(e) }
+            _ => { None }
+        }
+    }
+}
+
+impl From<io::Error> for DDSError {
+    fn from(e: io::Error) -> Self {
+        DDSError::Io(e)
+    }
+}
+
+/*
+struct DDS_PIXELFORMAT {
+  DWORD dwSize;
+  DWORD dwFlags;
+  DWORD dwFourCC;
+  DWORD dwRGBBitCount;
+  DWORD dwRBitMask;
+  DWORD dwGBitMask;
+  DWORD dwBBitMask;
+  DWORD dwABitMask;
+};
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct DDS_PixelFormat {
+    pub size: u32,
+    pub flags: u32,
+    pub four_cc: u32,
+    pub rgb_bitcount: u32,
+    pub r_bitmask: u32,
+    pub g_bitmask: u32,
+    pub b_bitmask: u32,
+    pub a_bitmask: u32,
+}
+
+impl DDS_PixelFormat {
+    pub const SIZE: u32 = 32;
+
+    pub const DDPF_ALPHAPIXELS: u32 = 0x1;
+    pub const DDPF_ALPHA: u32 = 0x2;
+    pub const DDPF_FOURCC: u32 = 0x4;
+    pub const DDPF_RGB: u32 = 0x40;
+    pub const DDPF_YUV: u32 = 0x200;
+    pub const DDPF_LUMINANCE: u32 = 0x20000;
+
+    pub fn from_header_bytes(header_bytes: &[u8]) -> Self {
+        let start = 19 * size_of::<u32>();
+        let mut current_offset = 0;
+
+        let size = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let flags = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let four_cc = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let rgb_bitcount = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let r_bitmask = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let g_bitmask = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let b_bitmask = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let a_bitmask = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        
+
+        DDS_PixelFormat {
+            size,
+            flags,
+            four_cc,
+            rgb_bitcount,
+            r_bitmask,
+            g_bitmask,
+            b_bitmask,
+            a_bitmask
+        }
+    }
+
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        let fields = read_u32_data(reader, 8)?;
+        Ok(DDS_PixelFormat {
+            size: fields[0],
+            flags: fields[1],
+            four_cc: fields[2],
+            rgb_bitcount: fields[3],
+            r_bitmask: fields[4],
+            g_bitmask: fields[5],
+            b_bitmask: fields[6],
+            a_bitmask: fields[7]
+        })
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        write_u32_data(writer, &[
+            self.size,
+            self.flags,
+            self.four_cc,
+            self.rgb_bitcount,
+            self.r_bitmask,
+            self.g_bitmask,
+            self.b_bitmask,
+            self.a_bitmask
+        ])
+    }
+
+    pub fn has_four_cc(&self, code: &[u8; 4]) -> bool {
+        self.flags & Self::DDPF_FOURCC != 0 && self.four_cc == four_cc(code)
+    }
+}
+
+impl Default for DDS_PixelFormat {
+    fn default() -> Self {
+        DDS_PixelFormat {
+            size: Self::SIZE,
+            flags: Self::DDPF_FOURCC,
+            four_cc: four_cc(b"DX10"),
+            rgb_bitcount: 0,
+            r_bitmask: 0,
+            g_bitmask: 0,
+            b_bitmask: 0,
+            a_bitmask: 0,
+        }
+    }
+}
+
+/*
+typedef struct {
+     DWORD           dwSize;
+     DWORD           dwFlags;
+     DWORD           dwHeight;
+     DWORD           dwWidth;
+     DWORD           dwPitchOrLinearSize;
+     DWORD           dwDepth;
+     DWORD           dwMipMapCount;
+     DWORD           dwReserved1[11];
+     DDS_PIXELFORMAT ddspf;
+     DWORD           dwCaps;
+     DWORD           dwCaps2;
+     DWORD           dwCaps3;
+     DWORD           dwCaps4;
+     DWORD           dwReserved2;
+} DDS_HEADER;
+   */
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct DDSHeader {
+    pub magic_word: u32,        // 0x20534444
+    pub size: u32,
+    pub flags: u32,
+    pub height: u32,
+    pub width: u32,
+    pub pitch_or_linear_size: u32,
+    pub depth: u32,
+    pub mipmap_count: u32,
+    pub reserved_1: [u32; 11],
+    pub spf: DDS_PixelFormat,
+    pub caps: u32,
+    pub caps2: u32,
+    pub caps3: u32,
+    pub caps4: u32,
+    pub reserved2: u32,
+    pub dx10_header: DDSHeader_DXT10            //Only present in the file if spf's FourCC is "DX10"
+}
+
+impl DDSHeader {
+    pub const MAGIC_WORD: u32 = 0x20534444;     //"DDS "
+    pub const SIZE: u32 = 124;                  //Size of DDS_HEADER, which excludes the magic word and the DX10 header
+
+    pub const DDSD_CAPS: u32 = 0x1;
+    pub const DDSD_HEIGHT: u32 = 0x2;
+    pub const DDSD_WIDTH: u32 = 0x4;
+    pub const DDSD_PITCH: u32 = 0x8;
+    pub const DDSD_PIXELFORMAT: u32 = 0x1000;
+    pub const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+    pub const DDSD_LINEARSIZE: u32 = 0x80000;
+    pub const DDSD_DEPTH: u32 = 0x800000;
+
+    pub const DDSCAPS_COMPLEX: u32 = 0x8;
+    pub const DDSCAPS_TEXTURE: u32 = 0x1000;
+    pub const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+    pub const DDSCAPS2_CUBEMAP: u32 = 0x200;
+    pub const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xFC00;
+    pub const DDSCAPS2_VOLUME: u32 = 0x200000;
+
+    pub fn from_file(dds_file: &mut File) -> Result<Self, DDSError> {
+        Self::from_reader(dds_file)
+    }
+
+    //Reads the magic word, DDS_HEADER, and the DX10 header if there is one
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R) -> Result<Self, DDSError> {
+        let magic_word = read_u32(reader).map_err(DDSError::from_read)?;
+        if magic_word != Self::MAGIC_WORD {
+            return Err(DDSError::BadMagic(magic_word));
+        }
+
+        let fields = read_u32_data(reader, 7).map_err(DDSError::from_read)?;
+        let mut reserved_1 = [0; 11];
+        reserved_1.copy_from_slice(&read_u32_data(reader, 11).map_err(DDSError::from_read)?);
+        let spf = DDS_PixelFormat::from_reader(reader).map_err(DDSError::from_read)?;
+        let caps = read_u32_data(reader, 5).map_err(DDSError::from_read)?;
+
+        if fields[0] != Self::SIZE {
+            return Err(DDSError::InvalidHeader("DDS_HEADER.dwSize must be 124"));
+        }
+        if spf.size != DDS_PixelFormat::SIZE {
+            return Err(DDSError::InvalidHeader("DDS_PIXELFORMAT.dwSize must be 32"));
+        }
+
+        let dx10_header = if spf.has_four_cc(b"DX10") {
+            DDSHeader_DXT10::from_reader(reader)?
+        } else {
+            DDSHeader_DXT10::default()
+        };
+
+        Ok(DDSHeader {
+            magic_word,
+            size: fields[0],
+            flags: fields[1],
+            height: fields[2],
+            width: fields[3],
+            pitch_or_linear_size: fields[4],
+            depth: fields[5],
+            mipmap_count: fields[6],
+            reserved_1,
+            spf,
+            caps: caps[0],
+            caps2: caps[1],
+            caps3: caps[2],
+            caps4: caps[3],
+            reserved2: caps[4],
+            dx10_header
+        })
+    }
+
+    pub fn has_dx10_header(&self) -> bool {
+        self.spf.has_four_cc(b"DX10")
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        write_u32_data(writer, &[
+            self.magic_word,
+            self.size,
+            self.flags,
+            self.height,
+            self.width,
+            self.pitch_or_linear_size,
+            self.depth,
+            self.mipmap_count
+        ])?;
+        write_u32_data(writer, &self.reserved_1)?;
+        self.spf.write_to(writer)?;
+        write_u32_data(writer, &[self.caps, self.caps2, self.caps3, self.caps4, self.reserved2])?;
+        if self.has_dx10_header() {
+            self.dx10_header.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for DDSHeader {
+    fn default() -> Self {
+        DDSHeader {
+            magic_word: Self::MAGIC_WORD,
+            size: Self::SIZE,
+            flags: 0,
+            height: 0,
+            width: 0,
+            pitch_or_linear_size: 0,
+            depth: 0,
+            mipmap_count: 1,
+            reserved_1: [0; 11],
+            spf: DDS_PixelFormat::default(),
+            caps: 0,
+            caps2: 0,
+            caps3: 0,
+            caps4: 0,
+            reserved2: 0,
+            dx10_header: DDSHeader_DXT10::default()
+        }
+    }
+}
+
+/*
+typedef struct {
+  DXGI_FORMAT              dxgiFormat;
+  D3D10_RESOURCE_DIMENSION resourceDimension;
+  UINT                     miscFlag;
+  UINT                     arraySize;
+  UINT                     miscFlags2;
+} DDS_HEADER_DXT10;
+ */
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct DDSHeader_DXT10 {
+    pub dxgi_format: DXGI_FORMAT,
+    pub resource_dimension: D3D10_RESOURCE_DIMENSION,
+    pub misc_flag: u32,
+    pub array_size: u32,
+    pub misc_flags2: u32
+}
+
+impl DDSHeader_DXT10 {
+    pub const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+    pub fn from_header_bytes(header_bytes: &[u8]) -> Self {
+        let start = size_of::<DDSHeader>() - size_of::<DDSHeader_DXT10>();
+        let mut current_offset = 0;
+
+        let dxgi_format = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        let dxgi_format = DXGI_FORMAT::from_u32(dxgi_format).unwrap_or(DXGI_FORMAT::UNKNOWN);
+        current_offset += size_of::<u32>();
+
+        let resource_dimension = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        let resource_dimension = D3D10_RESOURCE_DIMENSION::from_u32(resource_dimension).unwrap_or(D3D10_RESOURCE_DIMENSION::UNKNOWN);
+        current_offset += size_of::<u32>();
+
+        let misc_flag = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let array_size = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        current_offset += size_of::<u32>();
+
+        let misc_flags2 = read_u32_from_le_bytes(header_bytes, start + current_offset);
+        
+        DDSHeader_DXT10 {
+            dxgi_format,
+            resource_dimension,
+            misc_flag,
+            array_size,
+            misc_flags2
+        }
+    }
+
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R) -> Result<Self, DDSError> {
+        let fields = read_u32_data(reader, 5).map_err(DDSError::from_read)?;
+        let dxgi_format = match DXGI_FORMAT::from_u32(fields[0]) {
+            Some(format) => { format }
+            None => { return Err(DDSError::UnsupportedFormat(format!("unknown DXGI_FORMAT {}", fields[0]))); }
+        };
+        let resource_dimension = match D3D10_RESOURCE_DIMENSION::from_u32(fields[1]) {
+            Some(dimension) => { dimension }
+            None => { return Err(DDSError::InvalidHeader("unknown resource dimension")); }
+        };
+
+        Ok(DDSHeader_DXT10 {
+            dxgi_format,
+            resource_dimension,
+            misc_flag: fields[2],
+            array_size: fields[3],
+            misc_flags2: fields[4]
+        })
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        write_u32_data(writer, &[
+            self.dxgi_format as u32,
+            self.resource_dimension as u32,
+            self.misc_flag,
+            self.array_size,
+            self.misc_flags2
+        ])
+    }
+}
+
+impl Default for DDSHeader_DXT10 {
+    fn default() -> Self {
+        DDSHeader_DXT10 {
+            dxgi_format: DXGI_FORMAT::UNKNOWN,
+            resource_dimension: D3D10_RESOURCE_DIMENSION::TEXTURE2D,
+            misc_flag: 0,
+            array_size: 1,
+            misc_flags2: 0
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum D3D10_RESOURCE_DIMENSION {
+    UNKNOWN = 0,
+    BUFFER = 1,
+    TEXTURE1D = 2,
+    TEXTURE2D = 3,
+    TEXTURE3D = 4,
+
+    _RESERVED = 0xFFFFFFFF
+}
+
+impl D3D10_RESOURCE_DIMENSION {
+    pub fn from_u32(n: u32) -> Option<Self> {
+        use D3D10_RESOURCE_DIMENSION::*;
+        match n {
+            0 => { Some(UNKNOWN) }
+            1 => { Some(BUFFER) }
+            2 => { Some(TEXTURE1D) }
+            3 => { Some(TEXTURE2D) }
+            4 => { Some(TEXTURE3D) }
+            _ => { None }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum DXGI_FORMAT {
+    UNKNOWN = 0,
+    R32G32B32A32_TYPELESS = 1,
+    R32G32B32A32_FLOAT = 2,
+    R32G32B32A32_UINT = 3,
+    R32G32B32A32_SINT = 4,
+    R32G32B32_TYPELESS = 5,
+    R32G32B32_FLOAT = 6,
+    R32G32B32_UINT = 7,
+    R32G32B32_SINT = 8,
+    R16G16B16A16_TYPELESS = 9,
+    R16G16B16A16_FLOAT = 10,
+    R16G16B16A16_UNORM = 11,
+    R16G16B16A16_UINT = 12,
+    R16G16B16A16_SNORM = 13,
+    R16G16B16A16_SINT = 14,
+    R32G32_TYPELESS = 15,
+    R32G32_FLOAT = 16,
+    R32G32_UINT = 17,
+    R32G32_SINT = 18,
+    R32G8X24_TYPELESS = 19,
+    D32_FLOAT_S8X24_UINT = 20,
+    R32_FLOAT_X8X24_TYPELESS = 21,
+    X32_TYPELESS_G8X24_UINT = 22,
+    R10G10B10A2_TYPELESS = 23,
+    R10G10B10A2_UNORM = 24,
+    R10G10B10A2_UINT = 25,
+    R11G11B10_FLOAT = 26,
+    R8G8B8A8_TYPELESS = 27,
+    R8G8B8A8_UNORM = 28,
+    R8G8B8A8_UNORM_SRGB = 29,
+    R8G8B8A8_UINT = 30,
+    R8G8B8A8_SNORM = 31,
+    R8G8B8A8_SINT = 32,
+    R16G16_TYPELESS = 33,
+    R16G16_FLOAT = 34,
+    R16G16_UNORM = 35,
+    R16G16_UINT = 36,
+    R16G16_SNORM = 37,
+    R16G16_SINT = 38,
+    R32_TYPELESS = 39,
+    D32_FLOAT = 40,
+    R32_FLOAT = 41,
+    R32_UINT = 42,
+    R32_SINT = 43,
+    R24G8_TYPELESS = 44,
+    D24_UNORM_S8_UINT = 45,
+    R24_UNORM_X8_TYPELESS = 46,
+    X24_TYPELESS_G8_UINT = 47,
+    R8G8_TYPELESS = 48,
+    R8G8_UNORM = 49,
+    R8G8_UINT = 50,
+    R8G8_SNORM = 51,
+    R8G8_SINT = 52,
+    R16_TYPELESS = 53,
+    R16_FLOAT = 54,
+    D16_UNORM = 55,
+    R16_UNORM = 56,
+    R16_UINT = 57,
+    R16_SNORM = 58,
+    R16_SINT = 59,
+    R8_TYPELESS = 60,
+    R8_UNORM = 61,
+    R8_UINT = 62,
+    R8_SNORM = 63,
+    R8_SINT = 64,
+    A8_UNORM = 65,
+    R1_UNORM = 66,
+    R9G9B9E5_SHAREDEXP = 67,
+    R8G8_B8G8_UNORM = 68,
+    G8R8_G8B8_UNORM = 69,
+    BC1_TYPELESS = 70,
+    BC1_UNORM = 71,
+    BC1_UNORM_SRGB = 72,
+    BC2_TYPELESS = 73,
+    BC2_UNORM = 74,
+    BC2_UNORM_SRGB = 75,
+    BC3_TYPELESS = 76,
+    BC3_UNORM = 77,
+    BC3_UNORM_SRGB = 78,
+    BC4_TYPELESS = 79,
+    BC4_UNORM = 80,
+    BC4_SNORM = 81,
+    BC5_TYPELESS = 82,
+    BC5_UNORM = 83,
+    BC5_SNORM = 84,
+    B5G6R5_UNORM = 85,
+    B5G5R5A1_UNORM = 86,
+    B8G8R8A8_UNORM = 87,
+    B8G8R8X8_UNORM = 88,
+    R10G10B10_XR_BIAS_A2_UNORM = 89,
+    B8G8R8A8_TYPELESS = 90,
+    B8G8R8A8_UNORM_SRGB = 91,
+    B8G8R8X8_TYPELESS = 92,
+    B8G8R8X8_UNORM_SRGB = 93,
+    BC6H_TYPELESS = 94,
+    BC6H_UF16 = 95,
+    BC6H_SF16 = 96,
+    BC7_TYPELESS = 97,
+    BC7_UNORM = 98,
+    BC7_UNORM_SRGB = 99,
+    AYUV = 100,
+    Y410 = 101,
+    Y416 = 102,
+    NV12 = 103,
+    P010 = 104,
+    P016 = 105,
+    //420_OPAQUE = 106,
+    YUY2 = 107,
+    Y210 = 108,
+    Y216 = 109,
+    NV11 = 110,
+    AI44 = 111,
+    IA44 = 112,
+    P8 = 113,
+    A8P8 = 114,
+    B4G4R4A4_UNORM = 115,
+    P208 = 130,
+    V208 = 131,
+    V408 = 132,
+    SAMPLER_FEEDBACK_MIN_MIP_OPAQUE,
+    SAMPLER_FEEDBACK_MIP_REGION_USED_OPAQUE,
+    FORCE_UINT = 0xffffffff
+}
+
+impl DXGI_FORMAT {
+    pub fn from_u32(n: u32) -> Option<Self> {
+        use DXGI_FORMAT::*;
+        match n {
+            0 => { Some(UNKNOWN) }
+            1 => { Some(R32G32B32A32_TYPELESS) }
+            2 => { Some(R32G32B32A32_FLOAT) }
+            3 => { Some(R32G32B32A32_UINT) }
+            4 => { Some(R32G32B32A32_SINT) }
+            5 => { Some(R32G32B32_TYPELESS) }
+            6 => { Some(R32G32B32_FLOAT) }
+            7 => { Some(R32G32B32_UINT) }
+            8 => { Some(R32G32B32_SINT) }
+            9 => { Some(R16G16B16A16_TYPELESS) }
+            10 => { Some(R16G16B16A16_FLOAT) }
+            11 => { Some(R16G16B16A16_UNORM) }
+            12 => { Some(R16G16B16A16_UINT) }
+            13 => { Some(R16G16B16A16_SNORM) }
+            14 => { Some(R16G16B16A16_SINT) }
+            15 => { Some(R32G32_TYPELESS) }
+            16 => { Some(R32G32_FLOAT) }
+            17 => { Some(R32G32_UINT) }
+            18 => { Some(R32G32_SINT) }
+            19 => { Some(R32G8X24_TYPELESS) }
+            20 => { Some(D32_FLOAT_S8X24_UINT) }
+            21 => { Some(R32_FLOAT_X8X24_TYPELESS) }
+            22 => { Some(X32_TYPELESS_G8X24_UINT) }
+            23 => { Some(R10G10B10A2_TYPELESS) }
+            24 => { Some(R10G10B10A2_UNORM) }
+            25 => { Some(R10G10B10A2_UINT) }
+            26 => { Some(R11G11B10_FLOAT) }
+            27 => { Some(R8G8B8A8_TYPELESS) }
+            28 => { Some(R8G8B8A8_UNORM) }
+            29 => { Some(R8G8B8A8_UNORM_SRGB) }
+            30 => { Some(R8G8B8A8_UINT) }
+            31 => { Some(R8G8B8A8_SNORM) }
+            32 => { Some(R8G8B8A8_SINT) }
+            33 => { Some(R16G16_TYPELESS) }
+            34 => { Some(R16G16_FLOAT) }
+            35 => { Some(R16G16_UNORM) }
+            36 => { Some(R16G16_UINT) }
+            37 => { Some(R16G16_SNORM) }
+            38 => { Some(R16G16_SINT) }
+            39 => { Some(R32_TYPELESS) }
+            40 => { Some(D32_FLOAT) }
+            41 => { Some(R32_FLOAT) }
+            42 => { Some(R32_UINT) }
+            43 => { Some(R32_SINT) }
+            44 => { Some(R24G8_TYPELESS) }
+            45 => { Some(D24_UNORM_S8_UINT) }
+            46 => { Some(R24_UNORM_X8_TYPELESS) }
+            47 => { Some(X24_TYPELESS_G8_UINT) }
+            48 => { Some(R8G8_TYPELESS) }
+            49 => { Some(R8G8_UNORM) }
+            50 => { Some(R8G8_UINT) }
+            51 => { Some(R8G8_SNORM) }
+            52 => { Some(R8G8_SINT) }
+            53 => { Some(R16_TYPELESS) }
+            54 => { Some(R16_FLOAT) }
+            55 => { Some(D16_UNORM) }
+            56 => { Some(R16_UNORM) }
+            57 => { Some(R16_UINT) }
+            58 => { Some(R16_SNORM) }
+            59 => { Some(R16_SINT) }
+            60 => { Some(R8_TYPELESS) }
+            61 => { Some(R8_UNORM) }
+            62 => { Some(R8_UINT) }
+            63 => { Some(R8_SNORM) }
+            64 => { Some(R8_SINT) }
+            65 => { Some(A8_UNORM) }
+            66 => { Some(R1_UNORM) }
+            67 => { Some(R9G9B9E5_SHAREDEXP) }
+            68 => { Some(R8G8_B8G8_UNORM) }
+            69 => { Some(G8R8_G8B8_UNORM) }
+            70 => { Some(BC1_TYPELESS) }
+            71 => { Some(BC1_UNORM) }
+            72 => { Some(BC1_UNORM_SRGB) }
+            73 => { Some(BC2_TYPELESS) }
+            74 => { Some(BC2_UNORM) }
+            75 => { Some(BC2_UNORM_SRGB) }
+            76 => { Some(BC3_TYPELESS) }
+            77 => { Some(BC3_UNORM) }
+            78 => { Some(BC3_UNORM_SRGB) }
+            79 => { Some(BC4_TYPELESS) }
+            80 => { Some(BC4_UNORM) }
+            81 => { Some(BC4_SNORM) }
+            82 => { Some(BC5_TYPELESS) }
+            83 => { Some(BC5_UNORM) }
+            84 => { Some(BC5_SNORM) }
+            85 => { Some(B5G6R5_UNORM) }
+            86 => { Some(B5G5R5A1_UNORM) }
+            87 => { Some(B8G8R8A8_UNORM) }
+            88 => { Some(B8G8R8X8_UNORM) }
+            89 => { Some(R10G10B10_XR_BIAS_A2_UNORM) }
+            90 => { Some(B8G8R8A8_TYPELESS) }
+            91 => { Some(B8G8R8A8_UNORM_SRGB) }
+            92 => { Some(B8G8R8X8_TYPELESS) }
+            93 => { Some(B8G8R8X8_UNORM_SRGB) }
+            94 => { Some(BC6H_TYPELESS) }
+            95 => { Some(BC6H_UF16) }
+            96 => { Some(BC6H_SF16) }
+            97 => { Some(BC7_TYPELESS) }
+            98 => { Some(BC7_UNORM) }
+            99 => { Some(BC7_UNORM_SRGB) }
+            100 => { Some(AYUV) }
+            101 => { Some(Y410) }
+            102 => { Some(Y416) }
+            103 => { Some(NV12) }
+            104 => { Some(P010) }
+            105 => { Some(P016) }
+            107 => { Some(YUY2) }
+            108 => { Some(Y210) }
+            109 => { Some(Y216) }
+            110 => { Some(NV11) }
+            111 => { Some(AI44) }
+            112 => { Some(IA44) }
+            113 => { Some(P8) }
+            114 => { Some(A8P8) }
+            115 => { Some(B4G4R4A4_UNORM) }
+            130 => { Some(P208) }
+            131 => { Some(V208) }
+            132 => { Some(V408) }
+            133 => { Some(SAMPLER_FEEDBACK_MIN_MIP_OPAQUE) }
+            134 => { Some(SAMPLER_FEEDBACK_MIP_REGION_USED_OPAQUE) }
+            0xFFFFFFFF => { Some(FORCE_UINT) }
+            _ => { None }
+        }
+    }
+
+    //Returns None for planar, packed and sub-byte formats, which have no simple per-pixel or per-block size
+    pub fn layout(&self) -> Option<PixelLayout> {
+        use PixelLayout::*;
+        match *self as u32 {
+            1..=4 => { Some(Uncompressed { bits_per_pixel: 128 }) }
+            5..=8 => { Some(Uncompressed { bits_per_pixel: 96 }) }
+            9..=22 => { Some(Uncompressed { bits_per_pixel: 64 }) }
+            23..=47 | 67 => { Some(Uncompressed { bits_per_pixel: 32 }) }
+            48..=59 => { Some(Uncompressed { bits_per_pixel: 16 }) }
+            60..=65 => { Some(Uncompressed { bits_per_pixel: 8 }) }
+            70..=72 | 79..=81 => { Some(BlockCompressed { block_bytes: 8 }) }
+            73..=78 | 82..=84 | 94..=99 => { Some(BlockCompressed { block_bytes: 16 }) }
+            85 | 86 | 115 => { Some(Uncompressed { bits_per_pixel: 16 }) }
+            87..=93 | 100 | 101 => { Some(Uncompressed { bits_per_pixel: 32 }) }
+            102 => { Some(Uncompressed { bits_per_pixel: 64 }) }
+            111..=113 => { Some(Uncompressed { bits_per_pixel: 8 }) }
+            114 => { Some(Uncompressed { bits_per_pixel: 16 }) }
+            _ => { None }
+        }
+    }
+}
+
+pub fn compute_pitch_bc(width: u32, block_size: u32) -> u32 {
+    //max( 1, ((width+3)/4) ) * block-size
+    u32::max(1, width.div_ceil(4)) * block_size
+}
+
+//How the bytes of a surface are laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelLayout {
+    BlockCompressed { block_bytes: u32 },      //4x4 pixel blocks
+    Uncompressed { bits_per_pixel: u32 }
+}
+
+impl PixelLayout {
+    pub fn row_pitch(&self, width: u32) -> u32 {
+        match self {
+            PixelLayout::BlockCompressed { block_bytes } => { compute_pitch_bc(width, *block_bytes) }
+            PixelLayout::Uncompressed { bits_per_pixel } => { (width * bits_per_pixel).div_ceil(8) }
+        }
+    }
+
+    pub fn surface_size(&self, width: u32, height: u32, depth: u32) -> usize {
+        let rows = match self {
+            PixelLayout::BlockCompressed { .. } => { u32::max(1, height.div_ceil(4)) }
+            PixelLayout::Uncompressed { .. } => { height }
+        };
+        self.row_pitch(width) as usize * rows as usize * depth as usize
+    }
+
+    //surface_size() that returns None instead of overflowing
+    pub fn checked_surface_size(&self, width: u32, height: u32, depth: u32) -> Option<usize> {
+        let (row_pitch, rows) = match self {
+            PixelLayout::BlockCompressed { block_bytes } => {
+                ((u32::max(1, width.div_ceil(4)) as usize).checked_mul(*block_bytes as usize)?, u32::max(1, height.div_ceil(4)))
+            }
+            PixelLayout::Uncompressed { bits_per_pixel } => { ((width as usize).checked_mul(*bits_per_pixel as usize)?.div_ceil(8), height) }
+        };
+        row_pitch.checked_mul(rows as usize)?.checked_mul(depth as usize)
+    }
+}
+
+//The format of a DDS file's pixels
+//Legacy files are mapped onto a DXGI_FORMAT wherever one exists
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DDSFormat {
+    Dxgi(DXGI_FORMAT),
+
+    //Uncompressed formats described only by their channel masks, e.g. 24-bit RGB which has no DXGI equivalent
+    Masked {
+        rgb_bitcount: u32,
+        r_bitmask: u32,
+        g_bitmask: u32,
+        b_bitmask: u32,
+        a_bitmask: u32
+    }
+}
+
+impl DDSFormat {
+    //Interprets a pre-DX10 pixel format
+    pub fn from_pixel_format(spf: &DDS_PixelFormat) -> Result<Self, DDSError> {
+        use DXGI_FORMAT::*;
+
+        if spf.flags & DDS_PixelFormat::DDPF_FOURCC != 0 {
+            let format = match &spf.four_cc.to_le_bytes() {
+                b"DXT1" => { BC1_UNORM }
+                b"DXT2" | b"DXT3" => { BC2_UNORM }
+                b"DXT4" | b"DXT5" => { BC3_UNORM }
+                b"ATI1" | b"BC4U" => { BC4_UNORM }
+                b"BC4S" => { BC4_SNORM }
+                b"ATI2" | b"BC5U" => { BC5_UNORM }
+                b"BC5S" => { BC5_SNORM }
+                b"RGBG" => { R8G8_B8G8_UNORM }
+                b"GRGB" => { G8R8_G8B8_UNORM }
+                _ => {
+                    //Some writers put a D3DFORMAT value in the FourCC field instead of four characters
+                    match spf.four_cc {
+                        36 => { R16G16B16A16_UNORM }
+                        110 => { R16G16B16A16_SNORM }
+                        111 => { R16_FLOAT }
+                        112 => { R16G16_FLOAT }
+                        113 => { R16G16B16A16_FLOAT }
+                        114 => { R32_FLOAT }
+                        115 => { R32G32_FLOAT }
+                        116 => { R32G32B32A32_FLOAT }
+                        _ => {
+                            let chars = String::from_utf8_lossy(&spf.four_cc.to_le_bytes()).into_owned();
+                            return Err(DDSError::UnsupportedFormat(format!("FourCC \"{}\"", chars)));
+                        }
+                    }
+                }
+            };
+            return Ok(DDSFormat::Dxgi(format));
+        }
+
+        //The alpha mask is only meaningful if the file says so
+        let a_bitmask = if spf.flags & (DDS_PixelFormat::DDPF_ALPHAPIXELS | DDS_PixelFormat::DDPF_ALPHA) != 0 {
+            spf.a_bitmask
+        } else {
+            0
+        };
+        let masks = (spf.rgb_bitcount, spf.r_bitmask, spf.g_bitmask, spf.b_bitmask, a_bitmask);
+
+        let known_format = if spf.flags & DDS_PixelFormat::DDPF_RGB != 0 {
+            match masks {
+                (32, 0xFF, 0xFF00, 0xFF0000, 0xFF000000) => { Some(R8G8B8A8_UNORM) }
+                (32, 0xFF0000, 0xFF00, 0xFF, 0xFF000000) => { Some(B8G8R8A8_UNORM) }
+                (32, 0xFF0000, 0xFF00, 0xFF, 0) => { Some(B8G8R8X8_UNORM) }
+                (32, 0xFFFF, 0xFFFF0000, 0, 0) => { Some(R16G16_UNORM) }
+                (32, 0x3FF, 0xFFC00, 0x3FF00000, 0xC0000000) => { Some(R10G10B10A2_UNORM) }
+                (32, 0xFFFFFFFF, 0, 0, 0) => { Some(R32_FLOAT) }
+                (16, 0xF800, 0x7E0, 0x1F, 0) => { Some(B5G6R5_UNORM) }
+                (16, 0x7C00, 0x3E0, 0x1F, 0x8000) => { Some(B5G5R5A1_UNORM) }
+                (16, 0xF00, 0xF0, 0xF, 0xF000) => { Some(B4G4R4A4_UNORM) }
+                _ => { None }
+            }
+        } else if spf.flags & DDS_PixelFormat::DDPF_LUMINANCE != 0 {
+            match masks {
+                (8, 0xFF, _, _, 0) => { Some(R8_UNORM) }
+                (16, 0xFFFF, _, _, 0) => { Some(R16_UNORM) }
+                (16, 0xFF, _, _, 0xFF00) => { Some(R8G8_UNORM) }
+                _ => { None }
+            }
+        } else if spf.flags & DDS_PixelFormat::DDPF_ALPHA != 0 {
+            match masks {
+                (8, _, _, _, 0xFF) => { Some(A8_UNORM) }
+                _ => { None }
+            }
+        } else {
+            return Err(DDSError::UnsupportedFormat(format!("pixel format flags 0x{:X}", spf.flags)));
+        };
+
+        match known_format {
+            Some(format) => { Ok(DDSFormat::Dxgi(format)) }
+            None if spf.rgb_bitcount > 0 && spf.rgb_bitcount.is_multiple_of(8) => {
+                Ok(DDSFormat::Masked {
+                    rgb_bitcount: spf.rgb_bitcount,
+                    r_bitmask: spf.r_bitmask,
+                    g_bitmask: spf.g_bitmask,
+                    b_bitmask: spf.b_bitmask,
+                    a_bitmask
+                })
+            }
+            None => { Err(DDSError::UnsupportedFormat(format!("{}-bit masked format", spf.rgb_bitcount))) }
+        }
+    }
+
+    pub fn layout(&self) -> Option<PixelLayout> {
+        match self {
+            DDSFormat::Dxgi(format) => { format.layout() }
+            DDSFormat::Masked { rgb_bitcount, .. } => { Some(PixelLayout::Uncompressed { bits_per_pixel: *rgb_bitcount }) }
+        }
+    }
+}
+
+//A complete DDS file: 2D textures, mip chains, cube maps, texture arrays and volume textures
+//Surfaces are stored the same way the file stores them: for each layer, every mip from largest to smallest
+//Cube maps have six layers per array element, in the order +X, -X, +Y, -Y, +Z, -Z
+#[derive(Clone, Debug)]
+pub struct DDSTexture {
+    pub format: DDSFormat,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub mipmap_count: u32,
+    pub array_size: u32,
+    pub is_cubemap: bool,
+    pub data: Vec<u8>
+}
+
+impl DDSTexture {
+    const MAX_DIMENSION: u32 = 65536;
+    const MAX_ARRAY_SIZE: u32 = 2048;
+
+    pub fn from_file(path: &str) -> Result<Self, DDSError> {
+        let file = File::open(path)?;
+        Self::from_reader(&mut BufReader::new(file))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DDSError> {
+        Self::from_reader(&mut &bytes[..])
+    }
+
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R) -> Result<Self, DDSError> {
+        let header = DDSHeader::from_reader(reader)?;
+
+        let (format, array_size, is_cubemap, depth) = if header.has_dx10_header() {
+            let dx10 = &header.dx10_header;
+            let depth = match dx10.resource_dimension {
+                D3D10_RESOURCE_DIMENSION::TEXTURE1D | D3D10_RESOURCE_DIMENSION::TEXTURE2D => { 1 }
+                D3D10_RESOURCE_DIMENSION::TEXTURE3D => { u32::max(1, header.depth) }
+                _ => { return Err(DDSError::InvalidHeader("resource dimension must be a 1D, 2D or 3D texture")); }
+            };
+            let is_cubemap = dx10.misc_flag & DDSHeader_DXT10::DDS_RESOURCE_MISC_TEXTURECUBE != 0;
+            (DDSFormat::Dxgi(dx10.dxgi_format), u32::max(1, dx10.array_size), is_cubemap, depth)
+        } else {
+            let is_cubemap = header.caps2 & DDSHeader::DDSCAPS2_CUBEMAP != 0;
+            if is_cubemap && header.caps2 & DDSHeader::DDSCAPS2_CUBEMAP_ALLFACES != DDSHeader::DDSCAPS2_CUBEMAP_ALLFACES {
+                return Err(DDSError::UnsupportedFormat(String::from("cube map with missing faces")));
+            }
+            let depth = if header.caps2 & DDSHeader::DDSCAPS2_VOLUME != 0 { u32::max(1, header.depth) } else { 1 };
+            (DDSFormat::from_pixel_format(&header.spf)?, 1, is_cubemap, depth)
+        };
+
+        //Plenty of writers leave DDSD_MIPMAPCOUNT unset, so the count is trusted whenever it's nonzero
+        let mipmap_count = u32::max(1, header.mipmap_count);
+
+        let largest_dimension = u32::max(u32::max(header.width, header.height), depth);
+        if header.width == 0 || header.height == 0 || largest_dimension > Self::MAX_DIMENSION {
+            return Err(DDSError::InvalidHeader("image dimensions are out of range"));
+        }
+        if array_size > Self::MAX_ARRAY_SIZE {
+            return Err(DDSError::InvalidHeader("array size is out of range"));
+        }
+        if mipmap_count > routines::calculate_mipcount(largest_dimension, 1) {
+            return Err(DDSError::InvalidHeader("more mips than the image dimensions allow"));
+        }
+
+        let mut texture = DDSTexture {
+            format,
+            width: header.width,
+            height: header.height,
+            depth,
+            mipmap_count,
+            array_size,
+            is_cubemap,
+            data: Vec::new()
+        };
+
+        let data_size = texture.data_size()?;
+
+        //Reading through take() means a bogus header can't make us allocate more than the file actually holds
+        reader.take(data_size as u64).read_to_end(&mut texture.data)?;
+        if texture.data.len() != data_size {
+            return Err(DDSError::Truncated);
+        }
+
+        Ok(texture)
+    }
+
+    //Number of 2D surfaces with full mip chains in the file
+    pub fn layer_count(&self) -> u32 {
+        if self.is_cubemap {
+            self.array_size.saturating_mul(6)
+        } else {
+            self.array_size
+        }
+    }
+
+    pub fn mip_resolution(&self, level: u32) -> (u32, u32, u32) {
+        let (w, h) = routines::mip_resolution(self.width, self.height, level);
+        (w, h, u32::max(self.depth >> level, 1))
+    }
+
+    //None for formats without a simple layout, or if the size doesn't fit in a usize
+    pub fn mip_size(&self, level: u32) -> Option<usize> {
+        self.checked_mip_size(level).ok()
+    }
+
+    fn checked_mip_size(&self, level: u32) -> Result<usize, DDSError> {
+        let layout = match self.format.layout() {
+            Some(layout) => { layout }
+            None => { return Err(DDSError::UnsupportedFormat(format!("{:?}", self.format))); }
+        };
+        let (w, h, d) = self.mip_resolution(level);
+        layout.checked_surface_size(w, h, d).ok_or(DDSError::InvalidHeader("image data is too large"))
+    }
+
+    //A crafted header can describe more bytes than fit in a usize, which is an error rather than an overflow
+    fn layer_size(&self) -> Result<usize, DDSError> {
+        let mut size: usize = 0;
+        for level in 0..self.mipmap_count {
+            size = size.checked_add(self.checked_mip_size(level)?).ok_or(DDSError::InvalidHeader("image data is too large"))?;
+        }
+        Ok(size)
+    }
+
+    fn data_size(&self) -> Result<usize, DDSError> {
+        self.layer_size()?.checked_mul(self.layer_count() as usize).ok_or(DDSError::InvalidHeader("image data is too large"))
+    }
+
+    //The bytes of one mip level of one layer, or None if either is out of range
+    pub fn mip(&self, layer: u32, level: u32) -> Option<&[u8]> {
+        if layer >= self.layer_count() || level >= self.mipmap_count {
+            return None;
+        }
+
+        let mut offset = self.layer_size().ok()? * layer as usize;
+        for l in 0..level {
+            offset += self.mip_size(l)?;
+        }
+        self.data.get(offset..offset + self.mip_size(level)?)
+    }
+
+    //Every mip level of one layer, largest first
+    pub fn mips(&self, layer: u32) -> Vec<&[u8]> {
+        (0..self.mipmap_count).filter_map(|level| { self.mip(layer, level) }).collect()
+    }
+
+    pub fn header(&self) -> DDSHeader {
+        let layout = self.format.layout();
+
+        let mut flags = DDSHeader::DDSD_CAPS | DDSHeader::DDSD_HEIGHT | DDSHeader::DDSD_WIDTH | DDSHeader::DDSD_PIXELFORMAT;
+        let mut caps = DDSHeader::DDSCAPS_TEXTURE;
+        let mut caps2 = 0;
+
+        let pitch_or_linear_size = match layout {
+            Some(PixelLayout::BlockCompressed { .. }) => {
+                flags |= DDSHeader::DDSD_LINEARSIZE;
+                self.mip_size(0).unwrap_or(0) as u32
+            }
+            Some(l) => {
+                flags |= DDSHeader::DDSD_PITCH;
+                l.row_pitch(self.width)
+            }
+            None => { 0 }
+        };
+
+        if self.mipmap_count > 1 {
+            flags |= DDSHeader::DDSD_MIPMAPCOUNT;
+            caps |= DDSHeader::DDSCAPS_COMPLEX | DDSHeader::DDSCAPS_MIPMAP;
+        }
+        if self.is_cubemap {
+            caps |= DDSHeader::DDSCAPS_COMPLEX;
+            caps2 |= DDSHeader::DDSCAPS2_CUBEMAP | DDSHeader::DDSCAPS2_CUBEMAP_ALLFACES;
+        }
+        if self.depth > 1 {
+            flags |= DDSHeader::DDSD_DEPTH;
+            caps |= DDSHeader::DDSCAPS_COMPLEX;
+            caps2 |= DDSHeader::DDSCAPS2_VOLUME;
+        }
+        if self.array_size > 1 {
+            caps |= DDSHeader::DDSCAPS_COMPLEX;
+        }
+
+        let (spf, dx10_header) = match self.format {
+            DDSFormat::Dxgi(dxgi_format) => {
+                let dx10 = DDSHeader_DXT10 {
+                    dxgi_format,
+                    resource_dimension: if self.depth > 1 { D3D10_RESOURCE_DIMENSION::TEXTURE3D } else { D3D10_RESOURCE_DIMENSION::TEXTURE2D },
+                    misc_flag: if self.is_cubemap { DDSHeader_DXT10::DDS_RESOURCE_MISC_TEXTURECUBE } else { 0 },
+                    array_size: self.array_size,
+                    misc_flags2: 0
+                };
+                (DDS_PixelFormat::default(), dx10)
+            }
+            DDSFormat::Masked { rgb_bitcount, r_bitmask, g_bitmask, b_bitmask, a_bitmask } => {
+                let mut pf_flags = DDS_PixelFormat::DDPF_RGB;
+                if a_bitmask != 0 {
+                    pf_flags |= DDS_PixelFormat::DDPF_ALPHAPIXELS;
+                }
+                let spf = DDS_PixelFormat {
+                    size: DDS_PixelFormat::SIZE,
+                    flags: pf_flags,
+                    four_cc: 0,
+                    rgb_bitcount,
+                    r_bitmask,
+                    g_bitmask,
+                    b_bitmask,
+                    a_bitmask
+                };
+                (spf, DDSHeader_DXT10::default())
+            }
+        };
+
+        DDSHeader {
+            flags,
+            height: self.height,
+            width: self.width,
+            pitch_or_linear_size,
+            depth: if self.depth > 1 { self.depth } else { 0 },
+            mipmap_count: self.mipmap_count,
+            spf,
+            caps,
+            caps2,
+            dx10_header,
+            ..Default::default()
+        }
+    }
+
+    pub fn write_file(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let data_size = match self.data_size() {
+            Ok(size) => { size }
+            Err(e) => { return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't compute the size of the image data: {}", e))); }
+        };
+        if self.data.len() != data_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("expected {} bytes of image data but have {}", data_size, self.data.len())));
+        }
+        if let DDSFormat::Masked { .. } = self.format {
+            if self.array_size > 1 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "texture arrays need a DXGI format"));
+            }
+        }
+
+        self.header().write_to(writer)?;
+        writer.write_all(&self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Each layer's mips are filled with layer * 16 + level so offsets can be checked from the bytes
+    fn texture(format: DDSFormat, width: u32, height: u32, mipmap_count: u32, array_size: u32, is_cubemap: bool) -> DDSTexture {
+        let mut texture = DDSTexture { format, width, height, depth: 1, mipmap_count, array_size, is_cubemap, data: Vec::new() };
+        for layer in 0..texture.layer_count() {
+            for level in 0..mipmap_count {
+                let size = texture.mip_size(level).unwrap();
+                texture.data.extend(std::iter::repeat_n((layer * 16 + level) as u8, size));
+            }
+        }
+        texture
+    }
+
+    fn to_bytes(texture: &DDSTexture) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        texture.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn assert_same(a: &DDSTexture, b: &DDSTexture) {
+        assert_eq!(a.format, b.format);
+        assert_eq!((a.width, a.height, a.depth), (b.width, b.height, b.depth));
+        assert_eq!((a.mipmap_count, a.array_size, a.is_cubemap), (b.mipmap_count, b.array_size, b.is_cubemap));
+        assert_eq!(a.data, b.data);
+    }
+
+    //A pre-DX10 header followed by zeroed image data
+    fn legacy_bytes(spf: DDS_PixelFormat, width: u32, height: u32, data_size: usize) -> Vec<u8> {
+        let header = DDSHeader { width, height, spf, ..Default::default() };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        bytes.resize(bytes.len() + data_size, 0);
+        bytes
+    }
+
+    fn four_cc_format(code: &[u8; 4]) -> DDS_PixelFormat {
+        DDS_PixelFormat { flags: DDS_PixelFormat::DDPF_FOURCC, four_cc: four_cc(code), ..Default::default() }
+    }
+
+    fn masked_format(flags: u32, rgb_bitcount: u32, masks: [u32; 4]) -> DDS_PixelFormat {
+        DDS_PixelFormat {
+            size: DDS_PixelFormat::SIZE,
+            flags,
+            four_cc: 0,
+            rgb_bitcount,
+            r_bitmask: masks[0],
+            g_bitmask: masks[1],
+            b_bitmask: masks[2],
+            a_bitmask: masks[3]
+        }
+    }
+
+    #[test]
+    fn dx10_round_trip() {
+        let original = texture(DDSFormat::Dxgi(DXGI_FORMAT::BC7_UNORM_SRGB), 8, 8, 4, 3, false);
+        let bytes = to_bytes(&original);
+        assert_eq!(&bytes[84..88], b"DX10");
+        assert_eq!(bytes.len(), 4 + 124 + 20 + 3 * (64 + 16 + 16 + 16));
+
+        let read = DDSTexture::from_bytes(&bytes).unwrap();
+        assert_same(&read, &original);
+        assert_eq!(to_bytes(&read), bytes);
+
+        let header = DDSHeader::from_reader(&mut &bytes[..]).unwrap();
+        assert_eq!(header.dx10_header.dxgi_format, DXGI_FORMAT::BC7_UNORM_SRGB);
+        assert_eq!(header.dx10_header.array_size, 3);
+        assert_eq!(header.pitch_or_linear_size, 64);
+    }
+
+    #[test]
+    fn legacy_four_cc_formats() {
+        let cases: [(&[u8; 4], DXGI_FORMAT, usize); 3] = [
+            (b"DXT1", DXGI_FORMAT::BC1_UNORM, 8),
+            (b"DXT3", DXGI_FORMAT::BC2_UNORM, 16),
+            (b"DXT5", DXGI_FORMAT::BC3_UNORM, 16)
+        ];
+        for (code, expected, block_bytes) in cases.iter() {
+            let bytes = legacy_bytes(four_cc_format(code), 8, 4, 2 * block_bytes);
+            let texture = DDSTexture::from_bytes(&bytes).unwrap();
+            assert_eq!(texture.format, DDSFormat::Dxgi(*expected));
+            assert_eq!(texture.data.len(), 2 * block_bytes);
+        }
+
+        assert!(matches!(DDSFormat::from_pixel_format(&four_cc_format(b"ABCD")), Err(DDSError::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn legacy_bitmask_formats() {
+        use DDS_PixelFormat as PF;
+        let rgba = [0xFF, 0xFF00, 0xFF0000, 0xFF000000];
+        let bgra = [0xFF0000, 0xFF00, 0xFF, 0xFF000000];
+        let cases = [
+            (masked_format(PF::DDPF_RGB | PF::DDPF_ALPHAPIXELS, 32, rgba), DDSFormat::Dxgi(DXGI_FORMAT::R8G8B8A8_UNORM)),
+            (masked_format(PF::DDPF_RGB | PF::DDPF_ALPHAPIXELS, 32, bgra), DDSFormat::Dxgi(DXGI_FORMAT::B8G8R8A8_UNORM)),
+            //The alpha mask is ignored without DDPF_ALPHAPIXELS
+            (masked_format(PF::DDPF_RGB, 32, bgra), DDSFormat::Dxgi(DXGI_FORMAT::B8G8R8X8_UNORM)),
+            (masked_format(PF::DDPF_RGB, 16, [0xF800, 0x7E0, 0x1F, 0]), DDSFormat::Dxgi(DXGI_FORMAT::B5G6R5_UNORM)),
+            (masked_format(PF::DDPF_LUMINANCE, 8, [0xFF, 0, 0, 0]), DDSFormat::Dxgi(DXGI_FORMAT::R8_UNORM)),
+            (masked_format(PF::DDPF_ALPHA, 8, [0, 0, 0, 0xFF]), DDSFormat::Dxgi(DXGI_FORMAT::A8_UNORM)),
+            (masked_format(PF::DDPF_RGB, 24, [0xFF0000, 0xFF00, 0xFF, 0]), DDSFormat::Masked {
+                rgb_bitcount: 24,
+                r_bitmask: 0xFF0000,
+                g_bitmask: 0xFF00,
+                b_bitmask: 0xFF,
+                a_bitmask: 0
+            })
+        ];
+        for (spf, expected) in cases.iter() {
+            assert_eq!(DDSFormat::from_pixel_format(spf).unwrap(), *expected);
+        }
+
+        let texture = DDSTexture::from_bytes(&legacy_bytes(cases[6].0.clone(), 3, 2, 18)).unwrap();
+        assert_eq!(texture.format, cases[6].1);
+        assert_eq!(texture.mip_size(0), Some(18));
+
+        assert!(DDSFormat::from_pixel_format(&masked_format(PF::DDPF_RGB, 12, [0xF00, 0xF0, 0xF, 0])).is_err());
+        assert!(DDSFormat::from_pixel_format(&masked_format(PF::DDPF_YUV, 32, rgba)).is_err());
+    }
+
+    #[test]
+    fn cube_and_array_mip_offsets() {
+        for &format in [DDSFormat::Dxgi(DXGI_FORMAT::R8G8B8A8_UNORM), DDSFormat::Dxgi(DXGI_FORMAT::BC1_UNORM)].iter() {
+            let original = texture(format, 8, 8, 4, 2, true);
+            let read = DDSTexture::from_bytes(&to_bytes(&original)).unwrap();
+            assert_same(&read, &original);
+            assert_eq!(read.layer_count(), 12);
+
+            for layer in 0..12 {
+                let mips = read.mips(layer);
+                assert_eq!(mips.len(), 4);
+                for (level, mip) in mips.iter().enumerate() {
+                    assert_eq!(mip.len(), read.mip_size(level as u32).unwrap());
+                    assert!(mip.iter().all(|&b| b as u32 == layer * 16 + level as u32));
+                }
+            }
+            assert!(read.mip(12, 0).is_none());
+            assert!(read.mip(0, 4).is_none());
+        }
+
+        //Legacy cube maps are recognized from caps2
+        let original = texture(DDSFormat::Masked { rgb_bitcount: 24, r_bitmask: 0xFF0000, g_bitmask: 0xFF00, b_bitmask: 0xFF, a_bitmask: 0 }, 4, 4, 3, 1, true);
+        let bytes = to_bytes(&original);
+        assert_ne!(&bytes[84..88], b"DX10");
+        let read = DDSTexture::from_bytes(&bytes).unwrap();
+        assert_same(&read, &original);
+        assert_eq!(read.mip(5, 2), Some(&[82u8, 82, 82][..]));
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        for bytes in [
+            to_bytes(&texture(DDSFormat::Dxgi(DXGI_FORMAT::R8G8B8A8_UNORM), 4, 4, 3, 2, true)),
+            legacy_bytes(four_cc_format(b"DXT5"), 8, 8, 64)
+        ].iter() {
+            assert!(DDSTexture::from_bytes(bytes).is_ok());
+            for length in 0..bytes.len() {
+                assert!(matches!(DDSTexture::from_bytes(&bytes[..length]), Err(DDSError::Truncated)), "{} of {} bytes", length, bytes.len());
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        let header = DDSHeader {
+            width: 65536,
+            height: 65536,
+            depth: 65536,
+            mipmap_count: 17,
+            dx10_header: DDSHeader_DXT10 {
+                dxgi_format: DXGI_FORMAT::R32G32B32A32_FLOAT,
+                resource_dimension: D3D10_RESOURCE_DIMENSION::TEXTURE3D,
+                misc_flag: DDSHeader_DXT10::DDS_RESOURCE_MISC_TEXTURECUBE,
+                array_size: 2048,
+                misc_flags2: 0
+            },
+            ..Default::default()
+        };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert!(matches!(DDSTexture::from_bytes(&bytes), Err(DDSError::InvalidHeader("image data is too large"))));
+
+        let texture = DDSTexture {
+            format: DDSFormat::Dxgi(DXGI_FORMAT::R32G32B32A32_FLOAT),
+            width: 65536,
+            height: 65536,
+            depth: 65536,
+            mipmap_count: 17,
+            array_size: u32::MAX,
+            is_cubemap: true,
+            data: Vec::new()
+        };
+        assert!(texture.write_to(&mut Vec::new()).is_err());
+        assert!(texture.mip(0, 0).is_none());
+    }
+}
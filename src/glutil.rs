@@ -1,13 +1,17 @@
 use gl::types::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
-use std::str;
 use std::io;
-use std::io::Read;
-use std::fs::File;
+use std::path::{Component, Path, PathBuf};
 use std::{mem, ptr};
 use std::os::raw::c_void;
+use std::time::SystemTime;
 use image::DynamicImage;
 use crate::structs::*;
+use crate::dds::{DDSError, DDSFormat, DDSTexture, DXGI_FORMAT};
+use crate::io::{self as ozy_io, IndexType, OzyImage};
+use crate::routines;
 
 const FLOATS_PER_TRANSFORM: usize = 16;
 
@@ -69,94 +73,1304 @@ pub extern "system" fn gl_debug_callback(source: GLenum, gltype: GLenum, id: GLu
 	println!("------------------------------------------------------------");
 }
 
-pub unsafe fn compile_shader(shadertype: GLenum, source: &str) -> GLuint {
+//Human-readable name of a shader stage enum
+pub fn shader_stage_name(stage: GLenum) -> &'static str {
+	match stage {
+		gl::VERTEX_SHADER => { "vertex" }
+		gl::FRAGMENT_SHADER => { "fragment" }
+		gl::GEOMETRY_SHADER => { "geometry" }
+		gl::TESS_CONTROL_SHADER => { "tessellation control" }
+		gl::TESS_EVALUATION_SHADER => { "tessellation evaluation" }
+		gl::COMPUTE_SHADER => { "compute" }
+		_ => { "unknown" }
+	}
+}
+
+//One line of a driver's info log
+//line is None for messages the driver didn't attach a location to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderLogEntry {
+	pub source: Option<u32>,		//Index of the source string, which is what #line's second argument sets
+	pub line: Option<u32>,
+	pub file: Option<String>,		//Set when the line has been mapped back through the preprocessor
+	pub message: String
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+	Io {
+		path: String,
+		error: io::Error
+	},
+	Compile {
+		stage: GLenum,
+		path: Option<String>,
+		log: Vec<ShaderLogEntry>
+	},
+	Link {
+		paths: Vec<String>,
+		log: Vec<ShaderLogEntry>
+	},
+	Preprocess {			//Bad or unresolvable #include, including cycles
+		path: String,
+		line: u32,
+		message: String
+	}
+}
+
+impl ShaderError {
+	pub fn log(&self) -> &[ShaderLogEntry] {
+		match self {
+			ShaderError::Io { .. } | ShaderError::Preprocess { .. } => { &[] }
+			ShaderError::Compile { log, .. } | ShaderError::Link { log, .. } => { log }
+		}
+	}
+}
+
+impl std::fmt::Display for ShaderError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let location = match self {
+			ShaderError::Io { path, error } => { return write!(f, "unable to read shader \"{}\": {}", path, error); }
+			ShaderError::Preprocess { path, line, message } => { return write!(f, "{}:{}: {}", path, line, message); }
+			ShaderError::Compile { stage, path, .. } => {
+				write!(f, "{} shader failed to compile", shader_stage_name(*stage))?;
+				path.clone()
+			}
+			ShaderError::Link { paths, .. } => {
+				write!(f, "program failed to link")?;
+				if paths.is_empty() { None } else { Some(paths.join(", ")) }
+			}
+		};
+		if let Some(p) = &location {
+			write!(f, " ({})", p)?;
+		}
+		for entry in self.log() {
+			match (entry.file.as_ref().or(location.as_ref()), entry.line) {
+				(Some(p), Some(line)) => { write!(f, "\n{}:{}: {}", p, line, entry.message)?; }
+				(None, Some(line)) => { write!(f, "\nline {}: {}", line, entry.message)?; }
+				(_, None) => { write!(f, "\n{}", entry.message)?; }
+			}
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for ShaderError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			ShaderError::Io { error, .. } => { Some(error) }
+			_ => { None }
+		}
+	}
+}
+
+//Splits a driver info log into entries, pulling out the source string and line number where there is one
+//Understands the common vendor formats:
+//	NVIDIA:			0(12) : error C0000: ...
+//	Mesa:			0:12(5): error: ...
+//	AMD/Apple:		ERROR: 0:12: ...
+pub fn parse_shader_log(log: &str) -> Vec<ShaderLogEntry> {
+	let mut entries = Vec::new();
+	for raw_line in log.lines() {
+		let text = raw_line.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+		if text.is_empty() { continue; }
+
+		//Severity prefix that some drivers put before the location
+		let mut prefix = "";
+		let mut rest = text;
+		for p in ["ERROR:", "WARNING:"].iter() {
+			if let Some(r) = text.strip_prefix(p) {
+				prefix = p;
+				rest = r.trim_start();
+				break;
+			}
+		}
+
+		let entry = match parse_log_location(rest) {
+			Some((source, line, consumed)) => {
+				let message = rest[consumed..].trim_start_matches(|c: char| c.is_whitespace() || c == ':').trim();
+				let message = if prefix.is_empty() { String::from(message) } else { format!("{} {}", prefix, message) };
+				ShaderLogEntry {
+					source: Some(source),
+					line: Some(line),
+					file: None,
+					message
+				}
+			}
+			None => {
+				ShaderLogEntry {
+					source: None,
+					line: None,
+					file: None,
+					message: String::from(text)
+				}
+			}
+		};
+		entries.push(entry);
+	}
+	entries
+}
+
+//Parses "S(L)", "S:L", or "S:L(C)" off the front of s, returning (S, L, bytes consumed)
+fn parse_log_location(s: &str) -> Option<(u32, u32, usize)> {
+	fn leading_number(s: &str) -> Option<(u32, usize)> {
+		let len = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+		if len == 0 { return None; }
+		s[..len].parse().ok().map(|n| (n, len))
+	}
+
+	let (source, mut i) = leading_number(s)?;
+	let line = match s.as_bytes().get(i) {
+		Some(b'(') => {
+			let (line, len) = leading_number(&s[(i + 1)..])?;
+			i += 1 + len;
+			if s.as_bytes().get(i) != Some(&b')') { return None; }
+			i += 1;
+			line
+		}
+		Some(b':') => {
+			let (line, len) = leading_number(&s[(i + 1)..])?;
+			i += 1 + len;
+
+			//Mesa appends the column in parentheses
+			if s.as_bytes().get(i) == Some(&b'(') {
+				if let Some((_, len)) = leading_number(&s[(i + 1)..]) {
+					if s.as_bytes().get(i + 1 + len) == Some(&b')') {
+						i += len + 2;
+					}
+				}
+			}
+			line
+		}
+		_ => { return None; }
+	};
+
+	//A location is always followed by a colon, which keeps "1.0 is not supported" from parsing as one
+	let after = s[i..].trim_start();
+	if after.starts_with(':') {
+		Some((source, line, i))
+	} else {
+		None
+	}
+}
+
+unsafe fn shader_info_log(shader: GLuint) -> String {
+	let mut log_size = 0;
+	gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut log_size);
+	let mut infolog = vec![0u8; log_size.max(0) as usize];
+	if log_size > 0 {
+		gl::GetShaderInfoLog(shader, log_size, ptr::null_mut(), infolog.as_mut_ptr() as *mut GLchar);
+	}
+	String::from_utf8_lossy(&infolog).into_owned()
+}
+
+unsafe fn program_info_log(program: GLuint) -> String {
+	let mut log_size = 0;
+	gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut log_size);
+	let mut infolog = vec![0u8; log_size.max(0) as usize];
+	if log_size > 0 {
+		gl::GetProgramInfoLog(program, log_size, ptr::null_mut(), infolog.as_mut_ptr() as *mut GLchar);
+	}
+	String::from_utf8_lossy(&infolog).into_owned()
+}
+
+//GLSL source after #include resolution and define injection
+//line_origins[i] is the (index into files, line number) that output line i + 1 came from, or None for injected defines
+#[derive(Clone, Debug)]
+pub struct PreprocessedShader {
+	pub source: String,
+	pub files: Vec<String>,			//files[0] is the root file
+	line_origins: Vec<Option<(usize, u32)>>
+}
+
+impl PreprocessedShader {
+	//The original file and line that a line of the preprocessed source came from
+	pub fn origin(&self, line: u32) -> Option<(&str, u32)> {
+		let (file, original_line) = (*self.line_origins.get((line as usize).checked_sub(1)?)?)?;
+		Some((&self.files[file], original_line))
+	}
+
+	//Points the entries of a driver log at the files and lines they came from
+	pub fn map_log(&self, log: Vec<ShaderLogEntry>) -> Vec<ShaderLogEntry> {
+		log.into_iter().map(|mut entry| {
+			if let (Some(0), Some(line)) = (entry.source, entry.line) {
+				if let Some((file, original_line)) = self.origin(line) {
+					entry.file = Some(String::from(file));
+					entry.line = Some(original_line);
+				}
+			}
+			entry
+		}).collect()
+	}
+}
+
+struct Preprocessor<'a, L> {
+	loader: L,
+	defines: &'a [(&'a str, &'a str)],
+	out: PreprocessedShader,
+	stack: Vec<usize>,
+	once: Vec<usize>,				//Files that contain #pragma once
+	unreadable: Option<String>		//The file that stopped preprocessing by failing to load
+}
+
+impl<'a, L: FnMut(&str) -> io::Result<String>> Preprocessor<'a, L> {
+	fn emit(&mut self, line: &str, origin: Option<(usize, u32)>) {
+		self.out.source.push_str(line);
+		self.out.source.push('\n');
+		self.out.line_origins.push(origin);
+	}
+
+	fn emit_defines(&mut self) {
+		for (name, value) in self.defines {
+			let line = format!("#define {} {}", name, value);
+			self.emit(line.trim_end(), None);
+		}
+	}
+
+	//included_from is the (file, line) of the #include directive that pulled this file in
+	fn process(&mut self, path: String, included_from: Option<(&str, u32)>) -> Result<(), ShaderError> {
+		let existing = self.out.files.iter().position(|f| *f == path);
+		if let Some(idx) = existing {
+			//Files are pasted in every time they're included, unless they asked for #pragma once
+			if self.once.contains(&idx) { return Ok(()); }
+		}
+
+		if let Some(idx) = existing.filter(|i| self.stack.contains(i)) {
+			let (from_path, from_line) = included_from.unwrap_or((&path, 0));
+			let mut chain: Vec<&str> = self.stack.iter().skip_while(|i| **i != idx).map(|i| self.out.files[*i].as_str()).collect();
+			chain.push(&path);
+			return Err(ShaderError::Preprocess {
+				path: String::from(from_path),
+				line: from_line,
+				message: format!("include cycle: {}", chain.join(" -> "))
+			});
+		}
+
+		self.include_source(path, existing, included_from)
+	}
+
+	//existing is the file's index if it has been included before
+	fn include_source(&mut self, path: String, existing: Option<usize>, included_from: Option<(&str, u32)>) -> Result<(), ShaderError> {
+		let source = match (self.loader)(&path) {
+			Ok(s) => { s }
+			Err(error) => {
+				self.unreadable = Some(path.clone());
+				return Err(match included_from {
+					Some((from_path, from_line)) => {
+						ShaderError::Preprocess {
+							path: String::from(from_path),
+							line: from_line,
+							message: format!("unable to include \"{}\": {}", path, error)
+						}
+					}
+					None => { ShaderError::Io { path, error } }
+				});
+			}
+		};
+
+		let idx = match existing {
+			Some(idx) => { idx }
+			None => {
+				self.out.files.push(path.clone());
+				self.out.files.len() - 1
+			}
+		};
+		self.stack.push(idx);
+
+		//Defines go right after #version, which has to stay the first line, or at the very top if there isn't one
+		let is_root = included_from.is_none();
+		let mut defines_pending = is_root;
+		if is_root && !source.lines().any(|l| l.trim_start().starts_with("#version")) {
+			self.emit_defines();
+			defines_pending = false;
+		}
+
+		for (i, line) in source.lines().enumerate() {
+			let line_number = i as u32 + 1;
+			let directive = line.trim_start().strip_prefix('#').map(|d| d.trim_start());
+			if directive.and_then(|d| directive_argument(d, "pragma")) == Some("once") {
+				if !self.once.contains(&idx) {
+					self.once.push(idx);
+				}
+				continue;
+			}
+
+			if let Some(argument) = directive.and_then(|d| directive_argument(d, "include")) {
+				let name = match argument.strip_prefix('"').and_then(|a| a.split('"').next().filter(|_| a.contains('"'))) {
+					Some(n) if !n.is_empty() => { n }
+					_ => {
+						return Err(ShaderError::Preprocess {
+							path,
+							line: line_number,
+							message: format!("expected #include \"file\" but found #include {}", argument)
+						});
+					}
+				};
+
+				//Includes are relative to the including file
+				let parent = Path::new(&path).parent().unwrap_or_else(|| Path::new(""));
+				let target = normalize_path(&parent.join(name));
+				self.process(target, Some((&path, line_number)))?;
+				continue;
+			}
+
+			self.emit(line, Some((idx, line_number)));
+			if defines_pending && directive.is_some_and(|d| d.starts_with("version")) {
+				self.emit_defines();
+				defines_pending = false;
+			}
+		}
+
+		self.stack.pop();
+		Ok(())
+	}
+}
+
+//The trimmed text after a directive's keyword, e.g. "\"light.glsl\"" for include "light.glsl"
+//The keyword has to end there, so #includes isn't #include
+fn directive_argument<'d>(directive: &'d str, keyword: &str) -> Option<&'d str> {
+	let rest = directive.strip_prefix(keyword)?;
+	if rest.is_empty() || rest.starts_with(|c: char| c.is_whitespace() || c == '"') {
+		Some(rest.trim())
+	} else {
+		None
+	}
+}
+
+//Lexically resolves "." and ".." so the same file is always spelled the same way
+fn normalize_path(path: &Path) -> String {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				let can_pop = matches!(normalized.components().next_back(), Some(Component::Normal(_)));
+				if can_pop {
+					normalized.pop();
+				} else {
+					normalized.push("..");
+				}
+			}
+			c => { normalized.push(c); }
+		}
+	}
+	normalized.to_string_lossy().into_owned()
+}
+
+//Resolves #include "file.glsl" directives relative to the including file and injects defines as (name, value) pairs
+//e.g. preprocess_shader("shaders/mapped.frag", &[("INSTANCED", ""), ("PCF_TAPS", "16")])
+//Like the C preprocessor, a file is pasted in each time it's included unless it contains #pragma once. Cycles are reported as errors
+pub fn preprocess_shader(path: &str, defines: &[(&str, &str)]) -> Result<PreprocessedShader, ShaderError> {
+	preprocess_shader_with(path, defines, |p| std::fs::read_to_string(p))
+}
+
+//preprocess_shader() with the file loading passed in
+pub fn preprocess_shader_with<L: FnMut(&str) -> io::Result<String>>(path: &str, defines: &[(&str, &str)], loader: L) -> Result<PreprocessedShader, ShaderError> {
+	let mut preprocessor = Preprocessor::new(loader, defines);
+	preprocessor.process(normalize_path(Path::new(path)), None)?;
+	Ok(preprocessor.out)
+}
+
+impl<'a, L> Preprocessor<'a, L> {
+	fn new(loader: L, defines: &'a [(&'a str, &'a str)]) -> Self {
+		Preprocessor {
+			loader,
+			defines,
+			out: PreprocessedShader {
+				source: String::new(),
+				files: Vec::new(),
+				line_origins: Vec::new()
+			},
+			stack: Vec::new(),
+			once: Vec::new(),
+			unreadable: None
+		}
+	}
+}
+
+//Every file the given stages read when preprocessed, for watching them
+//When preprocessing fails the list covers what was read up to that point plus any file that failed to load,
+//so that fixing or creating it is noticed
+pub fn shader_source_files_with<L: FnMut(&str) -> io::Result<String>>(sources: &[(GLenum, &str)], mut loader: L) -> Vec<String> {
+	let mut files: Vec<String> = Vec::new();
+	for (_, path) in sources {
+		let mut preprocessor = Preprocessor::new(&mut loader, &[]);
+		let _ = preprocessor.process(normalize_path(Path::new(path)), None);
+		for file in preprocessor.out.files.into_iter().chain(preprocessor.unreadable) {
+			if !files.contains(&file) {
+				files.push(file);
+			}
+		}
+	}
+	files
+}
+
+pub fn shader_source_files(sources: &[(GLenum, &str)]) -> Vec<String> {
+	shader_source_files_with(sources, |p| std::fs::read_to_string(p))
+}
+
+//Compiles a single shader stage, deleting the shader object if compilation fails
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn compile_shader(shadertype: GLenum, source: &str) -> Result<GLuint, ShaderError> {
+	let cstr_source = match CString::new(source.as_bytes()) {
+		Ok(s) => { s }
+		Err(_) => {
+			return Err(ShaderError::Compile {
+				stage: shadertype,
+				path: None,
+				log: vec![ShaderLogEntry {
+					source: None,
+					line: None,
+					file: None,
+					message: String::from("shader source contains a nul byte")
+				}]
+			});
+		}
+	};
 	let shader = gl::CreateShader(shadertype);
-	let cstr_vert = CString::new(source.as_bytes()).unwrap();
-	gl::ShaderSource(shader, 1, &cstr_vert.as_ptr(), ptr::null());
+	gl::ShaderSource(shader, 1, &cstr_source.as_ptr(), ptr::null());
 	gl::CompileShader(shader);
 
 	//Check for errors
 	let mut success = gl::FALSE as GLint;
-	let mut log_size = 0;
 	gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
-	gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut log_size);
-	let mut infolog = Vec::with_capacity(log_size as usize);
 	if success != gl::TRUE as GLint {
-		gl::GetShaderInfoLog(shader, log_size, ptr::null_mut(), infolog.as_mut_ptr() as *mut GLchar);
-		shader_compilation_error(&infolog);
+		let log = parse_shader_log(&shader_info_log(shader));
+		gl::DeleteShader(shader);
+		return Err(ShaderError::Compile {
+			stage: shadertype,
+			path: None,
+			log
+		});
 	}
-	shader
+	Ok(shader)
 }
 
-pub unsafe fn compile_shader_from_file(shadertype: GLenum, path: &str) -> Result<GLuint, io::Error> {
-	let mut source = String::new();
-
-	match File::open(path) {
-		Ok(mut file) => {
-			file.read_to_string(&mut source).unwrap();
+//Compiles already-preprocessed source, with log lines pointing at the original files
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn compile_preprocessed_shader(shadertype: GLenum, shader: &PreprocessedShader) -> Result<GLuint, ShaderError> {
+	match compile_shader(shadertype, &shader.source) {
+		Ok(name) => { Ok(name) }
+		Err(ShaderError::Compile { stage, log, .. }) => {
+			Err(ShaderError::Compile {
+				stage,
+				path: shader.files.first().cloned(),
+				log: shader.map_log(log)
+			})
 		}
-		Err(e) => { return Err(e); }
+		Err(e) => { Err(e) }
 	}
-	Ok(compile_shader(shadertype, &source))
 }
 
-pub fn compile_program_from_files(sources: &[(GLenum, &str)]) -> Result<GLuint, String> {
+//Reads, preprocesses, and compiles a shader file
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn compile_shader_from_file(shadertype: GLenum, path: &str) -> Result<GLuint, ShaderError> {
+	compile_shader_from_file_with_defines(shadertype, path, &[])
+}
+
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn compile_shader_from_file_with_defines(shadertype: GLenum, path: &str, defines: &[(&str, &str)]) -> Result<GLuint, ShaderError> {
+	let shader = preprocess_shader(path, defines)?;
+	compile_preprocessed_shader(shadertype, &shader)
+}
+
+//Links already-compiled shaders into a program. The shaders are not deleted
+/// # Safety
+/// A GL context must be current, and every name in shaders must be a compiled shader object from it.
+pub unsafe fn link_program(shaders: &[GLuint]) -> Result<GLuint, ShaderError> {
+	let shader_progam = gl::CreateProgram();
+	for shader in shaders {
+		gl::AttachShader(shader_progam, *shader);
+	}
+	gl::LinkProgram(shader_progam);
+	for shader in shaders {
+		gl::DetachShader(shader_progam, *shader);
+	}
+
+	//Check for errors
+	let mut success = gl::FALSE as GLint;
+	gl::GetProgramiv(shader_progam, gl::LINK_STATUS, &mut success);
+	if success != gl::TRUE as GLint {
+		let log = parse_shader_log(&program_info_log(shader_progam));
+		gl::DeleteProgram(shader_progam);
+		return Err(ShaderError::Link {
+			paths: Vec::new(),
+			log
+		});
+	}
+	Ok(shader_progam)
+}
+
+pub fn compile_program_from_files(sources: &[(GLenum, &str)]) -> Result<GLuint, ShaderError> {
+	compile_program_from_files_with_defines(sources, &[])
+}
+
+//The same defines are injected into every stage
+pub fn compile_program_from_files_with_defines(sources: &[(GLenum, &str)], defines: &[(&str, &str)]) -> Result<GLuint, ShaderError> {
 	unsafe {
-		let shaders = {
-			let mut ss = Vec::with_capacity(sources.len());
-			for source in sources {
-				match compile_shader_from_file(source.0, source.1) {
-					Ok(shader) => { ss.push(shader); }
-					Err(e) => { return Err(format!("{}", e)); }
+		let mut shaders = Vec::with_capacity(sources.len());
+		for source in sources {
+			match compile_shader_from_file_with_defines(source.0, source.1, defines) {
+				Ok(shader) => { shaders.push(shader); }
+				Err(e) => {
+					for shader in &shaders {
+						gl::DeleteShader(*shader);
+					}
+					return Err(e);
 				}
 			}
-			ss
-		};
+		}
 
-		//Link shaders
-		let shader_progam = gl::CreateProgram();
+		let result = link_program(&shaders);
 		for shader in &shaders {
-			gl::AttachShader(shader_progam, *shader);
+			gl::DeleteShader(*shader);
 		}
-		gl::LinkProgram(shader_progam);
+		match result {
+			Err(ShaderError::Link { log, .. }) => {
+				Err(ShaderError::Link {
+					paths: sources.iter().map(|s| String::from(s.1)).collect(),
+					log
+				})
+			}
+			r => { r }
+		}
+	}
+}
 
-		//Check for errors
-		let mut success = gl::FALSE as GLint;
-		let mut log_size = 0;
-		gl::GetProgramiv(shader_progam, gl::LINK_STATUS, &mut success);
-		gl::GetProgramiv(shader_progam, gl::INFO_LOG_LENGTH, &mut log_size);
-		let mut infolog = vec![0; log_size as usize];
-		if success != gl::TRUE as GLint {
-			gl::GetProgramInfoLog(shader_progam, log_size, ptr::null_mut(), infolog.as_mut_ptr() as *mut GLchar);
-			return Err(shader_compilation_error(&infolog))
+//Modification time of a file, or None if it can't be read right now
+pub fn file_modified_time(path: &str) -> Option<SystemTime> {
+	std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+//Tracks the modification times of a set of files
+//Knows nothing about GL, so the time source is passed in and can be faked
+#[derive(Clone, Debug)]
+pub struct SourceWatcher {
+	paths: Vec<String>,
+	stamps: Vec<Option<SystemTime>>
+}
+
+impl SourceWatcher {
+	pub fn new<M: FnMut(&str) -> Option<SystemTime>>(paths: Vec<String>, mut mtime_of: M) -> Self {
+		let stamps = paths.iter().map(|p| mtime_of(p)).collect();
+		SourceWatcher {
+			paths,
+			stamps
 		}
+	}
 
-		for shader in &shaders {
-			gl::DeleteShader(*shader);
+	pub fn paths(&self) -> &[String] { &self.paths }
+
+	//Returns true once per change to any of the files
+	//While a file that existed has gone missing (e.g. mid-way through an editor's save-by-rename) nothing is reported,
+	//and the change is picked up on a later poll once it's back. Files that were already missing don't hold anything up
+	pub fn poll_with<M: FnMut(&str) -> Option<SystemTime>>(&mut self, mut mtime_of: M) -> bool {
+		let current: Vec<Option<SystemTime>> = self.paths.iter().map(|p| mtime_of(p)).collect();
+		let vanished = current.iter().zip(self.stamps.iter()).any(|(now, before)| now.is_none() && before.is_some());
+		if vanished || current == self.stamps {
+			return false;
 		}
-		Ok(shader_progam)
+		self.stamps = current;
+		true
+	}
+
+	pub fn poll(&mut self) -> bool {
+		self.poll_with(file_modified_time)
 	}
 }
 
-fn shader_compilation_error(infolog: &[u8]) -> String {
-	let error_message = match str::from_utf8(infolog) {
-		Ok(message) => { message }
-		Err(_) => { panic!("Error getting the shader compilation error. This statement should be unreachable."); }
-	};
-	format!("\n--------SHADER COMPILATION ERROR--------\n{}", error_message)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderReload {
+	Unchanged,
+	Reloaded,					//The replaced program has been dropped
+	Failed						//The last good program is still in use, see ShaderProgram::last_error()
+}
+
+//A program that recompiles itself when any of its source files change, #included files included
+//A failed rebuild keeps the last good program and isn't retried until one of the files changes again
+//P is the owned program, which is only something other than Program in tests
+pub struct ShaderProgram<P = Program> {
+	program: P,
+	sources: Vec<(GLenum, String)>,
+	watcher: SourceWatcher,
+	last_error: Option<ShaderError>
+}
+
+impl ShaderProgram {
+	pub fn new(sources: &[(GLenum, &str)]) -> Result<Self, ShaderError> {
+		let program = Program::from_files(sources)?;
+		Ok(Self::from_program(program, sources))
+	}
+
+	//Wraps a program that was already built from sources
+	pub fn from_program(program: Program, sources: &[(GLenum, &str)]) -> Self {
+		Self::from_program_with(program, sources, shader_source_files(sources), file_modified_time)
+	}
+
+	//Checks the source files and rebuilds the program if any of them changed, deleting the replaced program
+	//Call this once per frame or so
+	pub fn update(&mut self) -> ShaderReload {
+		self.update_with(file_modified_time, Program::from_files, shader_source_files)
+	}
+}
+
+impl<P> ShaderProgram<P> {
+	//watched is every file the program was built from, as returned by shader_source_files()
+	pub fn from_program_with<M: FnMut(&str) -> Option<SystemTime>>(program: P, sources: &[(GLenum, &str)], watched: Vec<String>, mtime_of: M) -> Self {
+		ShaderProgram {
+			program,
+			sources: sources.iter().map(|(stage, path)| (*stage, String::from(*path))).collect(),
+			watcher: SourceWatcher::new(watched, mtime_of),
+			last_error: None
+		}
+	}
+
+	//The current program, which changes whenever update() reloads
+	pub fn program(&self) -> &P { &self.program }
+
+	pub fn sources(&self) -> Vec<(GLenum, &str)> {
+		self.sources.iter().map(|(stage, path)| (*stage, path.as_str())).collect()
+	}
+
+	pub fn watched_files(&self) -> &[String] { self.watcher.paths() }
+
+	pub fn last_error(&self) -> Option<&ShaderError> { self.last_error.as_ref() }
+
+	//update() with the time source, compiler, and include scanner passed in
+	//The set of watched files is rescanned after every rebuild attempt, so added and removed #includes are tracked
+	pub fn update_with<M, C, S>(&mut self, mut mtime_of: M, compile: C, scan: S) -> ShaderReload
+	where
+		M: FnMut(&str) -> Option<SystemTime>,
+		C: FnOnce(&[(GLenum, &str)]) -> Result<P, ShaderError>,
+		S: FnOnce(&[(GLenum, &str)]) -> Vec<String>
+	{
+		if !self.watcher.poll_with(&mut mtime_of) {
+			return ShaderReload::Unchanged;
+		}
+
+		let sources = self.sources();
+		let result = compile(&sources);
+		let watched = scan(&sources);
+		self.watcher = SourceWatcher::new(watched, &mut mtime_of);
+
+		match result {
+			Ok(program) => {
+				self.program = program;
+				self.last_error = None;
+				ShaderReload::Reloaded
+			}
+			Err(e) => {
+				self.last_error = Some(e);
+				ShaderReload::Failed
+			}
+		}
+	}
 }
 
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn uniform_location(program: GLuint, name: &str) -> GLint {
 	let cstring = CString::new(name.as_bytes()).unwrap();
 	gl::GetUniformLocation(program, cstring.as_ptr())
 }
 
+//An active uniform as reported by the driver after linking
+#[derive(Clone, Debug)]
+pub struct UniformInfo {
+	pub name: String,			//Arrays are stored under their base name, i.e. "lights" rather than "lights[0]"
+	pub gl_type: GLenum,
+	pub array_size: GLint,
+	pub location: GLint
+}
+
+fn is_sampler_type(gl_type: GLenum) -> bool {
+	matches!(gl_type,
+		gl::SAMPLER_1D | gl::SAMPLER_2D | gl::SAMPLER_3D | gl::SAMPLER_CUBE |
+		gl::SAMPLER_1D_SHADOW | gl::SAMPLER_2D_SHADOW | gl::SAMPLER_CUBE_SHADOW |
+		gl::SAMPLER_1D_ARRAY | gl::SAMPLER_2D_ARRAY | gl::SAMPLER_1D_ARRAY_SHADOW | gl::SAMPLER_2D_ARRAY_SHADOW |
+		gl::SAMPLER_2D_MULTISAMPLE | gl::SAMPLER_2D_MULTISAMPLE_ARRAY | gl::SAMPLER_CUBE_MAP_ARRAY | gl::SAMPLER_CUBE_MAP_ARRAY_SHADOW |
+		gl::SAMPLER_BUFFER | gl::SAMPLER_2D_RECT | gl::SAMPLER_2D_RECT_SHADOW |
+		gl::INT_SAMPLER_2D | gl::INT_SAMPLER_3D | gl::INT_SAMPLER_CUBE | gl::INT_SAMPLER_2D_ARRAY |
+		gl::UNSIGNED_INT_SAMPLER_2D | gl::UNSIGNED_INT_SAMPLER_3D | gl::UNSIGNED_INT_SAMPLER_CUBE | gl::UNSIGNED_INT_SAMPLER_2D_ARRAY |
+		gl::IMAGE_2D | gl::IMAGE_3D | gl::IMAGE_CUBE | gl::IMAGE_2D_ARRAY
+	)
+}
+
+//Rust types that can be uploaded to a uniform
+//accepts() is what Program uses to check the value against the GLSL type
+pub trait UniformValue: Sized {
+	fn accepts(gl_type: GLenum) -> bool;
+	/// # Safety
+	/// A GL context must be current, and location must be a uniform location in program.
+	unsafe fn upload(program: GLuint, location: GLint, values: &[Self]);
+}
+
+macro_rules! impl_uniform_value {
+	($t:ty, $scalar:ty, $setter:path, $($gl_type:path)|+) => {
+		impl UniformValue for $t {
+			fn accepts(gl_type: GLenum) -> bool {
+				matches!(gl_type, $($gl_type)|+)
+			}
+
+			unsafe fn upload(program: GLuint, location: GLint, values: &[Self]) {
+				$setter(program, location, values.len() as GLsizei, values.as_ptr() as *const $scalar);
+			}
+		}
+	};
+}
+
+macro_rules! impl_uniform_matrix {
+	($t:ty, $setter:path, $gl_type:path) => {
+		impl UniformValue for $t {
+			fn accepts(gl_type: GLenum) -> bool {
+				gl_type == $gl_type
+			}
+
+			unsafe fn upload(program: GLuint, location: GLint, values: &[Self]) {
+				$setter(program, location, values.len() as GLsizei, gl::FALSE, values.as_ptr() as *const GLfloat);
+			}
+		}
+	};
+}
+
+impl_uniform_value!(f32, GLfloat, gl::ProgramUniform1fv, gl::FLOAT);
+impl_uniform_value!(glm::TVec2<f32>, GLfloat, gl::ProgramUniform2fv, gl::FLOAT_VEC2);
+impl_uniform_value!(glm::TVec3<f32>, GLfloat, gl::ProgramUniform3fv, gl::FLOAT_VEC3);
+impl_uniform_value!(glm::TVec4<f32>, GLfloat, gl::ProgramUniform4fv, gl::FLOAT_VEC4);
+impl_uniform_value!(u32, GLuint, gl::ProgramUniform1uiv, gl::UNSIGNED_INT | gl::BOOL);
+impl_uniform_value!(glm::TVec2<i32>, GLint, gl::ProgramUniform2iv, gl::INT_VEC2);
+impl_uniform_value!(glm::TVec3<i32>, GLint, gl::ProgramUniform3iv, gl::INT_VEC3);
+impl_uniform_value!(glm::TVec4<i32>, GLint, gl::ProgramUniform4iv, gl::INT_VEC4);
+impl_uniform_matrix!(glm::TMat3<f32>, gl::ProgramUniformMatrix3fv, gl::FLOAT_MAT3);
+impl_uniform_matrix!(glm::TMat4<f32>, gl::ProgramUniformMatrix4fv, gl::FLOAT_MAT4);
+
+//i32 is also how texture units are assigned to samplers
+impl UniformValue for i32 {
+	fn accepts(gl_type: GLenum) -> bool {
+		gl_type == gl::INT || gl_type == gl::BOOL || is_sampler_type(gl_type)
+	}
+
+	unsafe fn upload(program: GLuint, location: GLint, values: &[Self]) {
+		gl::ProgramUniform1iv(program, location, values.len() as GLsizei, values.as_ptr());
+	}
+}
+
+//A linked program that owns its GL name and knows its active uniforms
+//Uniforms are set with glProgramUniform*, so there's no glUseProgram or location lookup per call
+pub struct Program {
+	pub name: GLuint,
+	uniforms: HashMap<String, UniformInfo>,
+	warned: RefCell<HashSet<String>>
+}
+
+impl Program {
+	//Takes ownership of an already-linked program and introspects its uniforms
+	/// # Safety
+	/// name must be a linked program in the current GL context. It's deleted when the Program is dropped, so nothing else may delete it.
+	pub unsafe fn from_name(name: GLuint) -> Self {
+		let mut count = 0;
+		let mut max_length = 0;
+		gl::GetProgramiv(name, gl::ACTIVE_UNIFORMS, &mut count);
+		gl::GetProgramiv(name, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_length);
+
+		let mut uniforms = HashMap::with_capacity(count.max(0) as usize);
+		let mut buffer = vec![0u8; max_length.max(1) as usize];
+		for i in 0..count.max(0) as GLuint {
+			let mut length = 0;
+			let mut array_size = 0;
+			let mut gl_type = 0;
+			gl::GetActiveUniform(name, i, buffer.len() as GLsizei, &mut length, &mut array_size, &mut gl_type, buffer.as_mut_ptr() as *mut GLchar);
+			let full_name = String::from_utf8_lossy(&buffer[..length.max(0) as usize]).into_owned();
+
+			//Members of uniform blocks don't have locations
+			let location = uniform_location(name, &full_name);
+			if location < 0 { continue; }
+
+			let base_name = match full_name.strip_suffix("[0]") {
+				Some(base) => { String::from(base) }
+				None => { full_name }
+			};
+			uniforms.insert(base_name.clone(), UniformInfo {
+				name: base_name,
+				gl_type,
+				array_size,
+				location
+			});
+		}
+
+		Program {
+			name,
+			uniforms,
+			warned: RefCell::new(HashSet::new())
+		}
+	}
+
+	pub fn from_files(sources: &[(GLenum, &str)]) -> Result<Self, ShaderError> {
+		let name = compile_program_from_files(sources)?;
+		unsafe { Ok(Self::from_name(name)) }
+	}
+
+	pub fn into_raw(self) -> GLuint {
+		let name = self.name;
+		mem::forget(self);
+		name
+	}
+
+	pub fn uniform(&self, name: &str) -> Option<&UniformInfo> {
+		self.uniforms.get(name)
+	}
+
+	pub fn uniforms(&self) -> impl Iterator<Item = &UniformInfo> {
+		self.uniforms.values()
+	}
+
+	pub fn location(&self, name: &str) -> Option<GLint> {
+		self.uniform(name).map(|u| u.location)
+	}
+
+	/// # Safety
+	/// The GL context this program belongs to must be current.
+	pub unsafe fn bind(&self) {
+		gl::UseProgram(self.name);
+	}
+
+	/// # Safety
+	/// The GL context this program belongs to must be current.
+	pub unsafe fn set<T: UniformValue>(&self, name: &str, value: &T) {
+		self.set_array(name, std::slice::from_ref(value));
+	}
+
+	//Unknown names and type mismatches are reported once per name and otherwise ignored
+	//Arrays longer than the uniform are truncated
+	/// # Safety
+	/// The GL context this program belongs to must be current.
+	pub unsafe fn set_array<T: UniformValue>(&self, name: &str, values: &[T]) {
+		let uniform = match self.uniforms.get(name) {
+			Some(u) => { u }
+			None => {
+				self.warn_once(name, || format!("Program {} has no active uniform named \"{}\"", self.name, name));
+				return;
+			}
+		};
+		if !T::accepts(uniform.gl_type) {
+			self.warn_once(name, || format!("Program {}: uniform \"{}\" (GL type 0x{:X}) can't be set from a {}", self.name, name, uniform.gl_type, std::any::type_name::<T>()));
+			return;
+		}
+		if values.len() > uniform.array_size as usize {
+			self.warn_once(name, || format!("Program {}: {} values passed to uniform \"{}\" of size {}", self.name, values.len(), name, uniform.array_size));
+		}
+
+		let count = usize::min(values.len(), uniform.array_size as usize);
+		if count > 0 {
+			T::upload(self.name, uniform.location, &values[..count]);
+		}
+	}
+
+	fn warn_once<F: FnOnce() -> String>(&self, name: &str, message: F) {
+		if self.warned.borrow_mut().insert(String::from(name)) {
+			println!("{}", message());
+		}
+	}
+}
+
+impl Drop for Program {
+	fn drop(&mut self) {
+		unsafe { gl::DeleteProgram(self.name); }
+	}
+}
+
+#[inline]
+fn round_up(value: usize, alignment: usize) -> usize {
+	value.div_ceil(alignment) * alignment
+}
+
+//A value with a std140 representation
+//ALIGNMENT is the base alignment and SIZE the number of bytes write_std140() fills, including padding between matrix columns
+pub trait Std140Value {
+	const ALIGNMENT: usize;
+	const SIZE: usize;
+	fn write_std140(&self, out: &mut [u8]);
+}
+
+macro_rules! impl_std140_scalar {
+	($t:ty) => {
+		impl Std140Value for $t {
+			const ALIGNMENT: usize = 4;
+			const SIZE: usize = 4;
+			fn write_std140(&self, out: &mut [u8]) {
+				out[..4].copy_from_slice(&self.to_ne_bytes());
+			}
+		}
+	};
+}
+
+//vec3 is aligned like a vec4 but only takes up 12 bytes, so a scalar can follow it in the same 16
+macro_rules! impl_std140_vector {
+	($t:ty, $n:expr, $alignment:expr) => {
+		impl Std140Value for $t {
+			const ALIGNMENT: usize = $alignment;
+			const SIZE: usize = 4 * $n;
+			fn write_std140(&self, out: &mut [u8]) {
+				for i in 0..$n {
+					self[i].write_std140(&mut out[(4 * i)..]);
+				}
+			}
+		}
+	};
+}
+
+//Matrices are stored as arrays of column vectors, and every column is padded out to 16 bytes
+macro_rules! impl_std140_matrix {
+	($t:ty, $n:expr) => {
+		impl Std140Value for $t {
+			const ALIGNMENT: usize = 16;
+			const SIZE: usize = 16 * $n;
+			fn write_std140(&self, out: &mut [u8]) {
+				for column in 0..$n {
+					for row in 0..$n {
+						self[(row, column)].write_std140(&mut out[(16 * column + 4 * row)..]);
+					}
+				}
+			}
+		}
+	};
+}
+
+impl_std140_scalar!(f32);
+impl_std140_scalar!(i32);
+impl_std140_scalar!(u32);
+impl_std140_vector!(glm::TVec2<f32>, 2, 8);
+impl_std140_vector!(glm::TVec3<f32>, 3, 16);
+impl_std140_vector!(glm::TVec4<f32>, 4, 16);
+impl_std140_vector!(glm::TVec2<i32>, 2, 8);
+impl_std140_vector!(glm::TVec3<i32>, 3, 16);
+impl_std140_vector!(glm::TVec4<i32>, 4, 16);
+impl_std140_matrix!(glm::TMat3<f32>, 3);
+impl_std140_matrix!(glm::TMat4<f32>, 4);
+
+//GLSL bools are 4 bytes
+impl Std140Value for bool {
+	const ALIGNMENT: usize = 4;
+	const SIZE: usize = 4;
+	fn write_std140(&self, out: &mut [u8]) {
+		(*self as u32).write_std140(out);
+	}
+}
+
+//A Rust struct that mirrors a GLSL uniform block
+//The implementation pushes the fields in the same order they're declared in GLSL, e.g.
+//	impl Std140Block for FrameUniforms {
+//		fn write_std140(&self, writer: &mut Std140Writer) {
+//			writer.push(&self.view_projection).push(&self.shadow_matrix).push(&self.sun_direction);
+//		}
+//	}
+pub trait Std140Block {
+	fn write_std140(&self, writer: &mut Std140Writer);
+
+	fn to_std140_bytes(&self) -> Vec<u8> {
+		let mut writer = Std140Writer::new();
+		self.write_std140(&mut writer);
+		writer.finish()
+	}
+
+	//The offset of every pushed field, in push order
+	fn std140_offsets(&self) -> Vec<usize> {
+		let mut writer = Std140Writer::new();
+		self.write_std140(&mut writer);
+		writer.offsets
+	}
+}
+
+//Lays values out one after another according to the std140 rules, inserting padding as it goes
+#[derive(Clone, Debug, Default)]
+pub struct Std140Writer {
+	bytes: Vec<u8>,
+	offsets: Vec<usize>
+}
+
+impl Std140Writer {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn reserve(&mut self, alignment: usize, size: usize) -> usize {
+		let offset = round_up(self.bytes.len(), alignment);
+		self.bytes.resize(offset + size, 0);
+		self.offsets.push(offset);
+		offset
+	}
+
+	pub fn push<T: Std140Value>(&mut self, value: &T) -> &mut Self {
+		let offset = self.reserve(T::ALIGNMENT, T::SIZE);
+		value.write_std140(&mut self.bytes[offset..]);
+		self
+	}
+
+	//Every array element is aligned to 16 bytes, so a float[4] takes up 64 bytes
+	pub fn push_array<T: Std140Value>(&mut self, values: &[T]) -> &mut Self {
+		let stride = round_up(T::SIZE, 16);
+		let offset = self.reserve(16, stride * values.len());
+		for (i, value) in values.iter().enumerate() {
+			value.write_std140(&mut self.bytes[(offset + i * stride)..]);
+		}
+		self.bytes.resize(round_up(self.bytes.len(), 16), 0);
+		self
+	}
+
+	//Nested structs are aligned to 16 bytes and padded out to a multiple of 16
+	pub fn push_struct<T: Std140Block>(&mut self, value: &T) -> &mut Self {
+		let nested = value.to_std140_bytes();
+		let offset = self.reserve(16, nested.len());
+		self.bytes[offset..].copy_from_slice(&nested);
+		self
+	}
+
+	pub fn push_struct_array<T: Std140Block>(&mut self, values: &[T]) -> &mut Self {
+		self.reserve(16, 0);
+		for value in values {
+			self.bytes.extend_from_slice(&value.to_std140_bytes());
+		}
+		self
+	}
+
+	pub fn offsets(&self) -> &[usize] { &self.offsets }
+
+	//The finished block, padded to a multiple of 16 bytes
+	pub fn finish(mut self) -> Vec<u8> {
+		self.bytes.resize(round_up(self.bytes.len(), 16), 0);
+		self.bytes
+	}
+}
+
+//A uniform buffer object attached to a fixed binding point
+pub struct UniformBuffer {
+	pub buffer: Buffer,
+	pub binding: GLuint,
+	capacity: usize,		//Bytes allocated, which only grows
+	size: usize				//Bytes in the last block written
+}
+
+impl UniformBuffer {
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn new<T: Std140Block>(binding: GLuint, value: &T) -> Self {
+		let mut buffer = UniformBuffer {
+			buffer: Buffer::new(),
+			binding,
+			capacity: 0,
+			size: 0
+		};
+		buffer.update(value);
+		buffer
+	}
+
+	pub fn size(&self) -> usize { self.size }
+
+	pub fn capacity(&self) -> usize { self.capacity }
+
+	//Reallocates only when the block has grown
+	/// # Safety
+	/// The GL context this buffer belongs to must be current.
+	pub unsafe fn update<T: Std140Block>(&mut self, value: &T) {
+		let bytes = value.to_std140_bytes();
+		gl::BindBuffer(gl::UNIFORM_BUFFER, self.buffer.name);
+		if bytes.len() > self.capacity {
+			gl::BufferData(gl::UNIFORM_BUFFER, bytes.len() as GLsizeiptr, bytes.as_ptr() as *const c_void, gl::DYNAMIC_DRAW);
+			self.capacity = bytes.len();
+		} else {
+			gl::BufferSubData(gl::UNIFORM_BUFFER, 0, bytes.len() as GLsizeiptr, bytes.as_ptr() as *const c_void);
+		}
+		self.size = bytes.len();
+		gl::BindBufferBase(gl::UNIFORM_BUFFER, self.binding, self.buffer.name);
+	}
+
+	//Points the uniform block named block_name in each program at this buffer's binding
+	//Returns how many of the programs actually have that block
+	//A block whose size doesn't match the last update() means the Rust and GLSL declarations disagree, which gets a warning
+	/// # Safety
+	/// The GL context this buffer belongs to must be current, and every name in programs must be a linked program from it.
+	pub unsafe fn bind_to_block(&self, programs: &[GLuint], block_name: &str) -> usize {
+		let cstring = CString::new(block_name.as_bytes()).unwrap();
+		let mut bound = 0;
+		for &program in programs {
+			let index = gl::GetUniformBlockIndex(program, cstring.as_ptr());
+			if index == gl::INVALID_INDEX { continue; }
+
+			let mut block_size = 0;
+			gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut block_size);
+			if block_size as usize != self.size {
+				println!("Uniform block \"{}\" in program {} is {} bytes but the last block written to its buffer was {} bytes", block_name, program, block_size, self.size);
+			}
+
+			gl::UniformBlockBinding(program, index, self.binding);
+			bound += 1;
+		}
+		bound
+	}
+}
+
+/// # Safety
+/// A GL context must be current on the calling thread.
 pub unsafe fn gl_gen_buffer() -> GLuint {
 	let mut buffer = 0;
 	gl::GenBuffers(1, &mut buffer);
 	buffer
 }
 
+//Owning wrappers around GL object names that delete the object on drop
+//into_raw() gives up ownership for code that still wants a bare GLuint
+#[derive(Debug)]
+pub struct Buffer {
+	pub name: GLuint
+}
+
+impl Buffer {
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn new() -> Self {
+		Buffer { name: gl_gen_buffer() }
+	}
+
+	//Creates a buffer, binds it to target, and fills it with data
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn with_data<T>(target: GLenum, data: &[T], usage: GLenum) -> Self {
+		let buffer = Self::new();
+		gl::BindBuffer(target, buffer.name);
+		gl::BufferData(target, mem::size_of_val(data) as GLsizeiptr, data.as_ptr() as *const c_void, usage);
+		buffer
+	}
+
+	/// # Safety
+	/// name must be a buffer object from the current GL context. It's deleted when the Buffer is dropped, so nothing else may delete it.
+	pub unsafe fn from_raw(name: GLuint) -> Self {
+		Buffer { name }
+	}
+
+	pub fn into_raw(self) -> GLuint {
+		let name = self.name;
+		mem::forget(self);
+		name
+	}
+
+	/// # Safety
+	/// The GL context this buffer belongs to must be current.
+	pub unsafe fn bind(&self, target: GLenum) {
+		gl::BindBuffer(target, self.name);
+	}
+}
+
+impl Drop for Buffer {
+	fn drop(&mut self) {
+		unsafe { gl::DeleteBuffers(1, &self.name); }
+	}
+}
+
+//A vertex array object together with the buffers it reads from, which are deleted along with it
+#[derive(Debug)]
+pub struct VertexArray {
+	pub name: GLuint,
+	pub buffers: Vec<Buffer>
+}
+
+impl VertexArray {
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn new() -> Self {
+		let mut name = 0;
+		gl::GenVertexArrays(1, &mut name);
+		VertexArray {
+			name,
+			buffers: Vec::new()
+		}
+	}
+
+	/// # Safety
+	/// name must be a vertex array object from the current GL context and buffers the buffers it reads from. All of them are deleted on drop.
+	pub unsafe fn from_raw(name: GLuint, buffers: Vec<Buffer>) -> Self {
+		VertexArray {
+			name,
+			buffers
+		}
+	}
+
+	//Takes ownership of the names returned by create_vertex_array_object()
+	/// # Safety
+	/// names must have come from create_vertex_array_object() and not be owned by anything else.
+	pub unsafe fn from_names(names: VertexArrayNames) -> Self {
+		Self::from_raw(names.vao, vec![Buffer::from_raw(names.vbo), Buffer::from_raw(names.ebo)])
+	}
+
+	pub fn into_raw(self) -> GLuint {
+		let mut this = mem::ManuallyDrop::new(self);
+		for buffer in this.buffers.drain(..) {
+			buffer.into_raw();
+		}
+		this.name
+	}
+
+	/// # Safety
+	/// The GL context this vertex array belongs to must be current.
+	pub unsafe fn bind(&self) {
+		gl::BindVertexArray(self.name);
+	}
+}
+
+impl Drop for VertexArray {
+	fn drop(&mut self) {
+		//The buffers are deleted afterwards by their own Drop
+		unsafe { gl::DeleteVertexArrays(1, &self.name); }
+	}
+}
+
+#[derive(Debug)]
+pub struct Texture {
+	pub name: GLuint
+}
+
+impl Texture {
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn new() -> Self {
+		let mut name = 0;
+		gl::GenTextures(1, &mut name);
+		Texture { name }
+	}
+
+	/// # Safety
+	/// name must be a texture object from the current GL context. It's deleted when the Texture is dropped, so nothing else may delete it.
+	pub unsafe fn from_raw(name: GLuint) -> Self {
+		Texture { name }
+	}
+
+	//Owning version of try_load_texture()
+	pub fn from_path(path: &str, parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> Result<Self, image::ImageError> {
+		Ok(Texture { name: try_load_texture(path, parameters, color_space)? })
+	}
+
+	pub fn into_raw(self) -> GLuint {
+		let name = self.name;
+		mem::forget(self);
+		name
+	}
+
+	/// # Safety
+	/// The GL context this texture belongs to must be current.
+	pub unsafe fn bind(&self, target: GLenum) {
+		gl::BindTexture(target, self.name);
+	}
+}
+
+impl Drop for Texture {
+	fn drop(&mut self) {
+		unsafe { gl::DeleteTextures(1, &self.name); }
+	}
+}
+
 #[derive(Clone, Debug)]
 pub struct VertexArrayNames {
 	pub vao: GLuint,
@@ -166,174 +1380,491 @@ pub struct VertexArrayNames {
 
 //Input: array of vertex data, an array of indices, and an array representing the number of elements per vertex attribute
 //Output: A vertex array object with the vertex data bound as a GL_ARRAY_BUFFER, and the index data bound as a GL_ELEMENT_ARRAY_BUFFER
+//For anything other than interleaved f32 attributes and u16 indices, use create_vertex_array() with a VertexLayout
+/// # Safety
+/// A GL context must be current on the calling thread.
 pub unsafe fn create_vertex_array_object(vertices: &[f32], indices: &[u16], attribute_strides: &[i32]) -> VertexArrayNames {
-	let mut vao = 0;
-	gl::GenVertexArrays(1, &mut vao);
-	let (vbo, ebo) = {
-		let mut s = [0; 2];
-		gl::GenBuffers(2, &mut s[0]);
-		(s[0], s[1])
-	};
+	let mut layout = VertexLayout::new();
+	for (location, components) in attribute_strides.iter().enumerate() {
+		layout = layout.attribute(location as GLuint, *components, AttributeType::F32);
+	}
 
-	gl::BindVertexArray(vao);
+	let mesh = create_vertex_array(&layout, &[gpu_bytes(vertices)], IndexData::U16(indices));
+	let vertex_array = mesh.vertex_array;
+	let (vbo, ebo) = (vertex_array.buffers[0].name, vertex_array.buffers[1].name);
+	VertexArrayNames {
+		vao: vertex_array.into_raw(),
+		vbo,
+		ebo
+	}
+}
+
+//Plain-old-data types that can be copied byte-for-byte into a GL buffer
+/// # Safety
+/// Implementors must have no padding bytes and no pointers, since gpu_bytes() reads every byte of them.
+pub unsafe trait GpuData: Copy + 'static {}
+
+unsafe impl GpuData for u8 {}
+unsafe impl GpuData for i8 {}
+unsafe impl GpuData for u16 {}
+unsafe impl GpuData for i16 {}
+unsafe impl GpuData for u32 {}
+unsafe impl GpuData for i32 {}
+unsafe impl GpuData for f32 {}
+unsafe impl<T: GpuData, const N: usize> GpuData for [T; N] {}
+unsafe impl GpuData for glm::TVec2<f32> {}
+unsafe impl GpuData for glm::TVec3<f32> {}
+unsafe impl GpuData for glm::TVec4<f32> {}
+unsafe impl GpuData for glm::TMat4<f32> {}
+
+pub fn gpu_bytes<T: GpuData>(data: &[T]) -> &[u8] {
+	unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data)) }
+}
+
+//Component types a vertex attribute can be stored as
+//Normalized types are read as floats in [0, 1] (or [-1, 1] when signed) and integer types need ivec/uvec inputs in GLSL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttributeType {
+	F32,
+	F16,					//Stored as u16 bit patterns, see texcomp::f32_to_f16()
+	U8Normalized,
+	I8Normalized,
+	U16Normalized,
+	I16Normalized,
+	U8,
+	U16,
+	U32,
+	I32
+}
+
+impl AttributeType {
+	pub fn size(&self) -> usize {
+		match self {
+			AttributeType::U8Normalized | AttributeType::I8Normalized | AttributeType::U8 => { 1 }
+			AttributeType::F16 | AttributeType::U16Normalized | AttributeType::I16Normalized | AttributeType::U16 => { 2 }
+			AttributeType::F32 | AttributeType::U32 | AttributeType::I32 => { 4 }
+		}
+	}
+
+	pub fn gl_type(&self) -> GLenum {
+		match self {
+			AttributeType::F32 => { gl::FLOAT }
+			AttributeType::F16 => { gl::HALF_FLOAT }
+			AttributeType::U8Normalized | AttributeType::U8 => { gl::UNSIGNED_BYTE }
+			AttributeType::I8Normalized => { gl::BYTE }
+			AttributeType::U16Normalized | AttributeType::U16 => { gl::UNSIGNED_SHORT }
+			AttributeType::I16Normalized => { gl::SHORT }
+			AttributeType::U32 => { gl::UNSIGNED_INT }
+			AttributeType::I32 => { gl::INT }
+		}
+	}
+
+	pub fn is_normalized(&self) -> bool {
+		matches!(self, AttributeType::U8Normalized | AttributeType::I8Normalized | AttributeType::U16Normalized | AttributeType::I16Normalized)
+	}
+
+	//Integer attributes go through glVertexAttribIPointer instead of being converted to float
+	pub fn is_integer(&self) -> bool {
+		matches!(self, AttributeType::U8 | AttributeType::U16 | AttributeType::U32 | AttributeType::I32)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+	pub location: GLuint,
+	pub components: GLint,
+	pub attribute_type: AttributeType,
+	pub divisor: GLuint,			//0 for per-vertex data, n to advance once every n instances
+	pub buffer: usize,				//Which of the layout's buffers the attribute is read from
+	pub offset: usize				//Byte offset within that buffer's vertex
+}
+
+impl VertexAttribute {
+	//Size in bytes, not counting any padding after it
+	pub fn size(&self) -> usize {
+		self.components as usize * self.attribute_type.size()
+	}
+}
+
+//Describes where each vertex attribute lives, built up one attribute at a time
+//Attributes added between calls to next_buffer() are interleaved in the same buffer, so
+//	VertexLayout::new().attribute(0, 3, AttributeType::F32).attribute(1, 4, AttributeType::U8Normalized)
+//is one buffer with a 16-byte stride, and calling next_buffer() before every attribute gives fully non-interleaved data
+//Offsets and strides follow the #[repr(C)] rules, so they match a vertex struct with one array field per attribute:
+//each attribute is aligned to its component size and the stride is rounded up to the largest component size in the buffer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexLayout {
+	attributes: Vec<VertexAttribute>,
+	strides: Vec<usize>
+}
+
+impl Default for VertexLayout {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl VertexLayout {
+	pub fn new() -> Self {
+		VertexLayout {
+			attributes: Vec::new(),
+			strides: vec![0]
+		}
+	}
+
+	//One buffer per attribute, like UninterleavedVertexArrays
+	pub fn uninterleaved(attributes: &[(GLuint, GLint, AttributeType)]) -> Self {
+		let mut layout = Self::new();
+		for &(location, components, attribute_type) in attributes {
+			layout = layout.next_buffer().attribute(location, components, attribute_type);
+		}
+		layout
+	}
+
+	pub fn attribute(self, location: GLuint, components: GLint, attribute_type: AttributeType) -> Self {
+		self.instanced_attribute(location, components, attribute_type, 0)
+	}
+
+	pub fn instanced_attribute(mut self, location: GLuint, components: GLint, attribute_type: AttributeType, divisor: GLuint) -> Self {
+		assert!((1..=4).contains(&components), "vertex attributes have 1 to 4 components, not {}", components);
+		assert!(self.attributes.iter().all(|a| a.location != location), "vertex attribute location {} is used twice", location);
+
+		let buffer = self.strides.len() - 1;
+		let end = self.attributes.iter().filter(|a| a.buffer == buffer).map(|a| a.offset + a.size()).max().unwrap_or(0);
+		let offset = round_up(end, attribute_type.size());
+		self.attributes.push(VertexAttribute {
+			location,
+			components,
+			attribute_type,
+			divisor,
+			buffer,
+			offset
+		});
+		let alignment = self.attributes.iter().filter(|a| a.buffer == buffer).map(|a| a.attribute_type.size()).max().unwrap_or(1);
+		self.strides[buffer] = round_up(offset + components as usize * attribute_type.size(), alignment);
+		self
+	}
+
+	//A mat4 takes up four consecutive locations, one per column
+	pub fn matrix4_attribute(mut self, location: GLuint, divisor: GLuint) -> Self {
+		for column in 0..4 {
+			self = self.instanced_attribute(location + column, 4, AttributeType::F32, divisor);
+		}
+		self
+	}
+
+	//Following attributes go in a new buffer
+	pub fn next_buffer(mut self) -> Self {
+		if self.strides[self.strides.len() - 1] != 0 {
+			self.strides.push(0);
+		}
+		self
+	}
+
+	pub fn attributes(&self) -> &[VertexAttribute] { &self.attributes }
+
+	pub fn buffer_count(&self) -> usize {
+		self.strides.iter().filter(|s| **s != 0).count()
+	}
+
+	//Size in bytes of one vertex in the given buffer
+	pub fn stride(&self, buffer: usize) -> usize {
+		self.strides[buffer]
+	}
+
+	//Points and enables the attributes of the currently bound vertex array
+	//buffers[i] is the buffer attributes with .buffer == i are read from
+	/// # Safety
+	/// A GL context must be current with the vertex array being set up bound, and every name in buffers must be a buffer object from it.
+	pub unsafe fn apply(&self, buffers: &[GLuint]) {
+		assert!(buffers.len() >= self.buffer_count(), "layout has {} buffers but only {} were given", self.buffer_count(), buffers.len());
+		for attribute in &self.attributes {
+			let stride = self.strides[attribute.buffer] as GLsizei;
+			let offset = attribute.offset as *const c_void;
+			gl::BindBuffer(gl::ARRAY_BUFFER, buffers[attribute.buffer]);
+			if attribute.attribute_type.is_integer() {
+				gl::VertexAttribIPointer(attribute.location, attribute.components, attribute.attribute_type.gl_type(), stride, offset);
+			} else {
+				let normalized = if attribute.attribute_type.is_normalized() { gl::TRUE } else { gl::FALSE };
+				gl::VertexAttribPointer(attribute.location, attribute.components, attribute.attribute_type.gl_type(), normalized, stride, offset);
+			}
+			gl::EnableVertexAttribArray(attribute.location);
+			gl::VertexAttribDivisor(attribute.location, attribute.divisor);
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum IndexData<'a> {
+	U16(&'a [u16]),
+	U32(&'a [u32])
+}
+
+impl IndexData<'_> {
+	pub fn index_type(&self) -> IndexType {
+		match self {
+			IndexData::U16(_) => { IndexType::U16 }
+			IndexData::U32(_) => { IndexType::U32 }
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		match self {
+			IndexData::U16(indices) => { indices.len() }
+			IndexData::U32(indices) => { indices.len() }
+		}
+	}
+
+	pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+	pub fn bytes(&self) -> &[u8] {
+		match self {
+			IndexData::U16(indices) => { gpu_bytes(indices) }
+			IndexData::U32(indices) => { gpu_bytes(indices) }
+		}
+	}
+}
+
+//A vertex array with an index buffer and what's needed to draw it
+#[derive(Debug)]
+pub struct IndexedVertexArray {
+	pub vertex_array: VertexArray,		//The index buffer is the last of vertex_array.buffers
+	pub index_count: GLsizei,
+	pub index_type: IndexType
+}
+
+impl IndexedVertexArray {
+	/// # Safety
+	/// The GL context this vertex array belongs to must be current, with a program bound that reads its attributes.
+	pub unsafe fn draw(&self, mode: GLenum) {
+		self.vertex_array.bind();
+		gl::DrawElements(mode, self.index_count, self.index_type.gl_type(), ptr::null());
+	}
+
+	/// # Safety
+	/// The GL context this vertex array belongs to must be current, with a program bound that reads its attributes.
+	pub unsafe fn draw_instanced(&self, mode: GLenum, instances: GLsizei) {
+		self.vertex_array.bind();
+		gl::DrawElementsInstanced(mode, self.index_count, self.index_type.gl_type(), ptr::null(), instances);
+	}
+}
 
-	gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-	gl::BufferData(gl::ARRAY_BUFFER,
-				   (vertices.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
-				   &vertices[0] as *const f32 as *const c_void,
-				   gl::STATIC_DRAW);
-
-	gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
-	gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
-				   (indices.len() * mem::size_of::<GLushort>()) as GLsizeiptr,
-				   &indices[0] as *const u16 as *const c_void,
-				   gl::STATIC_DRAW);
-
-	//Calculate the stride in bytes between individual vertices
-	let byte_stride = {
-		let mut sum = 0;
-		for stride in attribute_strides {
-			sum += stride;
-		}
-		sum * mem::size_of::<GLfloat>() as i32
+//Creates a vertex array with one GL_ARRAY_BUFFER per layout buffer, filled from buffers in the same order
+//The new vertex array is left bound
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn create_vertex_array(layout: &VertexLayout, buffers: &[&[u8]], indices: IndexData) -> IndexedVertexArray {
+	assert_eq!(buffers.len(), layout.buffer_count(), "layout expects {} buffers", layout.buffer_count());
+	let mut vertex_array = VertexArray::new();
+	vertex_array.bind();
+
+	for data in buffers {
+		vertex_array.buffers.push(Buffer::with_data(gl::ARRAY_BUFFER, data, gl::STATIC_DRAW));
+	}
+	let names: Vec<GLuint> = vertex_array.buffers.iter().map(|b| b.name).collect();
+	layout.apply(&names);
+
+	vertex_array.buffers.push(Buffer::with_data(gl::ELEMENT_ARRAY_BUFFER, indices.bytes(), gl::STATIC_DRAW));
+
+	IndexedVertexArray {
+		vertex_array,
+		index_count: indices.len() as GLsizei,
+		index_type: indices.index_type()
+	}
+}
+
+//Images that can't be loaded become a placeholder. Use try_load_texture() to find out why
+pub fn load_texture(path: &str, parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> GLuint {
+	let image_data = image_data_from_path(path, color_space).unwrap_or_else(|_| { ImageData::placeholder() });
+	unsafe { load_texture_from_data(image_data, parameters) }
+}
+
+//load_texture() that leaves what to do about a missing or broken image up to the caller
+pub fn try_load_texture(path: &str, parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> Result<GLuint, image::ImageError> {
+	let image_data = image_data_from_path(path, color_space)?;
+	unsafe { Ok(load_texture_from_data(image_data, parameters)) }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+	Linear,
+	Gamma
+}
+
+//Decodes any image format the image crate understands, including .hdr and .exr
+pub fn image_data_from_path(path: &str, space: ColorSpace) -> Result<ImageData, image::ImageError> {
+	Ok(image_data_from_image(image::open(path)?, space))
+}
+
+//8-bit images are uploaded as-is, 16-bit images natively except when sRGB is requested (there's no 16-bit sRGB format),
+//and float images always as linear 32-bit float
+//Single-channel and luma-alpha images end up in the red and red-green channels
+pub fn image_data_from_image(image: DynamicImage, space: ColorSpace) -> ImageData {
+	let gamma = space == ColorSpace::Gamma;
+	let width = image.width() as GLint;
+	let height = image.height() as GLint;
+	let (data, format, internal_format, data_type) = match image {
+		DynamicImage::ImageLuma8(im) => { (im.into_raw(), gl::RED, gl::R8, gl::UNSIGNED_BYTE) }
+		DynamicImage::ImageLumaA8(im) => { (im.into_raw(), gl::RG, gl::RG8, gl::UNSIGNED_BYTE) }
+		DynamicImage::ImageRgb8(im) => {
+			let internal_format = if gamma { gl::SRGB8 } else { gl::RGB8 };
+			(im.into_raw(), gl::RGB, internal_format, gl::UNSIGNED_BYTE)
+		}
+		DynamicImage::ImageRgba8(im) => {
+			let internal_format = if gamma { gl::SRGB8_ALPHA8 } else { gl::RGBA8 };
+			(im.into_raw(), gl::RGBA, internal_format, gl::UNSIGNED_BYTE)
+		}
+		DynamicImage::ImageLuma16(im) => { (gpu_bytes(&im.into_raw()).to_vec(), gl::RED, gl::R16, gl::UNSIGNED_SHORT) }
+		DynamicImage::ImageLumaA16(im) => { (gpu_bytes(&im.into_raw()).to_vec(), gl::RG, gl::RG16, gl::UNSIGNED_SHORT) }
+		DynamicImage::ImageRgb16(im) => {
+			if gamma {
+				(DynamicImage::ImageRgb16(im).to_rgb8().into_raw(), gl::RGB, gl::SRGB8, gl::UNSIGNED_BYTE)
+			} else {
+				(gpu_bytes(&im.into_raw()).to_vec(), gl::RGB, gl::RGB16, gl::UNSIGNED_SHORT)
+			}
+		}
+		DynamicImage::ImageRgba16(im) => {
+			if gamma {
+				(DynamicImage::ImageRgba16(im).to_rgba8().into_raw(), gl::RGBA, gl::SRGB8_ALPHA8, gl::UNSIGNED_BYTE)
+			} else {
+				(gpu_bytes(&im.into_raw()).to_vec(), gl::RGBA, gl::RGBA16, gl::UNSIGNED_SHORT)
+			}
+		}
+		DynamicImage::ImageRgb32F(im) => { (gpu_bytes(&im.into_raw()).to_vec(), gl::RGB, gl::RGB32F, gl::FLOAT) }
+		DynamicImage::ImageRgba32F(im) => { (gpu_bytes(&im.into_raw()).to_vec(), gl::RGBA, gl::RGBA32F, gl::FLOAT) }
+		other => {
+			let internal_format = if gamma { gl::SRGB8_ALPHA8 } else { gl::RGBA8 };
+			(other.to_rgba8().into_raw(), gl::RGBA, internal_format, gl::UNSIGNED_BYTE)
+		}
 	};
 
-	//Configure and enable the vertex attributes
-	let mut cumulative_size = 0;
-	for i in 0..attribute_strides.len() {
-		gl::VertexAttribPointer(i as GLuint,
-								attribute_strides[i],
-								gl::FLOAT,
-								gl::FALSE,
-								byte_stride,
-								(cumulative_size * mem::size_of::<GLfloat>() as GLuint) as *const c_void);
-		
-		gl::EnableVertexAttribArray(i as GLuint);
-		cumulative_size += attribute_strides[i] as u32;
+	ImageData {
+		data,
+		width,
+		height,
+		format,
+		internal_format,
+		data_type
+	}
+}
+
+//Per-instance data that knows its own vertex layout, starting at a given attribute location
+//Implement this for a #[repr(C)] struct to get e.g. a color and a uv offset per instance
+pub trait InstanceData: GpuData {
+	fn layout(location: GLuint) -> VertexLayout;
+}
+
+impl InstanceData for f32 {
+	fn layout(location: GLuint) -> VertexLayout {
+		VertexLayout::new().instanced_attribute(location, 1, AttributeType::F32, 1)
 	}
+}
 
-	VertexArrayNames {
-		vao,
-		vbo,
-		ebo
+impl InstanceData for glm::TVec2<f32> {
+	fn layout(location: GLuint) -> VertexLayout {
+		VertexLayout::new().instanced_attribute(location, 2, AttributeType::F32, 1)
 	}
 }
 
-pub fn load_texture(path: &str, parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> GLuint {
-	unsafe {
-		load_texture_from_data(image_data_from_path(path, color_space), parameters)
+impl InstanceData for glm::TVec3<f32> {
+	fn layout(location: GLuint) -> VertexLayout {
+		VertexLayout::new().instanced_attribute(location, 3, AttributeType::F32, 1)
 	}
 }
 
-pub enum ColorSpace {
-	Linear,
-	Gamma
+impl InstanceData for glm::TVec4<f32> {
+	fn layout(location: GLuint) -> VertexLayout {
+		VertexLayout::new().instanced_attribute(location, 4, AttributeType::F32, 1)
+	}
 }
 
-pub fn image_data_from_path(path: &str, space: ColorSpace) -> ImageData {
-	match image::open(path) {
-		Ok(DynamicImage::ImageRgb8(im)) => {
-			let internal_format = match space {
-				ColorSpace::Linear => { gl::RGB8 }
-				ColorSpace::Gamma => { gl::SRGB8 }
-			};
+impl InstanceData for glm::TMat4<f32> {
+	fn layout(location: GLuint) -> VertexLayout {
+		VertexLayout::new().matrix4_attribute(location, 1)
+	}
+}
 
-			let width = im.width();
-			let height = im.height();
-			let raw = im.into_raw();
-			ImageData {
-				data: raw,
-				width: width as GLint,
-				height: height as GLint,
-				format: gl::RGB,
-				internal_format
-			}
-		}
-		Ok(DynamicImage::ImageRgba8(im)) => {
-			let internal_format = match space {
-				ColorSpace::Linear => { gl::RGBA8 }
-				ColorSpace::Gamma => { gl::SRGB8_ALPHA8 }
-			};
+//A GL_ARRAY_BUFFER of per-instance attributes
+//update() orphans the old storage so the driver never has to wait on draws still reading it,
+//and the buffer name never changes when it grows, so vertex arrays it's attached to stay valid
+pub struct InstanceBuffer<T: GpuData> {
+	pub buffer: Buffer,
+	layout: VertexLayout,
+	capacity: usize,
+	count: usize,
+	_data: std::marker::PhantomData<T>
+}
 
-			let width = im.width();
-			let height = im.height();
-			let raw = im.into_raw();
-			ImageData {
-				data: raw,
-				width: width as GLint,
-				height: height as GLint,
-				format: gl::RGBA,
-				internal_format
-			}
-		}
-		Ok(DynamicImage::ImageRgba16(im)) => {
-			let internal_format = match space {
-				ColorSpace::Linear => { gl::RGBA8 }
-				ColorSpace::Gamma => { gl::SRGB8_ALPHA8 }
-			};
+impl<T: InstanceData> InstanceBuffer<T> {
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn new(location: GLuint, capacity: usize) -> Self {
+		Self::with_layout(T::layout(location), capacity)
+	}
+}
 
-			let width = im.width();
-			let height = im.height();
-			let raw = im.into_raw();
-			let mut rawu8 = vec![0u8; raw.len()];
-			for i in 0..raw.len() {
-				rawu8[i] = raw[i] as u8;
-			}
+impl<T: GpuData> InstanceBuffer<T> {
+	//layout must describe a single buffer whose stride is the size of T
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn with_layout(layout: VertexLayout, capacity: usize) -> Self {
+		assert_eq!(layout.buffer_count(), 1, "instance layouts describe exactly one buffer");
+		assert_eq!(layout.stride(0), mem::size_of::<T>(), "instance layout stride doesn't match the size of {}", std::any::type_name::<T>());
 
-			ImageData {
-				data: rawu8,
-				width: width as GLint,
-				height: height as GLint,
-				format: gl::RGBA,
-				internal_format
-			}
+		let buffer = Buffer::new();
+		buffer.bind(gl::ARRAY_BUFFER);
+		gl::BufferData(gl::ARRAY_BUFFER, (capacity * mem::size_of::<T>()) as GLsizeiptr, ptr::null(), gl::STREAM_DRAW);
+		InstanceBuffer {
+			buffer,
+			layout,
+			capacity,
+			count: 0,
+			_data: std::marker::PhantomData
 		}
-		Ok(DynamicImage::ImageLuma8(im)) => {
-			let width = im.width();
-			let height = im.height();
-			let raw = im.into_raw();
+	}
 
-			ImageData {
-				data: raw,
-				width: width as GLint,
-				height: height as GLint,
-				format: gl::RED,
-				internal_format: gl::R8
-			}
-		}
-		Ok(_) => {
-            println!("{} is of an unsupported image type", path);
-            ImageData {
-                data: vec![0u8],
-                width: 1,
-                height: 1,
-                format: gl::RGBA,
-                internal_format: gl::SRGB8_ALPHA8
-            }
+	pub fn capacity(&self) -> usize { self.capacity }
+
+	//Number of instances written by the last update()
+	pub fn count(&self) -> usize { self.count }
+
+	pub fn layout(&self) -> &VertexLayout { &self.layout }
+
+	//Binds vao and points the instance attributes at this buffer
+	/// # Safety
+	/// The GL context this buffer belongs to must be current, and vao must be a vertex array object from it.
+	pub unsafe fn attach(&self, vao: GLuint) {
+		gl::BindVertexArray(vao);
+		self.layout.apply(&[self.buffer.name]);
+	}
+
+	//Replaces the buffer's contents, growing it to at least double its size if data doesn't fit
+	/// # Safety
+	/// The GL context this buffer belongs to must be current.
+	pub unsafe fn update(&mut self, data: &[T]) {
+		self.buffer.bind(gl::ARRAY_BUFFER);
+		if data.len() > self.capacity {
+			self.capacity = usize::max(data.len(), 2 * self.capacity);
 		}
-		Err(e) => {
-			println!("Unable to open {}: {}", path, e);
-            ImageData {
-                data: vec![0u8],
-                width: 1,
-                height: 1,
-                format: gl::RGBA,
-                internal_format: gl::SRGB8_ALPHA8
-            }
+
+		let capacity_bytes = (self.capacity * mem::size_of::<T>()) as GLsizeiptr;
+		gl::BufferData(gl::ARRAY_BUFFER, capacity_bytes, ptr::null(), gl::STREAM_DRAW);
+		if !data.is_empty() {
+			gl::BufferSubData(gl::ARRAY_BUFFER, 0, mem::size_of_val(data) as GLsizeiptr, data.as_ptr() as *const c_void);
 		}
+		self.count = data.len();
 	}
 }
 
 //Create and attaches an instanced array buffer of 4x4 homogenous matrices of size max_instances to vao at instanced_attribute
-//Returns the name of the new buffer
-pub unsafe fn create_instanced_transform_buffer(vao: GLuint, max_instances: usize, instanced_attribute: GLuint) -> GLuint {
+//The buffer is deleted when the returned Buffer is dropped, so keep it alive as long as the vao, e.g. by pushing it onto a VertexArray
+/// # Safety
+/// A GL context must be current, and vao must be a vertex array object from it.
+pub unsafe fn create_instanced_transform_buffer(vao: GLuint, max_instances: usize, instanced_attribute: GLuint) -> Buffer {
 	gl::BindVertexArray(vao);
 
 	let data = vec![0.0f32; max_instances * FLOATS_PER_TRANSFORM];
-	let mut b = 0;
-	gl::GenBuffers(1, &mut b);
-	gl::BindBuffer(gl::ARRAY_BUFFER, b);
-	gl::BufferData(gl::ARRAY_BUFFER, (max_instances * FLOATS_PER_TRANSFORM * mem::size_of::<GLfloat>()) as GLsizeiptr, &data[0] as *const f32 as *const c_void, gl::DYNAMIC_DRAW);
+	let b = Buffer::with_data(gl::ARRAY_BUFFER, &data, gl::DYNAMIC_DRAW);
 
 	//Attach this buffer to the shell_mesh vao
 	//We have to individually bind each column of the matrix as a different vec4 vertex attribute
@@ -342,6 +1873,8 @@ pub unsafe fn create_instanced_transform_buffer(vao: GLuint, max_instances: usiz
 	b
 }
 
+/// # Safety
+/// A GL context must be current with the vertex array to add the buffer to bound.
 pub unsafe fn bind_new_transform_buffer(instanced_attribute: GLuint) {	
 	for i in 0..4 {
 		let attribute_index = instanced_attribute + i;
@@ -357,12 +1890,16 @@ pub unsafe fn bind_new_transform_buffer(instanced_attribute: GLuint) {
 }
 
 //Apllies the list of parameters to the current bound 2D texture
+/// # Safety
+/// A GL context must be current with a texture bound to target.
 pub unsafe fn apply_texture_parameters(target: GLuint, parameters: &[(GLenum, GLenum)]) {
 	for param in parameters {
 		gl::TexParameteri(target, param.0, param.1 as GLint);
 	}
 }
 
+/// # Safety
+/// A GL context must be current on the calling thread.
 pub unsafe fn load_texture_from_data(image_data: ImageData, parameters: &[(GLenum, GLenum)]) -> GLuint {
 	//Create texture
 	let mut tex = 0;
@@ -373,6 +1910,8 @@ pub unsafe fn load_texture_from_data(image_data: ImageData, parameters: &[(GLenu
 	apply_texture_parameters(gl::TEXTURE_2D, parameters);
 
 	//Upload texture data
+	//Rows of RGB8 and single-channel images aren't necessarily 4-byte aligned
+	gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
 	gl::TexImage2D(gl::TEXTURE_2D,
 				   0,
 				   image_data.internal_format as i32,
@@ -380,48 +1919,982 @@ pub unsafe fn load_texture_from_data(image_data: ImageData, parameters: &[(GLenu
 				   image_data.height,
 				   0,
 				   image_data.format,
-				   gl::UNSIGNED_BYTE,
-				   &image_data.data[0] as *const u8 as *const c_void);
+				   image_data.data_type,
+				   image_data.data.as_ptr() as *const c_void);
+	gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
 	gl::GenerateMipmap(gl::TEXTURE_2D);	//Generate mipmaps
 	tex
 }
 
+//Uploads every mip level of a BC7-compressed OzyImage into an immutable texture
+//Images whose bc7_bytes don't hold the whole mip chain are rejected before anything is created
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn load_ozy_image(image: &OzyImage, parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> Result<Texture, TextureError> {
+	let internal_format = match color_space {
+		ColorSpace::Linear => { gl::COMPRESSED_RGBA_BPTC_UNORM }
+		ColorSpace::Gamma => { gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM }
+	};
+	let mipmap_count = u32::max(image.mipmap_count, 1);
+	let expected = ozy_io::bc7_mip_chain_size(image.width, image.height, mipmap_count);
+	if image.bc7_bytes.len() < expected {
+		return Err(TextureError::Truncated { expected, found: image.bc7_bytes.len() });
+	}
+
+	let tex = Texture::new();
+	tex.bind(gl::TEXTURE_2D);
+	apply_texture_parameters(gl::TEXTURE_2D, parameters);
+	gl::TexStorage2D(gl::TEXTURE_2D, mipmap_count as GLsizei, internal_format, image.width as GLsizei, image.height as GLsizei);
+
+	let mut offset = 0;
+	for level in 0..mipmap_count {
+		let (w, h) = routines::mip_resolution(image.width, image.height, level);
+		let size = ispc::bc7::calc_output_size(w, h);
+		let data = &image.bc7_bytes[offset..(offset + size)];
+		gl::CompressedTexSubImage2D(gl::TEXTURE_2D,
+									level as GLint,
+									0,
+									0,
+									w as GLsizei,
+									h as GLsizei,
+									internal_format,
+									size as GLsizei,
+									data.as_ptr() as *const c_void);
+		offset += size;
+	}
+	Ok(tex)
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+	Image(image::ImageError),
+	Dds(DDSError),
+	SizeMismatch {			//Every face of a cube map or layer of an array has to match the first one
+		index: usize,
+		expected: (GLint, GLint),
+		found: (GLint, GLint)
+	},
+	FormatMismatch {
+		index: usize
+	},
+	NotSquare(GLint, GLint),
+	NotCubemap,
+	NoLayers,
+	UnsupportedFormat(String),
+	Truncated {				//Compressed data that's too short for the mip chain it claims to have
+		expected: usize,
+		found: usize
+	}
+}
+
+impl std::fmt::Display for TextureError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			TextureError::Image(e) => { write!(f, "{}", e) }
+			TextureError::Dds(e) => { write!(f, "{}", e) }
+			TextureError::SizeMismatch { index, expected, found } => {
+				write!(f, "image {} is {}x{} but the first image is {}x{}", index, found.0, found.1, expected.0, expected.1)
+			}
+			TextureError::FormatMismatch { index } => { write!(f, "image {} has a different pixel format than the first image", index) }
+			TextureError::NotSquare(width, height) => { write!(f, "cube map faces must be square but are {}x{}", width, height) }
+			TextureError::NotCubemap => { write!(f, "the DDS file is not a cube map") }
+			TextureError::NoLayers => { write!(f, "no images were given") }
+			TextureError::UnsupportedFormat(format) => { write!(f, "unsupported texture format: {}", format) }
+			TextureError::Truncated { expected, found } => { write!(f, "expected {} bytes of texture data but only found {}", expected, found) }
+		}
+	}
+}
+
+impl std::error::Error for TextureError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			TextureError::Image(e) => { Some(e) }
+			TextureError::Dds(e) => { Some(e) }
+			_ => { None }
+		}
+	}
+}
+
+impl From<image::ImageError> for TextureError {
+	fn from(e: image::ImageError) -> Self {
+		TextureError::Image(e)
+	}
+}
+
+impl From<DDSError> for TextureError {
+	fn from(e: DDSError) -> Self {
+		TextureError::Dds(e)
+	}
+}
+
+//S3TC enums come from EXT_texture_compression_s3tc and EXT_texture_sRGB, so the gl crate doesn't have them
+pub const COMPRESSED_RGBA_S3TC_DXT1_EXT: GLenum = 0x83F1;
+pub const COMPRESSED_RGBA_S3TC_DXT3_EXT: GLenum = 0x83F2;
+pub const COMPRESSED_RGBA_S3TC_DXT5_EXT: GLenum = 0x83F3;
+pub const COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT: GLenum = 0x8C4D;
+pub const COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT: GLenum = 0x8C4E;
+pub const COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT: GLenum = 0x8C4F;
+
+//How a DXGI format is uploaded to GL. format and data_type are unused for compressed formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlTextureFormat {
+	pub internal_format: GLenum,
+	pub format: GLenum,
+	pub data_type: GLenum,
+	pub compressed: bool
+}
+
+pub fn gl_format_for_dxgi(format: DXGI_FORMAT) -> Option<GlTextureFormat> {
+	let compressed = |internal_format| {
+		Some(GlTextureFormat { internal_format, format: 0, data_type: 0, compressed: true })
+	};
+	let uncompressed = |internal_format, format, data_type| {
+		Some(GlTextureFormat { internal_format, format, data_type, compressed: false })
+	};
+
+	match format {
+		DXGI_FORMAT::BC1_UNORM => { compressed(COMPRESSED_RGBA_S3TC_DXT1_EXT) }
+		DXGI_FORMAT::BC1_UNORM_SRGB => { compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT) }
+		DXGI_FORMAT::BC2_UNORM => { compressed(COMPRESSED_RGBA_S3TC_DXT3_EXT) }
+		DXGI_FORMAT::BC2_UNORM_SRGB => { compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT) }
+		DXGI_FORMAT::BC3_UNORM => { compressed(COMPRESSED_RGBA_S3TC_DXT5_EXT) }
+		DXGI_FORMAT::BC3_UNORM_SRGB => { compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT) }
+		DXGI_FORMAT::BC4_UNORM => { compressed(gl::COMPRESSED_RED_RGTC1) }
+		DXGI_FORMAT::BC4_SNORM => { compressed(gl::COMPRESSED_SIGNED_RED_RGTC1) }
+		DXGI_FORMAT::BC5_UNORM => { compressed(gl::COMPRESSED_RG_RGTC2) }
+		DXGI_FORMAT::BC5_SNORM => { compressed(gl::COMPRESSED_SIGNED_RG_RGTC2) }
+		DXGI_FORMAT::BC6H_UF16 => { compressed(gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT) }
+		DXGI_FORMAT::BC6H_SF16 => { compressed(gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT) }
+		DXGI_FORMAT::BC7_UNORM => { compressed(gl::COMPRESSED_RGBA_BPTC_UNORM) }
+		DXGI_FORMAT::BC7_UNORM_SRGB => { compressed(gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM) }
+		DXGI_FORMAT::R8_UNORM => { uncompressed(gl::R8, gl::RED, gl::UNSIGNED_BYTE) }
+		DXGI_FORMAT::R8G8_UNORM => { uncompressed(gl::RG8, gl::RG, gl::UNSIGNED_BYTE) }
+		DXGI_FORMAT::R8G8B8A8_UNORM => { uncompressed(gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE) }
+		DXGI_FORMAT::R8G8B8A8_UNORM_SRGB => { uncompressed(gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE) }
+		DXGI_FORMAT::B8G8R8A8_UNORM => { uncompressed(gl::RGBA8, gl::BGRA, gl::UNSIGNED_BYTE) }
+		DXGI_FORMAT::B8G8R8A8_UNORM_SRGB => { uncompressed(gl::SRGB8_ALPHA8, gl::BGRA, gl::UNSIGNED_BYTE) }
+		DXGI_FORMAT::B8G8R8X8_UNORM => { uncompressed(gl::RGB8, gl::BGRA, gl::UNSIGNED_BYTE) }
+		DXGI_FORMAT::R10G10B10A2_UNORM => { uncompressed(gl::RGB10_A2, gl::RGBA, gl::UNSIGNED_INT_2_10_10_10_REV) }
+		DXGI_FORMAT::R11G11B10_FLOAT => { uncompressed(gl::R11F_G11F_B10F, gl::RGB, gl::UNSIGNED_INT_10F_11F_11F_REV) }
+		DXGI_FORMAT::R16_FLOAT => { uncompressed(gl::R16F, gl::RED, gl::HALF_FLOAT) }
+		DXGI_FORMAT::R16G16_FLOAT => { uncompressed(gl::RG16F, gl::RG, gl::HALF_FLOAT) }
+		DXGI_FORMAT::R16G16B16A16_FLOAT => { uncompressed(gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT) }
+		DXGI_FORMAT::R16G16B16A16_UNORM => { uncompressed(gl::RGBA16, gl::RGBA, gl::UNSIGNED_SHORT) }
+		DXGI_FORMAT::R32_FLOAT => { uncompressed(gl::R32F, gl::RED, gl::FLOAT) }
+		DXGI_FORMAT::R32G32B32_FLOAT => { uncompressed(gl::RGB32F, gl::RGB, gl::FLOAT) }
+		DXGI_FORMAT::R32G32B32A32_FLOAT => { uncompressed(gl::RGBA32F, gl::RGBA, gl::FLOAT) }
+		_ => { None }
+	}
+}
+
+//Uploads one 2D surface (or one layer of an array when layer is Some) of already-allocated storage
+unsafe fn texture_sub_image(target: GLenum, level: GLint, width: u32, height: u32, layer: Option<u32>, format: &GlTextureFormat, data: &[u8]) {
+	let (w, h) = (width as GLsizei, height as GLsizei);
+	let pixels = data.as_ptr() as *const c_void;
+	match (layer, format.compressed) {
+		(None, true) => { gl::CompressedTexSubImage2D(target, level, 0, 0, w, h, format.internal_format, data.len() as GLsizei, pixels); }
+		(None, false) => { gl::TexSubImage2D(target, level, 0, 0, w, h, format.format, format.data_type, pixels); }
+		(Some(z), true) => { gl::CompressedTexSubImage3D(target, level, 0, 0, z as GLint, w, h, 1, format.internal_format, data.len() as GLsizei, pixels); }
+		(Some(z), false) => { gl::TexSubImage3D(target, level, 0, 0, z as GLint, w, h, 1, format.format, format.data_type, pixels); }
+	}
+}
+
+//Checks that every image matches the first one's size and format
+fn check_images_match(images: &[ImageData]) -> Result<&ImageData, TextureError> {
+	let first = images.first().ok_or(TextureError::NoLayers)?;
+	for (index, image) in images.iter().enumerate() {
+		if (image.width, image.height) != (first.width, first.height) {
+			return Err(TextureError::SizeMismatch {
+				index,
+				expected: (first.width, first.height),
+				found: (image.width, image.height)
+			});
+		}
+		if (image.format, image.internal_format, image.data_type) != (first.format, first.internal_format, first.data_type) {
+			return Err(TextureError::FormatMismatch { index });
+		}
+	}
+	Ok(first)
+}
+
+fn image_data_format(image: &ImageData) -> GlTextureFormat {
+	GlTextureFormat {
+		internal_format: image.internal_format,
+		format: image.format,
+		data_type: image.data_type,
+		compressed: false
+	}
+}
+
+//Creates a cube map with a full mip chain from faces in the order +X, -X, +Y, -Y, +Z, -Z
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn load_cubemap_from_data(faces: &[ImageData; 6], parameters: &[(GLenum, GLenum)]) -> Result<Texture, TextureError> {
+	let first = check_images_match(faces)?;
+	if first.width != first.height {
+		return Err(TextureError::NotSquare(first.width, first.height));
+	}
+	let format = image_data_format(first);
+	let size = first.width as u32;
+
+	let tex = Texture::new();
+	tex.bind(gl::TEXTURE_CUBE_MAP);
+	apply_texture_parameters(gl::TEXTURE_CUBE_MAP, parameters);
+	gl::TexStorage2D(gl::TEXTURE_CUBE_MAP, routines::calculate_mipcount(size, size) as GLsizei, format.internal_format, size as GLsizei, size as GLsizei);
+
+	gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+	for (i, face) in faces.iter().enumerate() {
+		texture_sub_image(gl::TEXTURE_CUBE_MAP_POSITIVE_X + i as GLenum, 0, size, size, None, &format, &face.data);
+	}
+	gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+	gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+	Ok(tex)
+}
+
+pub fn load_cubemap_from_faces(paths: &[&str; 6], parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> Result<Texture, TextureError> {
+	let load = |i: usize| { image_data_from_path(paths[i], color_space) };
+	let faces = [load(0)?, load(1)?, load(2)?, load(3)?, load(4)?, load(5)?];
+	unsafe { load_cubemap_from_data(&faces, parameters) }
+}
+
+//Direction through the center of texel (x, y) of a cube map face, following the face orientations in the GL spec
+fn cube_face_direction(face: usize, x: u32, y: u32, size: u32) -> glm::TVec3<f32> {
+	let sc = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+	let tc = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+	let direction = match face {
+		0 => { glm::vec3(1.0, -tc, -sc) }
+		1 => { glm::vec3(-1.0, -tc, sc) }
+		2 => { glm::vec3(sc, 1.0, tc) }
+		3 => { glm::vec3(sc, -1.0, -tc) }
+		4 => { glm::vec3(sc, -tc, 1.0) }
+		_ => { glm::vec3(-sc, -tc, -1.0) }
+	};
+	glm::normalize(&direction)
+}
+
+//Resamples an equirectangular (latitude/longitude) image into six RGB32F cube map faces
+//The engine is z-up, so the top row of the image is +Z and the horizon runs around the XY plane, with the center of the image at +X
+pub fn equirectangular_to_cubemap_faces(image: &image::Rgb32FImage, face_size: u32) -> [ImageData; 6] {
+	let (width, height) = image.dimensions();
+	let texel = |x: i64, y: i64| {
+		let x = x.rem_euclid(width as i64) as u32;
+		let y = y.clamp(0, height as i64 - 1) as u32;
+		image.get_pixel(x, y).0
+	};
+
+	let face = |face: usize| {
+		let mut data = Vec::with_capacity((face_size * face_size * 3) as usize);
+		for y in 0..face_size {
+			for x in 0..face_size {
+				let dir = cube_face_direction(face, x, y, face_size);
+				let u = 0.5 + f32::atan2(dir.y, dir.x) / (2.0 * std::f32::consts::PI);
+				let v = f32::acos(dir.z.clamp(-1.0, 1.0)) / std::f32::consts::PI;
+
+				//Bilinear filtering, wrapping horizontally
+				let fx = u * width as f32 - 0.5;
+				let fy = v * height as f32 - 0.5;
+				let (x0, y0) = (fx.floor() as i64, fy.floor() as i64);
+				let (tx, ty) = (fx - fx.floor(), fy - fy.floor());
+				let (a, b, c, d) = (texel(x0, y0), texel(x0 + 1, y0), texel(x0, y0 + 1), texel(x0 + 1, y0 + 1));
+				for channel in 0..3 {
+					let top = a[channel] * (1.0 - tx) + b[channel] * tx;
+					let bottom = c[channel] * (1.0 - tx) + d[channel] * tx;
+					data.push(top * (1.0 - ty) + bottom * ty);
+				}
+			}
+		}
+
+		ImageData {
+			data: gpu_bytes(&data).to_vec(),
+			width: face_size as GLint,
+			height: face_size as GLint,
+			format: gl::RGB,
+			internal_format: gl::RGB32F,
+			data_type: gl::FLOAT
+		}
+	};
+	[face(0), face(1), face(2), face(3), face(4), face(5)]
+}
+
+//Loads an equirectangular image, usually an .hdr, as a cube map with faces of face_size x face_size
+pub fn load_cubemap_from_equirectangular(path: &str, face_size: u32, parameters: &[(GLenum, GLenum)]) -> Result<Texture, TextureError> {
+	let image = image::open(path)?.to_rgb32f();
+	let faces = equirectangular_to_cubemap_faces(&image, face_size);
+	unsafe { load_cubemap_from_data(&faces, parameters) }
+}
+
+//Uploads a DDS file as whichever of TEXTURE_2D, TEXTURE_2D_ARRAY, or TEXTURE_CUBE_MAP it describes, with all of its mips
+//Returns the texture and its target. Only the first cube of a cube map array is used
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn load_dds_texture(dds: &DDSTexture, parameters: &[(GLenum, GLenum)]) -> Result<(Texture, GLenum), TextureError> {
+	let format = match dds.format {
+		DDSFormat::Dxgi(f) => { gl_format_for_dxgi(f).ok_or_else(|| TextureError::UnsupportedFormat(format!("{:?}", f)))? }
+		DDSFormat::Masked { .. } => { return Err(TextureError::UnsupportedFormat(String::from("uncommon legacy bitmask format"))); }
+	};
+	if dds.depth > 1 {
+		return Err(TextureError::UnsupportedFormat(String::from("volume texture")));
+	}
+	if dds.is_cubemap && dds.width != dds.height {
+		return Err(TextureError::NotSquare(dds.width as GLint, dds.height as GLint));
+	}
+
+	let target = if dds.is_cubemap {
+		gl::TEXTURE_CUBE_MAP
+	} else if dds.array_size > 1 {
+		gl::TEXTURE_2D_ARRAY
+	} else {
+		gl::TEXTURE_2D
+	};
+	let levels = u32::max(dds.mipmap_count, 1);
+	let (w, h) = (dds.width as GLsizei, dds.height as GLsizei);
+
+	let tex = Texture::new();
+	tex.bind(target);
+	apply_texture_parameters(target, parameters);
+	if target == gl::TEXTURE_2D_ARRAY {
+		gl::TexStorage3D(target, levels as GLsizei, format.internal_format, w, h, dds.array_size as GLsizei);
+	} else {
+		gl::TexStorage2D(target, levels as GLsizei, format.internal_format, w, h);
+	}
+
+	let layers = if dds.is_cubemap { 6 } else { dds.array_size };
+	gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+	for layer in 0..layers {
+		for level in 0..levels {
+			let data = match dds.mip(layer, level) {
+				Some(d) => { d }
+				None => {
+					gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+					return Err(TextureError::Dds(DDSError::Truncated));
+				}
+			};
+			let (mip_w, mip_h, _) = dds.mip_resolution(level);
+			match target {
+				gl::TEXTURE_CUBE_MAP => { texture_sub_image(gl::TEXTURE_CUBE_MAP_POSITIVE_X + layer, level as GLint, mip_w, mip_h, None, &format, data); }
+				gl::TEXTURE_2D_ARRAY => { texture_sub_image(target, level as GLint, mip_w, mip_h, Some(layer), &format, data); }
+				_ => { texture_sub_image(target, level as GLint, mip_w, mip_h, None, &format, data); }
+			}
+		}
+	}
+	gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+	Ok((tex, target))
+}
+
+pub fn load_cubemap_from_dds(path: &str, parameters: &[(GLenum, GLenum)]) -> Result<Texture, TextureError> {
+	let dds = DDSTexture::from_file(path)?;
+	if !dds.is_cubemap {
+		return Err(TextureError::NotCubemap);
+	}
+	unsafe { Ok(load_dds_texture(&dds, parameters)?.0) }
+}
+
+//Creates a TEXTURE_2D_ARRAY with a full mip chain from same-sized images, e.g. the layers of a terrain splat map
+/// # Safety
+/// A GL context must be current on the calling thread.
+pub unsafe fn load_texture_array_from_data(layers: &[ImageData], parameters: &[(GLenum, GLenum)]) -> Result<Texture, TextureError> {
+	let first = check_images_match(layers)?;
+	let format = image_data_format(first);
+	let (width, height) = (first.width as u32, first.height as u32);
+
+	let tex = Texture::new();
+	tex.bind(gl::TEXTURE_2D_ARRAY);
+	apply_texture_parameters(gl::TEXTURE_2D_ARRAY, parameters);
+	gl::TexStorage3D(
+		gl::TEXTURE_2D_ARRAY,
+		routines::calculate_mipcount(width, height) as GLsizei,
+		format.internal_format,
+		width as GLsizei,
+		height as GLsizei,
+		layers.len() as GLsizei
+	);
+
+	gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+	for (i, layer) in layers.iter().enumerate() {
+		texture_sub_image(gl::TEXTURE_2D_ARRAY, 0, width, height, Some(i as u32), &format, &layer.data);
+	}
+	gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+	gl::GenerateMipmap(gl::TEXTURE_2D_ARRAY);
+	Ok(tex)
+}
+
+pub fn load_texture_array(paths: &[&str], parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> Result<Texture, TextureError> {
+	let mut layers = Vec::with_capacity(paths.len());
+	for path in paths {
+		layers.push(image_data_from_path(path, color_space)?);
+	}
+	unsafe { load_texture_array_from_data(&layers, parameters) }
+}
+
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn bind_matrix4(program: GLuint, name: &str, matrix: &glm::TMat4<f32>) {
 	gl::UseProgram(program);
 	gl::UniformMatrix4fv(uniform_location(program, name), 1, gl::FALSE, &glm::value_ptr(matrix)[0]);
 }
 
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn bind_matrix4_array(program: GLuint, name: &str, matrices: &[glm::TMat4<f32>]) {
 	gl::UseProgram(program);
 	gl::UniformMatrix4fv(uniform_location(program, name), matrices.len() as GLint, gl::FALSE, &glm::value_ptr(&matrices[0])[0]);
 }
 
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn bind_vector4(program: GLuint, name: &str, vector: &glm::TVec4<f32>) {
 	gl::UseProgram(program);
 	gl::Uniform4fv(uniform_location(program, name), 1, &[vector.x, vector.y, vector.z, vector.w] as *const GLfloat);
 }
 
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn bind_vector3(program: GLuint, name: &str, vector: &glm::TVec3<f32>) {
 	gl::UseProgram(program);
 	gl::Uniform3fv(uniform_location(program, name), 1, &[vector.x, vector.y, vector.z] as *const GLfloat);
 }
 
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn bind_vector2(program: GLuint, name: &str, vector: &glm::TVec2<f32>) {
 	gl::UseProgram(program);
 	gl::Uniform2fv(uniform_location(program, name), 1, &[vector.x, vector.y] as *const GLfloat);
 }
 
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn bind_int(program: GLuint, name: &str, number: GLint) {
 	gl::UseProgram(program);
 	gl::Uniform1i(uniform_location(program, name), number);
 }
 
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn bind_float(program: GLuint, name: &str, number: GLfloat) {
 	gl::UseProgram(program);
 	gl::Uniform1f(uniform_location(program, name), number);
 }
 
+/// # Safety
+/// A GL context must be current, and program must be a linked program from it.
 pub unsafe fn bind_float_array(program: GLuint, name: &str, array: &[f32]) {
 	gl::UseProgram(program);
 	gl::Uniform1fv(uniform_location(program, name), array.len() as GLint, &array[0] as *const GLfloat);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::rc::Rc;
+	use std::time::Duration;
+
+	//Files as (path, contents, modification time in seconds)
+	struct FakeFiles {
+		files: HashMap<String, (String, u64)>
+	}
+
+	impl FakeFiles {
+		fn new(files: &[(&str, &str)]) -> Self {
+			FakeFiles { files: files.iter().map(|(p, s)| (String::from(*p), (String::from(*s), 1))).collect() }
+		}
+
+		fn touch(&mut self, path: &str) {
+			self.files.get_mut(path).unwrap().1 += 1;
+		}
+
+		fn write(&mut self, path: &str, contents: &str) {
+			let stamp = self.files.get(path).map_or(1, |f| f.1 + 1);
+			self.files.insert(String::from(path), (String::from(contents), stamp));
+		}
+
+		fn mtime(&self, path: &str) -> Option<SystemTime> {
+			self.files.get(path).map(|f| SystemTime::UNIX_EPOCH + Duration::from_secs(f.1))
+		}
+
+		fn load(&self, path: &str) -> io::Result<String> {
+			match self.files.get(path) {
+				Some(f) => { Ok(f.0.clone()) }
+				None => { Err(io::Error::new(io::ErrorKind::NotFound, "missing")) }
+			}
+		}
+
+		fn scan(&self, sources: &[(GLenum, &str)]) -> Vec<String> {
+			shader_source_files_with(sources, |p| self.load(p))
+		}
+	}
+
+	const SOURCES: [(GLenum, &str); 2] = [(gl::VERTEX_SHADER, "shaders/a.vert"), (gl::FRAGMENT_SHADER, "shaders/a.frag")];
+
+	fn fake_files() -> FakeFiles {
+		FakeFiles::new(&[
+			("shaders/a.vert", "#version 330 core\nvoid main() {}"),
+			("shaders/a.frag", "#version 330 core\n#include \"lib/light.glsl\"\nvoid main() {}"),
+			("shaders/lib/light.glsl", "float light() { return 1.0; }")
+		])
+	}
+
+	fn fake_error() -> ShaderError {
+		ShaderError::Preprocess { path: String::from("shaders/a.frag"), line: 1, message: String::from("broken") }
+	}
+
+	#[test]
+	fn watched_files_include_includes() {
+		let files = fake_files();
+		assert_eq!(files.scan(&SOURCES), vec!["shaders/a.vert", "shaders/a.frag", "shaders/lib/light.glsl"]);
+	}
+
+	#[test]
+	fn watched_files_include_a_missing_include() {
+		let mut files = fake_files();
+		files.write("shaders/a.frag", "#include \"lib/missing.glsl\"");
+		assert_eq!(files.scan(&SOURCES), vec!["shaders/a.vert", "shaders/a.frag", "shaders/lib/missing.glsl"]);
+	}
+
+	#[test]
+	fn source_watcher_reports_each_change_once() {
+		let mut files = fake_files();
+		let mut watcher = SourceWatcher::new(vec![String::from("shaders/a.vert")], |p| files.mtime(p));
+		assert!(!watcher.poll_with(|p| files.mtime(p)));
+		files.touch("shaders/a.vert");
+		assert!(watcher.poll_with(|p| files.mtime(p)));
+		assert!(!watcher.poll_with(|p| files.mtime(p)));
+	}
+
+	#[test]
+	fn source_watcher_waits_for_missing_files() {
+		let mut files = fake_files();
+		let mut watcher = SourceWatcher::new(vec![String::from("shaders/a.vert")], |p| files.mtime(p));
+		let saved = files.files.remove("shaders/a.vert").unwrap();
+		assert!(!watcher.poll_with(|p| files.mtime(p)));
+		files.files.insert(String::from("shaders/a.vert"), (saved.0, saved.1 + 1));
+		assert!(watcher.poll_with(|p| files.mtime(p)));
+	}
+
+	#[test]
+	fn editing_an_include_reloads() {
+		let mut files = fake_files();
+		let mut program = ShaderProgram::from_program_with(1, &SOURCES, files.scan(&SOURCES), |p| files.mtime(p));
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| -> Result<GLuint, ShaderError> { panic!("nothing changed") }, |s| files.scan(s)), ShaderReload::Unchanged);
+
+		files.touch("shaders/lib/light.glsl");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Ok(2), |s| files.scan(s)), ShaderReload::Reloaded);
+		assert_eq!(*program.program(), 2);
+	}
+
+	#[test]
+	fn reloading_drops_the_replaced_program() {
+		let mut files = fake_files();
+		let first = Rc::new(1);
+		let mut program = ShaderProgram::from_program_with(first.clone(), &SOURCES, files.scan(&SOURCES), |p| files.mtime(p));
+
+		files.touch("shaders/a.frag");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Err(fake_error()), |s| files.scan(s)), ShaderReload::Failed);
+		assert_eq!(Rc::strong_count(&first), 2);
+
+		files.touch("shaders/a.frag");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Ok(Rc::new(2)), |s| files.scan(s)), ShaderReload::Reloaded);
+		assert_eq!(Rc::strong_count(&first), 1);
+		assert_eq!(**program.program(), 2);
+	}
+
+	#[test]
+	fn new_includes_are_watched_after_a_reload() {
+		let mut files = fake_files();
+		let mut program = ShaderProgram::from_program_with(1, &SOURCES, files.scan(&SOURCES), |p| files.mtime(p));
+
+		files.write("shaders/shadow.glsl", "float shadow() { return 0.0; }");
+		files.write("shaders/a.vert", "#include \"shadow.glsl\"\nvoid main() {}");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Ok(2), |s| files.scan(s)), ShaderReload::Reloaded);
+		assert!(program.watched_files().contains(&String::from("shaders/shadow.glsl")));
+
+		files.touch("shaders/shadow.glsl");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Ok(3), |s| files.scan(s)), ShaderReload::Reloaded);
+
+		//Dropped includes stop being watched
+		files.write("shaders/a.vert", "void main() {}");
+		program.update_with(|p| files.mtime(p), |_| Ok(4), |s| files.scan(s));
+		assert!(!program.watched_files().contains(&String::from("shaders/shadow.glsl")));
+	}
+
+	#[test]
+	fn failed_rebuilds_keep_the_old_program_until_the_next_change() {
+		let mut files = fake_files();
+		let mut program = ShaderProgram::from_program_with(1, &SOURCES, files.scan(&SOURCES), |p| files.mtime(p));
+
+		files.touch("shaders/a.frag");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Err(fake_error()), |s| files.scan(s)), ShaderReload::Failed);
+		assert_eq!(*program.program(), 1);
+		assert!(program.last_error().is_some());
+
+		//No retry without a change
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| -> Result<GLuint, ShaderError> { panic!("retried without a change") }, |s| files.scan(s)), ShaderReload::Unchanged);
+
+		files.touch("shaders/lib/light.glsl");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Ok(2), |s| files.scan(s)), ShaderReload::Reloaded);
+		assert!(program.last_error().is_none());
+	}
+
+	#[test]
+	fn creating_a_missing_include_retries() {
+		let mut files = fake_files();
+		let mut program = ShaderProgram::from_program_with(1, &SOURCES, files.scan(&SOURCES), |p| files.mtime(p));
+
+		files.write("shaders/a.frag", "#include \"lib/fog.glsl\"\nvoid main() {}");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Err(fake_error()), |s| files.scan(s)), ShaderReload::Failed);
+		assert!(program.watched_files().contains(&String::from("shaders/lib/fog.glsl")));
+
+		files.write("shaders/lib/fog.glsl", "float fog() { return 0.0; }");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Ok(2), |s| files.scan(s)), ShaderReload::Reloaded);
+	}
+
+	#[test]
+	fn fixing_a_mistyped_include_retries() {
+		let mut files = fake_files();
+		let mut program = ShaderProgram::from_program_with(1, &SOURCES, files.scan(&SOURCES), |p| files.mtime(p));
+
+		files.write("shaders/a.frag", "#include \"lib/lihgt.glsl\"\nvoid main() {}");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Err(fake_error()), |s| files.scan(s)), ShaderReload::Failed);
+
+		files.write("shaders/a.frag", "#include \"lib/light.glsl\"\nvoid main() {}");
+		assert_eq!(program.update_with(|p| files.mtime(p), |_| Ok(2), |s| files.scan(s)), ShaderReload::Reloaded);
+		assert!(!program.watched_files().contains(&String::from("shaders/lib/lihgt.glsl")));
+	}
+
+	fn preprocess(files: &[(&str, &str)], root: &str) -> Result<PreprocessedShader, ShaderError> {
+		let files = FakeFiles::new(files);
+		preprocess_shader_with(root, &[], |p| files.load(p))
+	}
+
+	#[test]
+	fn repeated_includes_are_pasted_each_time() {
+		let shader = preprocess(&[
+			("a.glsl", "#include \"b.glsl\"\n#include \"b.glsl\""),
+			("b.glsl", "float b;")
+		], "a.glsl").unwrap();
+		assert_eq!(shader.source.lines().collect::<Vec<_>>(), vec!["float b;", "float b;"]);
+		assert_eq!(shader.files, vec!["a.glsl", "b.glsl"]);
+		assert_eq!(shader.origin(2), Some(("b.glsl", 1)));
+	}
+
+	#[test]
+	fn pragma_once_includes_a_file_once() {
+		let shader = preprocess(&[
+			("a.glsl", "#include \"b.glsl\"\n#include \"c.glsl\"\nvoid main() {}"),
+			("b.glsl", "#include \"common.glsl\"\nfloat b;"),
+			("c.glsl", "#include \"common.glsl\"\nfloat c;"),
+			("common.glsl", "#pragma once\nfloat common;")
+		], "a.glsl").unwrap();
+		assert_eq!(shader.source.lines().collect::<Vec<_>>(), vec!["float common;", "float b;", "float c;", "void main() {}"]);
+		assert_eq!(shader.origin(1), Some(("common.glsl", 2)));
+		assert_eq!(shader.origin(4), Some(("a.glsl", 3)));
+	}
+
+	#[test]
+	fn include_keyword_must_end() {
+		let shader = preprocess(&[("a.glsl", "#includefoo \"b.glsl\"\n#include\"b.glsl\""), ("b.glsl", "float b;")], "a.glsl").unwrap();
+		assert_eq!(shader.source.lines().collect::<Vec<_>>(), vec!["#includefoo \"b.glsl\"", "float b;"]);
+
+		let shader = preprocess(&[("a.glsl", "#pragma once_only\n#pragma   once")], "a.glsl").unwrap();
+		assert_eq!(shader.source.lines().collect::<Vec<_>>(), vec!["#pragma once_only"]);
+	}
+
+	#[test]
+	fn include_cycles_are_errors() {
+		let result = preprocess(&[
+			("a.glsl", "#include \"b.glsl\""),
+			("b.glsl", "#pragma once\n#include \"a.glsl\"")
+		], "a.glsl");
+		match result {
+			Err(ShaderError::Preprocess { path, line, message }) => {
+				assert_eq!((path.as_str(), line), ("b.glsl", 2));
+				assert_eq!(message, "include cycle: a.glsl -> b.glsl -> a.glsl");
+			}
+			_ => { panic!("expected an include cycle error"); }
+		}
+	}
+
+	struct Light {
+		position: glm::TVec3<f32>,
+		intensity: f32,
+		color: glm::TVec2<f32>
+	}
+
+	impl Std140Block for Light {
+		fn write_std140(&self, writer: &mut Std140Writer) {
+			writer.push(&self.position).push(&self.intensity).push(&self.color);
+		}
+	}
+
+	fn floats(bytes: &[u8]) -> Vec<f32> {
+		bytes.chunks(4).map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]])).collect()
+	}
+
+	#[test]
+	fn std140_scalar_packs_after_vec3() {
+		let mut writer = Std140Writer::new();
+		writer.push(&glm::vec3(1.0f32, 2.0, 3.0)).push(&4.0f32).push(&5.0f32);
+		assert_eq!(writer.offsets(), &[0, 12, 16]);
+		assert_eq!(floats(&writer.finish()), vec![1.0, 2.0, 3.0, 4.0, 5.0, 0.0, 0.0, 0.0]);
+	}
+
+	#[test]
+	fn std140_array_elements_have_a_16_byte_stride() {
+		let mut writer = Std140Writer::new();
+		writer.push(&1.0f32).push_array(&[2.0f32, 3.0]).push(&4.0f32);
+		assert_eq!(writer.offsets(), &[0, 16, 48]);
+		assert_eq!(floats(&writer.finish()), vec![
+			1.0, 0.0, 0.0, 0.0,
+			2.0, 0.0, 0.0, 0.0,
+			3.0, 0.0, 0.0, 0.0,
+			4.0, 0.0, 0.0, 0.0
+		]);
+
+		let mut writer = Std140Writer::new();
+		writer.push_array(&[glm::vec2(1.0f32, 2.0), glm::vec2(3.0, 4.0)]);
+		assert_eq!(floats(&writer.finish()), vec![1.0, 2.0, 0.0, 0.0, 3.0, 4.0, 0.0, 0.0]);
+	}
+
+	#[test]
+	fn std140_mat3_columns_are_padded() {
+		let matrix = glm::mat3(
+			1.0f32, 2.0, 3.0,
+			4.0, 5.0, 6.0,
+			7.0, 8.0, 9.0
+		);
+		let mut writer = Std140Writer::new();
+		writer.push(&0.5f32).push(&matrix).push(&10.0f32);
+		assert_eq!(writer.offsets(), &[0, 16, 64]);
+		assert_eq!(floats(&writer.finish()), vec![
+			0.5, 0.0, 0.0, 0.0,
+			1.0, 4.0, 7.0, 0.0,
+			2.0, 5.0, 8.0, 0.0,
+			3.0, 6.0, 9.0, 0.0,
+			10.0, 0.0, 0.0, 0.0
+		]);
+	}
+
+	#[test]
+	fn std140_nested_structs_are_aligned_to_16() {
+		let light = Light { position: glm::vec3(1.0, 2.0, 3.0), intensity: 4.0, color: glm::vec2(5.0, 6.0) };
+		assert_eq!(light.std140_offsets(), vec![0, 12, 16]);
+		assert_eq!(light.to_std140_bytes().len(), 32);
+
+		let mut writer = Std140Writer::new();
+		writer.push(&7.0f32).push_struct(&light).push(&8.0f32);
+		assert_eq!(writer.offsets(), &[0, 16, 48]);
+		assert_eq!(floats(&writer.finish()), vec![
+			7.0, 0.0, 0.0, 0.0,
+			1.0, 2.0, 3.0, 4.0,
+			5.0, 6.0, 0.0, 0.0,
+			8.0, 0.0, 0.0, 0.0
+		]);
+
+		let mut writer = Std140Writer::new();
+		writer.push(&7.0f32).push_struct_array(&[light, Light { position: glm::zero(), intensity: 9.0, color: glm::zero() }]);
+		let bytes = writer.finish();
+		assert_eq!(bytes.len(), 80);
+		assert_eq!(floats(&bytes)[4..8], [1.0, 2.0, 3.0, 4.0]);
+		assert_eq!(floats(&bytes)[12..16], [0.0, 0.0, 0.0, 9.0]);
+	}
+
+	#[test]
+	fn std140_finish_rounds_up_to_16() {
+		assert_eq!(Std140Writer::new().finish().len(), 0);
+
+		let mut writer = Std140Writer::new();
+		writer.push(&1i32);
+		assert_eq!(writer.finish().len(), 16);
+
+		let mut writer = Std140Writer::new();
+		writer.push(&glm::vec4(1.0f32, 2.0, 3.0, 4.0)).push(&true);
+		assert_eq!(writer.finish().len(), 32);
+	}
+
+	fn offsets(layout: &VertexLayout) -> Vec<usize> {
+		layout.attributes().iter().map(|a| a.offset).collect()
+	}
+
+	#[test]
+	fn vertex_layout_matches_repr_c() {
+		#[repr(C)]
+		struct Packed {
+			position: [f32; 3],
+			color: [u8; 3],
+			flags: u8
+		}
+		let layout = VertexLayout::new()
+			.attribute(0, 3, AttributeType::F32)
+			.attribute(1, 3, AttributeType::U8Normalized)
+			.attribute(2, 1, AttributeType::U8);
+		assert_eq!(offsets(&layout), vec![mem::offset_of!(Packed, position), mem::offset_of!(Packed, color), mem::offset_of!(Packed, flags)]);
+		assert_eq!(layout.stride(0), mem::size_of::<Packed>());
+
+		#[repr(C)]
+		struct Mixed {
+			id: u16,
+			color: [u8; 3],
+			weight: f32,
+			uv: [u16; 2]
+		}
+		let layout = VertexLayout::new()
+			.attribute(0, 1, AttributeType::U16)
+			.attribute(1, 3, AttributeType::U8Normalized)
+			.attribute(2, 1, AttributeType::F32)
+			.attribute(3, 2, AttributeType::F16);
+		assert_eq!(offsets(&layout), vec![mem::offset_of!(Mixed, id), mem::offset_of!(Mixed, color), mem::offset_of!(Mixed, weight), mem::offset_of!(Mixed, uv)]);
+		assert_eq!(layout.stride(0), mem::size_of::<Mixed>());
+	}
+
+	#[test]
+	fn vertex_layout_small_attributes_are_not_padded() {
+		let layout = VertexLayout::new().attribute(0, 1, AttributeType::U8);
+		assert_eq!(layout.stride(0), mem::size_of::<u8>());
+
+		let layout = VertexLayout::new().attribute(0, 1, AttributeType::U16).attribute(1, 1, AttributeType::U8);
+		assert_eq!(offsets(&layout), vec![0, 2]);
+		assert_eq!(layout.stride(0), mem::size_of::<(u16, u8)>());
+	}
+
+	#[test]
+	fn vertex_layout_buffers_are_laid_out_separately() {
+		let layout = VertexLayout::new()
+			.attribute(0, 3, AttributeType::F32)
+			.attribute(1, 4, AttributeType::U8Normalized)
+			.next_buffer()
+			.attribute(2, 1, AttributeType::U8)
+			.next_buffer()
+			.matrix4_attribute(5, 1);
+		assert_eq!(layout.buffer_count(), 3);
+		assert_eq!(offsets(&layout), vec![0, 12, 0, 0, 16, 32, 48]);
+		assert_eq!((layout.stride(0), layout.stride(1), layout.stride(2)), (16, 1, mem::size_of::<glm::TMat4<f32>>()));
+		assert!(layout.attributes()[3..].iter().all(|a| a.buffer == 2 && a.divisor == 1));
+
+		let layout = VertexLayout::uninterleaved(&[(0, 3, AttributeType::F32), (1, 2, AttributeType::U16Normalized)]);
+		assert_eq!(offsets(&layout), vec![0, 0]);
+		assert_eq!((layout.stride(0), layout.stride(1)), (12, 4));
+	}
+
+	#[test]
+	fn cube_face_centers_point_down_their_axes() {
+		let axes = [
+			glm::vec3(1.0, 0.0, 0.0),
+			glm::vec3(-1.0, 0.0, 0.0),
+			glm::vec3(0.0, 1.0, 0.0),
+			glm::vec3(0.0, -1.0, 0.0),
+			glm::vec3(0.0, 0.0, 1.0),
+			glm::vec3(0.0, 0.0, -1.0)
+		];
+		for (face, axis) in axes.iter().enumerate() {
+			assert!(glm::distance(&cube_face_direction(face, 2, 2, 5), axis) < 1.0e-6, "face {}", face);
+
+			//Every texel of a face is closer to its own axis than to any other
+			for (x, y) in [(0, 0), (4, 0), (0, 4), (4, 4), (1, 3)].iter() {
+				let dir = cube_face_direction(face, *x, *y, 5);
+				assert!((glm::length(&dir) - 1.0).abs() < 1.0e-6);
+				let closest = (0..6).max_by(|a, b| { glm::dot(&dir, &axes[*a]).partial_cmp(&glm::dot(&dir, &axes[*b])).unwrap() }).unwrap();
+				assert_eq!(closest, face);
+			}
+		}
+	}
+
+	#[test]
+	fn equirectangular_faces() {
+		//Each texel stores its own coordinates so samples show where in the image they came from
+		let (width, height) = (64, 32);
+		let image = image::Rgb32FImage::from_fn(width, height, |x, y| { image::Rgb([x as f32, y as f32, 1.0]) });
+
+		let face_size = 5;
+		let faces = equirectangular_to_cubemap_faces(&image, face_size);
+		let center = |face: &ImageData| {
+			let i = 3 * 4 * (2 * face_size as usize + 2);
+			let channel = |c: usize| { f32::from_le_bytes([face.data[i + 4 * c], face.data[i + 4 * c + 1], face.data[i + 4 * c + 2], face.data[i + 4 * c + 3]]) };
+			(channel(0), channel(1), channel(2))
+		};
+		for face in faces.iter() {
+			assert_eq!((face.width, face.height), (face_size as GLint, face_size as GLint));
+			assert_eq!((face.format, face.internal_format, face.data_type), (gl::RGB, gl::RGB32F, gl::FLOAT));
+			assert_eq!(face.data.len(), (face_size * face_size) as usize * 3 * mem::size_of::<f32>());
+			assert_eq!(center(face).2, 1.0);
+		}
+
+		//+X is the center of the image and +Y a quarter turn to its right, with the horizon halfway down
+		assert_eq!(center(&faces[0]), (31.5, 15.5, 1.0));
+		assert_eq!(center(&faces[2]), (47.5, 15.5, 1.0));
+		assert_eq!(center(&faces[3]), (15.5, 15.5, 1.0));
+		//-X straddles the left and right edges, so it's filtered across the seam
+		assert_eq!(center(&faces[1]), (31.5, 15.5, 1.0));
+
+		//+Z is the top row and -Z the bottom one
+		assert_eq!(center(&faces[4]).1, 0.0);
+		assert_eq!(center(&faces[5]).1, 31.0);
+	}
+
+	#[test]
+	fn shader_log_formats() {
+		let cases = [
+			//NVIDIA
+			("0(12) : error C0000: syntax error, unexpected '}'", Some(0), Some(12), "error C0000: syntax error, unexpected '}'"),
+			("2(140) : warning C7050: \"color\" might be used before being initialized", Some(2), Some(140), "warning C7050: \"color\" might be used before being initialized"),
+			//Mesa and AMD on Linux
+			("0:12(5): error: `foo' undeclared", Some(0), Some(12), "error: `foo' undeclared"),
+			("1:3(22): warning: extension `GL_ARB_foo' unsupported", Some(1), Some(3), "warning: extension `GL_ARB_foo' unsupported"),
+			//AMD on Windows and Apple
+			("ERROR: 0:7: 'vec5' : syntax error", Some(0), Some(7), "ERROR: 'vec5' : syntax error"),
+			//Intel on Windows
+			("ERROR: 3:41: 'albedo' : undeclared identifier", Some(3), Some(41), "ERROR: 'albedo' : undeclared identifier"),
+			("WARNING: 0:2: extension 'GL_ARB_foo' is not supported", Some(0), Some(2), "WARNING: extension 'GL_ARB_foo' is not supported"),
+			//Lines without a location
+			("ERROR: 1 compilation errors.  No code generated.", None, None, "ERROR: 1 compilation errors.  No code generated."),
+			("Vertex shader(s) failed to link.", None, None, "Vertex shader(s) failed to link."),
+			("1.0 is not supported", None, None, "1.0 is not supported"),
+			("0(12) error without a colon", None, None, "0(12) error without a colon")
+		];
+
+		for (line, source, line_number, message) in cases.iter() {
+			let entries = parse_shader_log(line);
+			assert_eq!(entries.len(), 1, "{}", line);
+			assert_eq!(entries[0], ShaderLogEntry {
+				source: *source,
+				line: *line_number,
+				file: None,
+				message: String::from(*message)
+			}, "{}", line);
+		}
+	}
+
+	#[test]
+	fn shader_log_lines() {
+		//Blank lines and the NUL terminator some drivers leave in are skipped
+		let log = "0(1) : error C0000: first\n\n  0(2) : error C0000: second  \n\0";
+		let entries = parse_shader_log(log);
+		let lines: Vec<Option<u32>> = entries.iter().map(|e| { e.line }).collect();
+		assert_eq!(lines, vec![Some(1), Some(2)]);
+		assert_eq!(entries[1].message, "error C0000: second");
+		assert!(parse_shader_log("").is_empty());
+	}
+
+	#[test]
+	fn image_data_formats() {
+		let luma_alpha = image::GrayAlphaImage::from_raw(2, 1, vec![10, 20, 30, 40]).unwrap();
+		let data = image_data_from_image(DynamicImage::ImageLumaA8(luma_alpha), ColorSpace::Gamma);
+		assert_eq!((data.width, data.height), (2, 1));
+		assert_eq!((data.format, data.internal_format, data.data_type), (gl::RG, gl::RG8, gl::UNSIGNED_BYTE));
+		assert_eq!(data.data, vec![10, 20, 30, 40]);
+
+		let rgb16 = || { image::ImageBuffer::<image::Rgb<u16>, _>::from_raw(2, 1, vec![0, 255, 0x8000, 65535, 257, 0x1234]).unwrap() };
+		let data = image_data_from_image(DynamicImage::ImageRgb16(rgb16()), ColorSpace::Linear);
+		assert_eq!((data.format, data.internal_format, data.data_type), (gl::RGB, gl::RGB16, gl::UNSIGNED_SHORT));
+		assert_eq!(data.data, gpu_bytes(&[0u16, 255, 0x8000, 65535, 257, 0x1234]).to_vec());
+
+		//There's no 16-bit sRGB format, so gamma-encoded 16-bit color is scaled down to 8 bits rather than truncated
+		let data = image_data_from_image(DynamicImage::ImageRgb16(rgb16()), ColorSpace::Gamma);
+		assert_eq!((data.format, data.internal_format, data.data_type), (gl::RGB, gl::SRGB8, gl::UNSIGNED_BYTE));
+		assert_eq!(data.data, vec![0, 1, 128, 255, 1, 18]);
+
+		let rgba32f = image::Rgba32FImage::from_raw(1, 2, vec![0.25, 0.5, 2.0, 1.0, -1.0, 0.0, 100.0, 0.5]).unwrap();
+		let data = image_data_from_image(DynamicImage::ImageRgba32F(rgba32f), ColorSpace::Gamma);
+		assert_eq!((data.width, data.height), (1, 2));
+		assert_eq!((data.format, data.internal_format, data.data_type), (gl::RGBA, gl::RGBA32F, gl::FLOAT));
+		assert_eq!(data.data, gpu_bytes(&[0.25f32, 0.5, 2.0, 1.0, -1.0, 0.0, 100.0, 0.5]).to_vec());
+	}
+}
@@ -1,427 +1,35 @@
 #![allow(non_camel_case_types)]
-use std::mem::{self, size_of};
+use std::mem;
 use std::fs::File;
-use std::io::{Read, Result, Write};
+use std::io::{BufReader, BufWriter, Read, Result, Write};
 use std::path::Path;
 use std::string::String;
 use crate::{structs::*, routines, render::PositionNormalTangentUvPrimitive};
 
-//DDS Programming Guide
-//https://learn.microsoft.com/en-us/windows/win32/direct3ddds/dx-graphics-dds-pguide
-
-/*
-struct DDS_PIXELFORMAT {
-  DWORD dwSize;
-  DWORD dwFlags;
-  DWORD dwFourCC;
-  DWORD dwRGBBitCount;
-  DWORD dwRBitMask;
-  DWORD dwGBitMask;
-  DWORD dwBBitMask;
-  DWORD dwABitMask;
-};
-*/
-#[derive(Debug)]
-#[repr(C)]
-pub struct DDS_PixelFormat {
-    pub size: u32,
-    pub flags: u32,
-    pub four_cc: u32,
-    pub rgb_bitcount: u32,
-    pub r_bitmask: u32,
-    pub g_bitmask: u32,
-    pub b_bitmask: u32,
-    pub a_bitmask: u32,
-}
-
-impl DDS_PixelFormat {
-    pub const DDPF_ALPHAPIXELS: u32 = 0x1;
-    pub const DDPF_ALPHA: u32 = 0x2;
-    pub const DDPF_FOURCC: u32 = 0x4;
-    pub const DDPF_RGB: u32 = 0x1;
-    pub const DDPF_YUV: u32 = 0x2;
-    pub const DDPF_LUMINANCE: u32 = 0x4;
-
-    pub fn from_header_bytes(header_bytes: &[u8]) -> Self {
-        let start = 19 * size_of::<u32>();
-        let mut current_offset = 0;
-
-        let size = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let flags = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let four_cc = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let rgb_bitcount = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let r_bitmask = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let g_bitmask = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let b_bitmask = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let a_bitmask = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        
-
-        DDS_PixelFormat {
-            size,
-            flags,
-            four_cc,
-            rgb_bitcount,
-            r_bitmask,
-            g_bitmask,
-            b_bitmask,
-            a_bitmask
-        }
-    }
-}
-
-impl Default for DDS_PixelFormat {
-    fn default() -> Self {
-        let mut four_cc = 0u32;
-        four_cc |= ('D' as u32) << 0;
-        four_cc |= ('X' as u32) << 8;
-        four_cc |= ('1' as u32) << 16;
-        four_cc |= ('0' as u32) << 24;
-        DDS_PixelFormat {
-            size: 32,
-            flags: 0,
-            four_cc,
-            rgb_bitcount: 8,
-            r_bitmask: 0,
-            g_bitmask: 0,
-            b_bitmask: 0,
-            a_bitmask: 0,
-        }
-    }
-}
-
-/*
-typedef struct {
-     DWORD           dwSize;
-     DWORD           dwFlags;
-     DWORD           dwHeight;
-     DWORD           dwWidth;
-     DWORD           dwPitchOrLinearSize;
-     DWORD           dwDepth;
-     DWORD           dwMipMapCount;
-     DWORD           dwReserved1[11];
-     DDS_PIXELFORMAT ddspf;
-     DWORD           dwCaps;
-     DWORD           dwCaps2;
-     DWORD           dwCaps3;
-     DWORD           dwCaps4;
-     DWORD           dwReserved2;
-} DDS_HEADER;
-   */
-#[derive(Debug)]
-#[repr(C)]
-pub struct DDSHeader {
-    pub magic_word: u32,        // 0x20534444
-    pub size: u32,
-    pub flags: u32,
-    pub height: u32,
-    pub width: u32,
-    pub pitch_or_linear_size: u32,
-    pub depth: u32,
-    pub mipmap_count: u32,
-    pub reserved_1: [u32; 11],
-    pub spf: DDS_PixelFormat,
-    pub caps: u32,
-    pub caps2: u32,
-    pub caps3: u32,
-    pub caps4: u32,
-    pub reserved2: u32,
-    pub dx10_header: DDSHeader_DXT10
-}
-
-impl DDSHeader {
-    const TRUE_BC7_HEADER_SIZE: usize = 148;        //This is DDSHeader + DDSHeader_DXT10 + magic word
-    const REQUIRED_BC7_SIZE: u32 = 124;
-
-    pub const DDSD_CAPS: u32 = 0x1;
-    pub const DDSD_HEIGHT: u32 = 0x2;
-    pub const DDSD_WIDTH: u32 = 0x4;
-    pub const DDSD_PITCH: u32 = 0x8;
-    pub const DDSD_PIXELFORMAT: u32 = 0x1000;
-    pub const DDSD_MIPMAPCOUNT: u32 = 0x20000;
-    pub const DDSD_LINEARSIZE: u32 = 0x80000;
-    pub const DDSD_DEPTH: u32 = 0x800000;
-
-    pub fn from_file(dds_file: &mut File) -> Self {
-        let mut header_buffer = vec![0u8; Self::TRUE_BC7_HEADER_SIZE];
-    
-        dds_file.read_exact(&mut header_buffer).unwrap();
-    
-        let height = read_u32_from_le_bytes(&header_buffer, 12);
-        let width = read_u32_from_le_bytes(&header_buffer, 16);
-        let pitch_or_linear_size = read_u32_from_le_bytes(&header_buffer, 20);
-        let mipmap_count = read_u32_from_le_bytes(&header_buffer, 28);
-        let pixel_format = DDS_PixelFormat::from_header_bytes(&header_buffer);
-
-        let dx10_header = DDSHeader_DXT10::from_header_bytes(&header_buffer);
-
-        DDSHeader {
-            height,
-            width,
-            pitch_or_linear_size,
-            mipmap_count,
-            spf: pixel_format,
-            dx10_header,
-            ..Default::default()
-        }
-    }
-}
+pub use crate::dds::{DDS_PixelFormat, DDSHeader, DDSHeader_DXT10, D3D10_RESOURCE_DIMENSION, DXGI_FORMAT, compute_pitch_bc};
 
-impl Default for DDSHeader {
-    fn default() -> Self {
-        DDSHeader {
-            magic_word: 0x20534444,
-            size: Self::REQUIRED_BC7_SIZE,
-            flags: 0,
-            height: 0,
-            width: 0,
-            pitch_or_linear_size: 0,
-            depth: 0,
-            mipmap_count: 1,
-            reserved_1: [0; 11],
-            spf: DDS_PixelFormat::default(),
-            caps: 0,
-            caps2: 0,
-            caps3: 0,
-            caps4: 0,
-            reserved2: 0,
-            dx10_header: DDSHeader_DXT10 {
-                dxgi_format: DXGI_FORMAT::UNKNOWN,
-                resource_dimension: D3D10_RESOURCE_DIMENSION::TEXTURE2D,
-                misc_flag: 0,
-                array_size: 1,
-                misc_flags2: 0
-            }
-        }
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexType {
+    U16,
+    U32
 }
 
-/*
-typedef struct {
-  DXGI_FORMAT              dxgiFormat;
-  D3D10_RESOURCE_DIMENSION resourceDimension;
-  UINT                     miscFlag;
-  UINT                     arraySize;
-  UINT                     miscFlags2;
-} DDS_HEADER_DXT10;
- */
-#[derive(Debug)]
-#[repr(C)]
-pub struct DDSHeader_DXT10 {
-    pub dxgi_format: DXGI_FORMAT,
-    pub resource_dimension: D3D10_RESOURCE_DIMENSION,
-    pub misc_flag: u32,
-    pub array_size: u32,
-    pub misc_flags2: u32
-}
-
-impl DDSHeader_DXT10 {
-    pub fn from_header_bytes(header_bytes: &[u8]) -> Self {
-        let start = size_of::<DDSHeader>() - size_of::<DDSHeader_DXT10>();
-        let mut current_offset = 0;
-
-        let dxgi_format = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        let dxgi_format = unsafe { std::mem::transmute(dxgi_format) };
-        current_offset += size_of::<u32>();
-
-        let resource_dimension = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        let resource_dimension = unsafe { std::mem::transmute(resource_dimension) };
-        current_offset += size_of::<u32>();
-
-        let misc_flag = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let array_size = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        current_offset += size_of::<u32>();
-
-        let misc_flags2 = read_u32_from_le_bytes(header_bytes, start + current_offset);
-        
-        DDSHeader_DXT10 {
-            dxgi_format,
-            resource_dimension,
-            misc_flag,
-            array_size,
-            misc_flags2
+impl IndexType {
+    pub fn size(&self) -> usize {
+        match self {
+            IndexType::U16 => { mem::size_of::<u16>() }
+            IndexType::U32 => { mem::size_of::<u32>() }
         }
     }
-}
 
-impl Default for DDSHeader_DXT10 {
-    fn default() -> Self {
-        DDSHeader_DXT10 {
-            dxgi_format: DXGI_FORMAT::UNKNOWN,
-            resource_dimension: D3D10_RESOURCE_DIMENSION::TEXTURE2D,
-            misc_flag: 0,
-            array_size: 1,
-            misc_flags2: 0
+    pub fn gl_type(&self) -> gl::types::GLenum {
+        match self {
+            IndexType::U16 => { gl::UNSIGNED_SHORT }
+            IndexType::U32 => { gl::UNSIGNED_INT }
         }
     }
 }
 
-impl DDSHeader_DXT10 {
-    pub const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
-}
-
-#[derive(Debug)]
-pub enum D3D10_RESOURCE_DIMENSION {
-    UNKNOWN = 0,
-    BUFFER = 1,
-    TEXTURE1D = 2,
-    TEXTURE2D = 3,
-    TEXTURE3D = 4,
-
-    _RESERVED = 0xFFFFFFFF
-}
-
-#[derive(Debug)]
-pub enum DXGI_FORMAT {
-    UNKNOWN = 0,
-    R32G32B32A32_TYPELESS = 1,
-    R32G32B32A32_FLOAT = 2,
-    R32G32B32A32_UINT = 3,
-    R32G32B32A32_SINT = 4,
-    R32G32B32_TYPELESS = 5,
-    R32G32B32_FLOAT = 6,
-    R32G32B32_UINT = 7,
-    R32G32B32_SINT = 8,
-    R16G16B16A16_TYPELESS = 9,
-    R16G16B16A16_FLOAT = 10,
-    R16G16B16A16_UNORM = 11,
-    R16G16B16A16_UINT = 12,
-    R16G16B16A16_SNORM = 13,
-    R16G16B16A16_SINT = 14,
-    R32G32_TYPELESS = 15,
-    R32G32_FLOAT = 16,
-    R32G32_UINT = 17,
-    R32G32_SINT = 18,
-    R32G8X24_TYPELESS = 19,
-    D32_FLOAT_S8X24_UINT = 20,
-    R32_FLOAT_X8X24_TYPELESS = 21,
-    X32_TYPELESS_G8X24_UINT = 22,
-    R10G10B10A2_TYPELESS = 23,
-    R10G10B10A2_UNORM = 24,
-    R10G10B10A2_UINT = 25,
-    R11G11B10_FLOAT = 26,
-    R8G8B8A8_TYPELESS = 27,
-    R8G8B8A8_UNORM = 28,
-    R8G8B8A8_UNORM_SRGB = 29,
-    R8G8B8A8_UINT = 30,
-    R8G8B8A8_SNORM = 31,
-    R8G8B8A8_SINT = 32,
-    R16G16_TYPELESS = 33,
-    R16G16_FLOAT = 34,
-    R16G16_UNORM = 35,
-    R16G16_UINT = 36,
-    R16G16_SNORM = 37,
-    R16G16_SINT = 38,
-    R32_TYPELESS = 39,
-    D32_FLOAT = 40,
-    R32_FLOAT = 41,
-    R32_UINT = 42,
-    R32_SINT = 43,
-    R24G8_TYPELESS = 44,
-    D24_UNORM_S8_UINT = 45,
-    R24_UNORM_X8_TYPELESS = 46,
-    X24_TYPELESS_G8_UINT = 47,
-    R8G8_TYPELESS = 48,
-    R8G8_UNORM = 49,
-    R8G8_UINT = 50,
-    R8G8_SNORM = 51,
-    R8G8_SINT = 52,
-    R16_TYPELESS = 53,
-    R16_FLOAT = 54,
-    D16_UNORM = 55,
-    R16_UNORM = 56,
-    R16_UINT = 57,
-    R16_SNORM = 58,
-    R16_SINT = 59,
-    R8_TYPELESS = 60,
-    R8_UNORM = 61,
-    R8_UINT = 62,
-    R8_SNORM = 63,
-    R8_SINT = 64,
-    A8_UNORM = 65,
-    R1_UNORM = 66,
-    R9G9B9E5_SHAREDEXP = 67,
-    R8G8_B8G8_UNORM = 68,
-    G8R8_G8B8_UNORM = 69,
-    BC1_TYPELESS = 70,
-    BC1_UNORM = 71,
-    BC1_UNORM_SRGB = 72,
-    BC2_TYPELESS = 73,
-    BC2_UNORM = 74,
-    BC2_UNORM_SRGB = 75,
-    BC3_TYPELESS = 76,
-    BC3_UNORM = 77,
-    BC3_UNORM_SRGB = 78,
-    BC4_TYPELESS = 79,
-    BC4_UNORM = 80,
-    BC4_SNORM = 81,
-    BC5_TYPELESS = 82,
-    BC5_UNORM = 83,
-    BC5_SNORM = 84,
-    B5G6R5_UNORM = 85,
-    B5G5R5A1_UNORM = 86,
-    B8G8R8A8_UNORM = 87,
-    B8G8R8X8_UNORM = 88,
-    R10G10B10_XR_BIAS_A2_UNORM = 89,
-    B8G8R8A8_TYPELESS = 90,
-    B8G8R8A8_UNORM_SRGB = 91,
-    B8G8R8X8_TYPELESS = 92,
-    B8G8R8X8_UNORM_SRGB = 93,
-    BC6H_TYPELESS = 94,
-    BC6H_UF16 = 95,
-    BC6H_SF16 = 96,
-    BC7_TYPELESS = 97,
-    BC7_UNORM = 98,
-    BC7_UNORM_SRGB = 99,
-    AYUV = 100,
-    Y410 = 101,
-    Y416 = 102,
-    NV12 = 103,
-    P010 = 104,
-    P016 = 105,
-    //420_OPAQUE = 106,
-    YUY2 = 107,
-    Y210 = 108,
-    Y216 = 109,
-    NV11 = 110,
-    AI44 = 111,
-    IA44 = 112,
-    P8 = 113,
-    A8P8 = 114,
-    B4G4R4A4_UNORM = 115,
-    P208 = 130,
-    V208 = 131,
-    V408 = 132,
-    SAMPLER_FEEDBACK_MIN_MIP_OPAQUE,
-    SAMPLER_FEEDBACK_MIP_REGION_USED_OPAQUE,
-    FORCE_UINT = 0xffffffff
-}
-
-pub fn compute_pitch_bc(width: u32, block_size: u32) -> u32 {
-    //max( 1, ((width+3)/4) ) * block-size
-    u32::max(1, (width + 3) / 4) * block_size
-}
-
-pub enum IndexType {
-    U16,
-    U32
-}
-
 pub struct UninterleavedVertexData {
     pub indices: Vec<u32>,
     pub positions: Vec<f32>,
@@ -430,7 +38,7 @@ pub struct UninterleavedVertexData {
     pub uvs: Vec<f32>
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct OzyMaterial {
     pub base_color: [f32; 4],
     pub emissive_factor: [f32; 3],
@@ -457,7 +65,7 @@ impl Default for OzyMaterial {
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct OzyImage {
     pub width: u32,
     pub height: u32,
@@ -465,6 +73,7 @@ pub struct OzyImage {
     pub bc7_bytes: Vec<u8>
 }
 
+#[derive(Debug, PartialEq)]
 pub struct OzyPrimitive {
     pub indices: Vec<u32>,
     pub vertex_positions: Vec<f32>,
@@ -492,6 +101,97 @@ impl PositionNormalTangentUvPrimitive for OzyPrimitive {
     }
 }
 
+//Identifies which part of an .ozy file was being read when something went wrong
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OzySection {
+    Header,
+    Material(usize),
+    Primitive(usize),
+    Texture(usize)
+}
+
+impl std::fmt::Display for OzySection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OzySection::Header => { write!(f, "header") }
+            OzySection::Material(i) => { write!(f, "material {}", i) }
+            OzySection::Primitive(i) => { write!(f, "primitive {}", i) }
+            OzySection::Texture(i) => { write!(f, "texture {}", i) }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OzyError {
+    Io(std::io::Error),
+    Truncated(OzySection),
+    AbsurdCount {
+        section: OzySection,
+        count: u32
+    },
+    MaterialIndexOutOfRange {
+        primitive: usize,
+        material_idx: u32,
+        material_count: usize
+    },
+    TextureIndexOutOfRange {
+        material: usize,
+        texture_idx: u32,
+        texture_count: usize
+    },
+    UnsupportedVersion(u32),
+    UnrecognizedFormat
+}
+
+impl OzyError {
+    //Turns an error from one of the read_* helpers into an OzyError for the given section
+    fn from_read(section: OzySection) -> impl Fn(std::io::Error) -> Self {
+        move |e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                OzyError::Truncated(section)
+            } else {
+                OzyError::Io(e)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for OzyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OzyError::Io(e) => { write!(f, "I/O error: {}", e) }
+            OzyError::Truncated(section) => { write!(f, "file ended while reading {}", section) }
+            OzyError::AbsurdCount { section, count } => { write!(f, "absurd count {} in {}", count, section) }
+            OzyError::MaterialIndexOutOfRange { primitive, material_idx, material_count } => {
+                write!(f, "primitive {} references material {} but there are only {} materials", primitive, material_idx, material_count)
+            }
+            OzyError::TextureIndexOutOfRange { material, texture_idx, texture_count } => {
+                write!(f, "material {} references texture {} but there are only {} textures", material, texture_idx, texture_count)
+            }
+            OzyError::UnsupportedVersion(version) => {
+                write!(f, "format version {} is not supported (the newest known version is {})", version, OzyMesh::VERSION)
+            }
+            OzyError::UnrecognizedFormat => { write!(f, "not a current or legacy OzyMesh file") }
+        }
+    }
+}
+
+impl std::error::Error for OzyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OzyError::Io(e) => { Some(e) }
+            _ => { None }
+        }
+    }
+}
+
+impl From<std::io::Error> for OzyError {
+    fn from(e: std::io::Error) -> Self {
+        OzyError::Io(e)
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct OzyMesh {
     pub name: String,
     pub textures: Vec<OzyImage>,
@@ -500,67 +200,108 @@ pub struct OzyMesh {
 }
 
 impl OzyMesh {
+    //Every .ozy in this format starts with MAGIC followed by a u32 version number
+    //Version 0 is the unversioned layout that predates the header, i.e. the same body with no magic or version.
+    //from_reader() reads files without the header as version 0, and also accepts an explicit version 0 header
+    pub const MAGIC: [u8; 4] = *b"OZYM";
+    pub const VERSION: u32 = 1;
+
+    //Upper bounds used to reject garbage counts before allocating for them
+    pub const MAX_SECTION_COUNT: u32 = 0x10000;
+    pub const MAX_ARRAY_LENGTH: u32 = 0x4000000;
+    pub const MAX_TEXTURE_DIMENSION: u32 = 16384;
+
+    //Panicking convenience wrapper around OzyMesh::load()
     pub fn from_file(path: &str) -> Self {
-        let name = String::from(Path::new(&path).file_stem().unwrap().to_str().unwrap());
+        match Self::load(path) {
+            Ok(mesh) => { mesh }
+            Err(e) => { panic!("Error loading OzyMesh \"{}\": {}", path, e); }
+        }
+    }
 
-        let mut file = File::open(path).unwrap();
-        
-        //Read header
-        let material_count = read_u32(&mut file).unwrap();
-        let primitive_count = read_u32(&mut file).unwrap();
-        let texture_count = read_u32(&mut file).unwrap();
+    pub fn load(path: &str) -> std::result::Result<Self, OzyError> {
+        let file = File::open(path)?;
+        Self::from_reader(&mut BufReader::new(file), &mesh_name(path))
+    }
 
-        let mut materials = Vec::with_capacity(material_count as usize);
-        let mut primitives = Vec::with_capacity(primitive_count as usize);
-        let mut textures = Vec::with_capacity(texture_count as usize);
+    pub fn from_bytes(bytes: &[u8], name: &str) -> std::result::Result<Self, OzyError> {
+        Self::from_reader(&mut &bytes[..], name)
+    }
 
-        for _ in 0..material_count {
-            let base_color = {
-                let b = read_f32_data(&mut file, 4).unwrap();
-                let mut o = [0.0; 4];
-                for j in 0..4 {
-                    o[j] = b[j];
-                }
-                o
-            };
-            let emissive_factor = {
-                let b = read_f32_data(&mut file, 3).unwrap();
-                let mut o = [0.0; 3];
-                for j in 0..3 {
-                    o[j] = b[j];
+    //Headerless files have nothing to identify them, so they're only accepted if the body accounts for every remaining byte
+    //If the counts at the start of the body don't make sense, or there are bytes left over, it's UnrecognizedFormat.
+    //That includes an OzyMeshOBSOLETE, which AnyOzyMesh can load. Errors past the counts are reported as they are
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R, name: &str) -> std::result::Result<Self, OzyError> {
+        let err = OzyError::from_read(OzySection::Header);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(&err)?;
+        if magic != Self::MAGIC {
+            let mut legacy = (&magic[..]).chain(reader);
+            let mesh = Self::read_body(&mut legacy, name).map_err(|e| {
+                match e {
+                    OzyError::Truncated(OzySection::Header) | OzyError::AbsurdCount { section: OzySection::Header, .. } => {
+                        OzyError::UnrecognizedFormat
+                    }
+                    _ => { e }
                 }
-                o
+            })?;
+            let mut trailing = [0u8];
+            return match legacy.read(&mut trailing) {
+                Ok(0) => { Ok(mesh) }
+                Ok(_) => { Err(OzyError::UnrecognizedFormat) }
+                Err(e) => { Err(OzyError::Io(e)) }
             };
-            let base_roughness = read_f32(&mut file).unwrap();
-            let base_metalness = read_f32(&mut file).unwrap();
+        }
 
-            let idx = read_u32(&mut file).unwrap();
-            let color_bc7_idx = if idx == 0xFFFFFFFF {
-                None
-            } else {
-                Some(idx)
-            };
+        let version = read_u32(reader).map_err(&err)?;
+        match version {
+            0 | Self::VERSION => { Self::read_body(reader, name) }
+            _ => { Err(OzyError::UnsupportedVersion(version)) }
+        }
+    }
 
-            let idx = read_u32(&mut file).unwrap();
-            let normal_bc7_idx = if idx == 0xFFFFFFFF {
-                None
-            } else {
-                Some(idx)
-            };
+    //Reads everything after the magic number and version
+    fn read_body<R: Read + ?Sized>(reader: &mut R, name: &str) -> std::result::Result<Self, OzyError> {
+        //Read counts
+        let material_count = read_count(reader, OzySection::Header, Self::MAX_SECTION_COUNT)?;
+        let primitive_count = read_count(reader, OzySection::Header, Self::MAX_SECTION_COUNT)?;
+        let texture_count = read_count(reader, OzySection::Header, Self::MAX_SECTION_COUNT)?;
 
-            let idx = read_u32(&mut file).unwrap();
-            let arm_bc7_idx = if idx == 0xFFFFFFFF {
-                None
-            } else {
-                Some(idx)
-            };
+        let mut materials = Vec::with_capacity(material_count);
+        let mut primitives = Vec::with_capacity(primitive_count);
+        let mut textures = Vec::with_capacity(texture_count);
 
-            let idx = read_u32(&mut file).unwrap();
-            let emissive_bc7_idx = if idx == 0xFFFFFFFF {
-                None
-            } else {
-                Some(idx)
+        for i in 0..material_count {
+            let section = OzySection::Material(i);
+            let err = OzyError::from_read(section);
+
+            let base_color = {
+                let b = read_f32_data(reader, 4).map_err(&err)?;
+                [b[0], b[1], b[2], b[3]]
+            };
+            let emissive_factor = {
+                let b = read_f32_data(reader, 3).map_err(&err)?;
+                [b[0], b[1], b[2]]
             };
+            let base_roughness = read_f32(reader).map_err(&err)?;
+            let base_metalness = read_f32(reader).map_err(&err)?;
+
+            //Texture indices of 0xFFFFFFFF mean the material doesn't have that map
+            let mut texture_indices = [None; 4];
+            for tex_idx in texture_indices.iter_mut() {
+                let idx = read_u32(reader).map_err(&err)?;
+                if idx != 0xFFFFFFFF {
+                    if idx as usize >= texture_count {
+                        return Err(OzyError::TextureIndexOutOfRange {
+                            material: i,
+                            texture_idx: idx,
+                            texture_count
+                        });
+                    }
+                    *tex_idx = Some(idx);
+                }
+            }
+            let [color_bc7_idx, normal_bc7_idx, arm_bc7_idx, emissive_bc7_idx] = texture_indices;
 
             let mat = OzyMaterial {
                 base_color,
@@ -575,13 +316,31 @@ impl OzyMesh {
             materials.push(mat);
         }
 
-        for _ in 0..primitive_count {
-            let material_idx = read_u32(&mut file).unwrap();
-            let indices = read_u32_array(&mut file).unwrap();
-            let vertex_positions = read_f32_array(&mut file).unwrap();
-            let vertex_normals = read_f32_array(&mut file).unwrap();
-            let vertex_tangents = read_f32_array(&mut file).unwrap();
-            let vertex_uvs = read_f32_array(&mut file).unwrap();
+        for i in 0..primitive_count {
+            let section = OzySection::Primitive(i);
+            let err = OzyError::from_read(section);
+
+            let material_idx = read_u32(reader).map_err(&err)?;
+            if material_idx as usize >= material_count {
+                return Err(OzyError::MaterialIndexOutOfRange {
+                    primitive: i,
+                    material_idx,
+                    material_count
+                });
+            }
+
+            let count = read_count(reader, section, Self::MAX_ARRAY_LENGTH)?;
+            let indices = read_u32_data(reader, count).map_err(&err)?;
+
+            let mut f32_arrays = Vec::with_capacity(4);
+            for _ in 0..4 {
+                let count = read_count(reader, section, Self::MAX_ARRAY_LENGTH)?;
+                f32_arrays.push(read_f32_data(reader, count).map_err(&err)?);
+            }
+            let vertex_uvs = f32_arrays.pop().unwrap();
+            let vertex_tangents = f32_arrays.pop().unwrap();
+            let vertex_normals = f32_arrays.pop().unwrap();
+            let vertex_positions = f32_arrays.pop().unwrap();
 
             let prim = OzyPrimitive {
                 material_idx,
@@ -594,17 +353,16 @@ impl OzyMesh {
             primitives.push(prim);
         }
 
-        for _ in 0..texture_count {
-            let width = read_u32(&mut file).unwrap();
-            let height = read_u32(&mut file).unwrap();
-            let mipmap_count = read_u32(&mut file).unwrap();
+        for i in 0..texture_count {
+            let section = OzySection::Texture(i);
+            let err = OzyError::from_read(section);
+
+            let width = read_count(reader, section, Self::MAX_TEXTURE_DIMENSION)? as u32;
+            let height = read_count(reader, section, Self::MAX_TEXTURE_DIMENSION)? as u32;
+            let mipmap_count = read_count(reader, section, routines::calculate_mipcount(width, height))? as u32;
             
-            let mut bc7_byte_count = 0;
-            for i in 0..mipmap_count {
-                let (w, h) = routines::mip_resolution(width, height, i);
-                bc7_byte_count += ispc::bc7::calc_output_size(w, h);
-            }
-            let bc7_bytes = read_u8_data(&mut file, bc7_byte_count).unwrap();
+            let bc7_byte_count = bc7_mip_chain_size(width, height, mipmap_count);
+            let bc7_bytes = read_u8_data(reader, bc7_byte_count).map_err(&err)?;
 
             let image = OzyImage {
                 width,
@@ -615,15 +373,100 @@ impl OzyMesh {
             textures.push(image);
         }
 
-        OzyMesh {
-            name,
+        Ok(OzyMesh {
+            name: String::from(name),
             materials,
             primitives,
             textures
+        })
+    }
+
+    pub fn write_file(&self, path: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    //Writes the mesh in exactly the layout OzyMesh::from_reader() expects
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> Result<()> {
+        //Header
+        writer.write_all(&Self::MAGIC)?;
+        write_u32(writer, Self::VERSION)?;
+        write_u32(writer, self.materials.len() as u32)?;
+        write_u32(writer, self.primitives.len() as u32)?;
+        write_u32(writer, self.textures.len() as u32)?;
+
+        for material in self.materials.iter() {
+            write_f32_data(writer, &material.base_color)?;
+            write_f32_data(writer, &material.emissive_factor)?;
+            write_f32(writer, material.base_roughness)?;
+            write_f32(writer, material.base_metalness)?;
+
+            let texture_indices = [material.color_bc7_idx, material.normal_bc7_idx, material.arm_bc7_idx, material.emissive_bc7_idx];
+            for idx in texture_indices.iter() {
+                write_u32(writer, idx.unwrap_or(0xFFFFFFFF))?;
+            }
+        }
+
+        for prim in self.primitives.iter() {
+            write_u32(writer, prim.material_idx)?;
+            write_u32_array(writer, &prim.indices)?;
+            write_f32_array(writer, &prim.vertex_positions)?;
+            write_f32_array(writer, &prim.vertex_normals)?;
+            write_f32_array(writer, &prim.vertex_tangents)?;
+            write_f32_array(writer, &prim.vertex_uvs)?;
         }
+
+        for (i, image) in self.textures.iter().enumerate() {
+            //The reader infers the size of the mip chain, so a mismatch here would corrupt everything after it
+            let expected_size = bc7_mip_chain_size(image.width, image.height, image.mipmap_count);
+            if image.bc7_bytes.len() != expected_size {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("texture {} has {} bytes of BC7 data but a {}x{} image with {} mips needs {}", i, image.bc7_bytes.len(), image.width, image.height, image.mipmap_count, expected_size)
+                ));
+            }
+
+            write_u32(writer, image.width)?;
+            write_u32(writer, image.height)?;
+            write_u32(writer, image.mipmap_count)?;
+            writer.write_all(&image.bc7_bytes)?;
+        }
+
+        Ok(())
+    }
+}
+
+//Meshes are named after their file
+fn mesh_name(path: &str) -> String {
+    match Path::new(path).file_stem() {
+        Some(stem) => { stem.to_string_lossy().into_owned() }
+        None => { String::new() }
     }
 }
 
+//Size in bytes of a full BC7 mip chain
+pub(crate) fn bc7_mip_chain_size(width: u32, height: u32, mipmap_count: u32) -> usize {
+    let mut byte_count = 0;
+    for i in 0..mipmap_count {
+        let (w, h) = routines::mip_resolution(width, height, i);
+        byte_count += ispc::bc7::calc_output_size(w, h);
+    }
+    byte_count
+}
+
+//Reads a u32 count and rejects it if it's larger than max
+fn read_count<R: Read + ?Sized>(reader: &mut R, section: OzySection, max: u32) -> std::result::Result<usize, OzyError> {
+    let count = read_u32(reader).map_err(OzyError::from_read(section))?;
+    if count > max {
+        return Err(OzyError::AbsurdCount {
+            section,
+            count
+        });
+    }
+    Ok(count as usize)
+}
+
 #[derive(Debug)]
 pub struct OzyMeshOBSOLETE {
 	pub vertex_array: VertexArray,
@@ -635,11 +478,16 @@ pub struct OzyMeshOBSOLETE {
 
 impl OzyMeshOBSOLETE {
     pub fn load(path: &str) -> Option<Self> {
-        let mut texture_name = String::new();
-        let mut colors = vec![];
+        Self::load_with_index_type(path, IndexType::U16)
+    }
 
+    pub fn load_32bit_idx(path: &str) -> Option<Self> {
+        Self::load_with_index_type(path, IndexType::U32)
+    }
+
+    fn load_with_index_type(path: &str, index_type: IndexType) -> Option<Self> {
         //Open the file
-        let mut model_file = match File::open(path) {
+        let model_file = match File::open(path) {
             Ok(file) => { file }
             Err(e) => {
                 println!("Unable to open \"{}\": {}", path, e);
@@ -647,91 +495,79 @@ impl OzyMeshOBSOLETE {
             }
         };
 
-        //Check how many solid colors there are
-        //If 0, it means this model uses textures
-        let color_count = match read_u8(&mut model_file) {
-            Ok(count) => { count as usize }
+        Self::from_reader(&mut BufReader::new(model_file), index_type)
+    }
+
+    pub fn from_bytes(bytes: &[u8], index_type: IndexType) -> Option<Self> {
+        Self::from_reader(&mut &bytes[..], index_type)
+    }
+
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R, index_type: IndexType) -> Option<Self> {
+        match Self::read(reader, index_type) {
+            Ok(mesh) => { Some(mesh) }
             Err(e) => {
-                println!("{}", e);
-                return None;
+                println!("Error reading OzyMeshOBSOLETE: {}", e);
+                None
+            }
+        }
+    }
+
+    //from_reader() without the printing, for callers that are only guessing at the format
+    pub fn read<R: Read + ?Sized>(reader: &mut R, index_type: IndexType) -> Result<Self> {
+        let context = |what: &'static str| {
+            move |e: std::io::Error| { std::io::Error::new(e.kind(), format!("{}: {}", what, e)) }
+        };
+        let check_size = |size: u32, what: &str| {
+            if size > OzyMesh::MAX_ARRAY_LENGTH {
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("absurd {} size {}", what, size)))
+            } else {
+                Ok(size as usize)
             }
         };
 
+        let mut texture_name = String::new();
+        let mut colors = vec![];
+
+        //Check how many solid colors there are
+        //If 0, it means this model uses textures
+        let color_count = read_u8(reader).map_err(context("color count"))? as usize;
+
         //Branching on whether or not the model is textured or uses solid colors
         if color_count == 0 {
             //Read the material name
-            texture_name = match read_pascal_strings(&mut model_file, 1) {
-                Ok(v) => { v[0].clone() }
-                Err(_) => { return None; }
-            };
+            texture_name = read_pascal_strings(reader, 1).map_err(context("texture name"))?.remove(0);
         } else {
             //Read the color f32s into a Vec
-            colors = match read_f32_data(&mut model_file, color_count * 4) {
-                Ok(data_block) => { data_block }
-                Err(e) => {
-                    println!("Error reading color data: {}", e);
-                    return None;
-                }
-            };
+            colors = read_f32_data(reader, color_count * 4).map_err(context("color data"))?;
         }
 
         //Transparency flag
-        let is_transparent = match read_u8(&mut model_file) {
-            Ok(flag) => { flag != 0 }
-            Err(e) => {
-                println!("Error reading transparency flag: {}", e);
-                return None;
-            }
-        };
+        let is_transparent = read_u8(reader).map_err(context("transparency flag"))? != 0;
 
         //The uv_velocity
-        let uv_velocity = match read_f32_data(&mut model_file, 2) {
-            Ok(data) => { [data[0], data[1]] }
-            Err(e) => { 
-                println!("Error reading uv_velocity: {}", e);
-                return None;
-            }
+        let uv_velocity = {
+            let data = read_f32_data(reader, 2).map_err(context("uv_velocity"))?;
+            [data[0], data[1]]
         };
-    
+
         //The length of the vertex data section of the file, in bytes
-        let vertices_size = match read_u32(&mut model_file) {
-            Ok(n) => { n }
-            Err(_) => { return None; }
-        };
-    
-        let vertices = {
-            let mut bytes = vec![0; vertices_size as usize];
-            if let Err(e) = model_file.read_exact(bytes.as_mut_slice()) {
-                println!("Error reading vertex data from file: {}", e);
-                return None;
-            }
-    
-            let mut v = Vec::with_capacity(vertices_size as usize / mem::size_of::<f32>());
-            for i in (0..bytes.len()).step_by(mem::size_of::<f32>()) {
-                let b = [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
-                v.push(f32::from_le_bytes(b));
-            }
-            v
+        let vertices_size = check_size(read_u32(reader).map_err(context("vertex data size"))?, "vertex data")?;
+        let vertices = read_f32_data(reader, vertices_size / mem::size_of::<f32>()).map_err(context("vertex data"))?;
+
+        //The exporter writes the index section's size as if the indices were u16s regardless of their actual width
+        let index_count = check_size(read_u32(reader).map_err(context("index data size"))?, "index data")? / mem::size_of::<u16>();
+        let indices = match index_type {
+            IndexType::U16 => { read_u16_data(reader, index_count).map_err(context("index data"))?.iter().map(|&n| { n as u32 }).collect() }
+            IndexType::U32 => { read_u32_data(reader, index_count).map_err(context("index data"))? }
         };
-        
-        let index_count = match read_u32(&mut model_file) {
-            Ok(n) => { (n / mem::size_of::<u16>() as u32) as usize }
-            Err(_) => { return None; }
-        };
-        
-        let indices = match read_u16_data(&mut model_file, index_count) {
-            Ok(n) => { n }
-            Err(_) => { return None; }
-        };
-        let indices: Vec<u32> = indices.iter().map(|&n|{n as u32}).collect();
-    
+
         let vertex_array = VertexArray {
             vertices,
             indices,
             attribute_offsets: vec![3, 3, 3, 3, 2]
         };
-    
-        Some(OzyMeshOBSOLETE {
+
+        Ok(OzyMeshOBSOLETE {
             vertex_array,
             texture_name,
             uv_velocity,
@@ -739,239 +575,493 @@ impl OzyMeshOBSOLETE {
             is_transparent
         })
     }
+}
 
-    pub fn load_32bit_idx(path: &str) -> Option<Self> {
-        let mut texture_name = String::new();
-        let mut colors = vec![];
+//Either kind of .ozy file, for loading assets that may predate OzyMesh::MAGIC
+pub enum AnyOzyMesh {
+    Current(OzyMesh),
+    Obsolete(OzyMeshOBSOLETE)
+}
 
-        //Open the file
-        let mut model_file = match File::open(path) {
-            Ok(file) => { file }
-            Err(e) => {
-                println!("Unable to open \"{}\": {}", path, e);
-                return None;
-            }
-        };
+impl AnyOzyMesh {
+    pub fn load(path: &str) -> std::result::Result<Self, OzyError> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes, &mesh_name(path))
+    }
 
-        //Check how many solid colors there are
-        //If 0, it means this model uses textures
-        let color_count = match read_u8(&mut model_file) {
-            Ok(count) => { count as usize }
-            Err(e) => {
-                println!("{}", e);
-                return None;
-            }
+    pub fn from_bytes(bytes: &[u8], name: &str) -> std::result::Result<Self, OzyError> {
+        //OzyMesh handles both the versioned layout and the unversioned one that predates it
+        let current_error = match OzyMesh::from_bytes(bytes, name) {
+            Ok(mesh) => { return Ok(AnyOzyMesh::Current(mesh)); }
+            Err(e) => { e }
         };
 
-        //Branching on whether or not the model is textured or uses solid colors
-        if color_count == 0 {
-            //Read the material name
-            texture_name = match read_pascal_strings(&mut model_file, 1) {
-                Ok(v) => { v[0].clone() }
-                Err(_) => { return None; }
-            };
-        } else {
-            //Read the color f32s into a Vec
-            colors = match read_f32_data(&mut model_file, color_count * 4) {
-                Ok(data_block) => { data_block }
-                Err(e) => {
-                    println!("Error reading color data: {}", e);
-                    return None;
-                }
-            };
+        //Without the header an obsolete mesh can get partway through the unversioned layout before failing
+        if bytes.starts_with(&OzyMesh::MAGIC) {
+            return Err(current_error);
         }
 
-        //Transparency flag
-        let is_transparent = match read_u8(&mut model_file) {
-            Ok(flag) => { flag != 0 }
-            Err(e) => {
-                println!("Error reading transparency flag: {}", e);
-                return None;
+        //The equivalents of OzyMeshOBSOLETE::load() and OzyMeshOBSOLETE::load_32bit_idx()
+        //A layout is only accepted if it accounts for every byte
+        for index_type in [IndexType::U16, IndexType::U32].iter() {
+            let mut remaining = bytes;
+            if let Ok(mesh) = OzyMeshOBSOLETE::read(&mut remaining, *index_type) {
+                if remaining.is_empty() {
+                    return Ok(AnyOzyMesh::Obsolete(mesh));
+                }
             }
-        };
+        }
 
-        //The uv_velocity
-        let uv_velocity = match read_f32_data(&mut model_file, 2) {
-            Ok(data) => { [data[0], data[1]] }
-            Err(e) => { 
-                println!("Error reading uv_velocity: {}", e);
-                return None;
-            }
-        };
-    
-        //The length of the vertex data section of the file, in bytes
-        let vertices_size = match read_u32(&mut model_file) {
-            Ok(n) => { n }
-            Err(_) => { return None; }
+        Err(current_error)
+    }
+}
+
+//A mesh referenced by a level, along with the model matrix of each of its instances
+#[derive(Debug)]
+pub struct OzyLevelMesh {
+    pub path: String,
+    pub instances: Vec<glm::TMat4<f32>>
+}
+
+//The contents of an OzyMap (.lvl) file as written by scripts/ozymap_export.py
+//All paths are relative to the game's base directory, i.e. the parent of the directory containing the .lvl
+//The .lvl doesn't record its collision file. The exporter names that after the Blender scene's main collection,
+//so collision_path assumes the .lvl was saved under the same name. Overwrite it if yours differ
+#[derive(Debug)]
+pub struct OzyLevel {
+    pub name: String,
+    pub meshes: Vec<OzyLevelMesh>,
+    pub collision_path: String
+}
+
+impl OzyLevel {
+    const FLOATS_PER_TRANSFORM: usize = 16;
+    pub const MAX_INSTANCE_COUNT: u32 = OzyMesh::MAX_ARRAY_LENGTH / Self::FLOATS_PER_TRANSFORM as u32;
+
+    pub fn from_file(path: &str) -> Result<Self> {
+        let name = match Path::new(path).file_stem().and_then(|s| s.to_str()) {
+            Some(stem) => { String::from(stem) }
+            None => { return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("\"{}\" has no file name", path))); }
         };
-    
-        let vertices = {
-            let mut bytes = vec![0; vertices_size as usize];
-            if let Err(e) = model_file.read_exact(bytes.as_mut_slice()) {
-                println!("Error reading vertex data from file: {}", e);
-                return None;
-            }
-    
-            let mut v = Vec::with_capacity(vertices_size as usize / mem::size_of::<f32>());
-            for i in (0..bytes.len()).step_by(mem::size_of::<f32>()) {
-                let b = [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
-                v.push(f32::from_le_bytes(b));
+
+        let file = File::open(path)?;
+        Self::from_reader(&mut BufReader::new(file), &name)
+    }
+
+    pub fn from_bytes(bytes: &[u8], name: &str) -> Result<Self> {
+        Self::from_reader(&mut &bytes[..], name)
+    }
+
+    //The file is a sequence of (.ozy filename, u32 instance count, column-major 4x4 matrix per instance) entries that runs until EOF
+    //Only a clean EOF before an entry ends the level. A file cut off anywhere inside an entry is an UnexpectedEof error
+    //name is the level's name, which is also used to find its collision file
+    pub fn from_reader<R: Read + ?Sized>(reader: &mut R, name: &str) -> Result<Self> {
+        let mut meshes = Vec::new();
+        while let Some(length) = read_u32_or_eof(reader)? {
+            let filename = match String::from_utf8(read_u8_data(reader, length as usize)?) {
+                Ok(s) => { s }
+                Err(e) => { return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("OzyLevel mesh filename isn't utf-8: {}", e))); }
+            };
+
+            let instance_count = read_u32(reader)?;
+            if instance_count > Self::MAX_INSTANCE_COUNT {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("absurd instance count {} for \"{}\"", instance_count, filename)));
             }
-            v
-        };
-        
-        let index_count = match read_u32(&mut model_file) {
-            Ok(n) => { (n / mem::size_of::<u16>() as u32) as usize }
-            Err(_) => { return None; }
-        };
-        
-        let indices = match read_u32_data(&mut model_file, index_count) {
-            Ok(n) => { n }
-            Err(_) => { return None; }
-        };
-    
-        let vertex_array = VertexArray {
-            vertices,
-            indices,
-            attribute_offsets: vec![3, 3, 3, 3, 2]
-        };
-    
-        Some(OzyMeshOBSOLETE {
-            vertex_array,
-            texture_name,
-            uv_velocity,
-            colors,
-            is_transparent
+            let matrix_floats = read_f32_data(reader, instance_count as usize * Self::FLOATS_PER_TRANSFORM)?;
+            let instances = matrix_floats.chunks_exact(Self::FLOATS_PER_TRANSFORM).map(glm::make_mat4).collect();
+
+            meshes.push(OzyLevelMesh {
+                path: format!("models/{}", filename),
+                instances
+            });
+        }
+
+        Ok(OzyLevel {
+            name: String::from(name),
+            meshes,
+            collision_path: format!("models/{}.ozt", name)
         })
     }
+}
+
+//Returns None if the reader is at EOF, but an UnexpectedEof error if it ends partway through the u32
+pub fn read_u32_or_eof<R: Read + ?Sized>(reader: &mut R) -> Result<Option<u32>> {
+    let mut buffer = [0; 4];
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => { break; }
+            Ok(n) => { filled += n; }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => { return Err(e); }
+        }
+    }
 
+    match filled {
+        0 => { Ok(None) }
+        4 => { Ok(Some(u32::from_le_bytes(buffer))) }
+        _ => { Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "file ended inside a u32")) }
+    }
 }
 
 pub fn read_u32_from_le_bytes(bytes: &[u8], offset: usize) -> u32 {
     u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
 }
 
-pub fn read_u8(file: &mut File) -> Result<u8> {
+pub fn read_u8<R: Read + ?Sized>(reader: &mut R) -> Result<u8> {
     let mut n = [0];
-    match file.read_exact(&mut n) {
-        Ok(_) => { Ok(u8::from_le_bytes(n)) }
-        Err(e) => { Err(e) }
-    }    
+    reader.read_exact(&mut n)?;
+    Ok(u8::from_le_bytes(n))
 }
 
-pub fn read_u8_data(file: &mut File, count: usize) -> Result<Vec<u8>> {
+pub fn read_u8_data<R: Read + ?Sized>(reader: &mut R, count: usize) -> Result<Vec<u8>> {
     let mut bytes = vec![0; count];
-    if let Err(e) = file.read_exact(bytes.as_mut_slice()) {
-        return Err(e);
-	}
+    reader.read_exact(bytes.as_mut_slice())?;
 	Ok(bytes)
 }
 
-pub fn read_u32(file: &mut File) -> Result<u32> {
+pub fn read_u32<R: Read + ?Sized>(reader: &mut R) -> Result<u32> {
 	let mut buffer = [0; 4];
-	match file.read_exact(&mut buffer) {
-		Ok(_) => { Ok(u32::from_le_bytes(buffer)) }
-		Err(e) => { Err(e) }
-	}
+	reader.read_exact(&mut buffer)?;
+	Ok(u32::from_le_bytes(buffer))
 }
 
-pub fn read_u32_data(file: &mut File, count: usize) -> Result<Vec<u32>> {
+pub fn read_u32_data<R: Read + ?Sized>(reader: &mut R, count: usize) -> Result<Vec<u32>> {
 	let mut bytes = vec![0; count * mem::size_of::<u32>()];
-	if let Err(e) = file.read_exact(bytes.as_mut_slice()) {
-        return Err(e);
-	}
+	reader.read_exact(bytes.as_mut_slice())?;
 
 	let mut v = Vec::with_capacity(count);
-	for i in (0..bytes.len()).step_by(mem::size_of::<u32>()) {
-		let b = [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
-		v.push(u32::from_le_bytes(b));
+	for b in bytes.chunks_exact(mem::size_of::<u32>()) {
+		v.push(u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
 	}
 	Ok(v)
 }
 
-pub fn read_u32_array(file: &mut File) -> Result<Vec<u32>> {
-    let count = read_u32(file).unwrap();
-    read_u32_data(file, count as usize)
+pub fn read_u32_array<R: Read + ?Sized>(reader: &mut R) -> Result<Vec<u32>> {
+    let count = read_u32(reader)?;
+    read_u32_data(reader, count as usize)
 }
 
-pub fn read_u16_data(file: &mut File, count: usize) -> Result<Vec<u16>> {
+pub fn read_u16_data<R: Read + ?Sized>(reader: &mut R, count: usize) -> Result<Vec<u16>> {
 	let mut bytes = vec![0; count * mem::size_of::<u16>()];
-	if let Err(e) = file.read_exact(bytes.as_mut_slice()) {
-        return Err(e);
-	}
+	reader.read_exact(bytes.as_mut_slice())?;
 
 	let mut v = Vec::with_capacity(count);
-	for i in (0..bytes.len()).step_by(mem::size_of::<u16>()) {
-		let b = [bytes[i], bytes[i + 1]];
-		v.push(u16::from_le_bytes(b));
+	for b in bytes.chunks_exact(mem::size_of::<u16>()) {
+		v.push(u16::from_le_bytes([b[0], b[1]]));
 	}
 	Ok(v)
 }
 
-pub fn read_f32(file: &mut File) -> Result<f32> {
+pub fn read_f32<R: Read + ?Sized>(reader: &mut R) -> Result<f32> {
     let mut bytes = [0u8; 4];
-	match file.read_exact(&mut bytes) {
-		Ok(_) => { Ok(f32::from_le_bytes(bytes)) }
-		Err(e) => { Err(e) }
-	}
+	reader.read_exact(&mut bytes)?;
+	Ok(f32::from_le_bytes(bytes))
 }
 
-pub fn read_f32_data(file: &mut File, count: usize) -> Result<Vec<f32>> {
+pub fn read_f32_data<R: Read + ?Sized>(reader: &mut R, count: usize) -> Result<Vec<f32>> {
 	let mut bytes = vec![0; count * mem::size_of::<f32>()];
-	if let Err(e) = file.read_exact(bytes.as_mut_slice()) {
-        return Err(e);
-	}
+	reader.read_exact(bytes.as_mut_slice())?;
 
 	let mut v = Vec::with_capacity(count);
-	for i in (0..bytes.len()).step_by(mem::size_of::<f32>()) {
-		let b = [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
-		v.push(f32::from_le_bytes(b));
+	for b in bytes.chunks_exact(mem::size_of::<f32>()) {
+		v.push(f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
 	}
 	Ok(v)    
 }
 
-pub fn read_f32_array(file: &mut File) -> Result<Vec<f32>> {
-    let count = read_u32(file).unwrap();
-    read_f32_data(file, count as usize)
+pub fn read_f32_array<R: Read + ?Sized>(reader: &mut R) -> Result<Vec<f32>> {
+    let count = read_u32(reader)?;
+    read_f32_data(reader, count as usize)
 }
 
-pub fn read_pascal_strings(file: &mut File, count: usize) -> Result<Vec<String>> {	
-	let mut int_buffer = [0; 4];
-	let mut strings = Vec::with_capacity(count as usize);
+pub fn read_pascal_strings<R: Read + ?Sized>(reader: &mut R, count: usize) -> Result<Vec<String>> {	
+	let mut strings = Vec::with_capacity(count);
 	for _ in 0..count {
 		//Read the name's length
-		if let Err(e) = file.read_exact(&mut int_buffer) {
-			return Err(e);
-		}
+		let length = read_u32(reader)?;
 
 		//Read the contents of the string into a buffer
-		let mut utf8_buffer = vec![0; u32::from_le_bytes(int_buffer) as usize];
-		if let Err(e) = file.read_exact(&mut utf8_buffer) {
-			return Err(e);
-		}
+		let utf8_buffer = read_u8_data(reader, length as usize)?;
 
 		//Turn buffer of utf-8 bytes into String
 		match String::from_utf8(utf8_buffer) {
 			Ok(name) => { strings.push(name); }
 			Err(e) => {
-				panic!("read_pascal_strings(): Error when making String from utf8: {}", e);
+				return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("read_pascal_strings(): Error when making String from utf8: {}", e)));
 			}
 		}
 	}
 	Ok(strings)
 }
 
-pub fn write_pascal_strings(file: &mut File, strs: &[&str]) -> Result<()> {
-    for i in 0..strs.len() {
-        let s = strs[i];
+pub fn write_pascal_strings<W: Write + ?Sized>(writer: &mut W, strs: &[&str]) -> Result<()> {
+    for s in strs {
         let l = s.len() as u32;
-        if let Err(e) = file.write(&u32::to_le_bytes(l)) {
-            return Err(e)
-        }        
+        writer.write_all(&u32::to_le_bytes(l))?;
+        writer.write_all(s.as_bytes())?;
+    }
+    Ok(())
+}
 
-        if let Err(e) = file.write(s.as_bytes()) {
-            return Err(e)
-        }
+pub fn write_u32<W: Write + ?Sized>(writer: &mut W, n: u32) -> Result<()> {
+    writer.write_all(&u32::to_le_bytes(n))
+}
+
+pub fn write_u32_data<W: Write + ?Sized>(writer: &mut W, data: &[u32]) -> Result<()> {
+    for n in data {
+        writer.write_all(&u32::to_le_bytes(*n))?;
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn write_u32_array<W: Write + ?Sized>(writer: &mut W, data: &[u32]) -> Result<()> {
+    write_u32(writer, data.len() as u32)?;
+    write_u32_data(writer, data)
+}
+
+pub fn write_f32<W: Write + ?Sized>(writer: &mut W, n: f32) -> Result<()> {
+    writer.write_all(&f32::to_le_bytes(n))
+}
+
+pub fn write_f32_data<W: Write + ?Sized>(writer: &mut W, data: &[f32]) -> Result<()> {
+    for n in data {
+        writer.write_all(&f32::to_le_bytes(*n))?;
+    }
+    Ok(())
+}
+
+pub fn write_f32_array<W: Write + ?Sized>(writer: &mut W, data: &[f32]) -> Result<()> {
+    write_u32(writer, data.len() as u32)?;
+    write_f32_data(writer, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level_bytes(entries: &[(&str, u32)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (filename, instance_count) in entries {
+            write_pascal_strings(&mut bytes, &[filename]).unwrap();
+            write_u32(&mut bytes, *instance_count).unwrap();
+            for i in 0..*instance_count {
+                let translation = glm::translation(&glm::vec3(i as f32, 0.0, 0.0));
+                write_f32_data(&mut bytes, translation.as_slice()).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn level_reads_until_clean_eof() {
+        let bytes = level_bytes(&[("tree.ozy", 2), ("rock.ozy", 0)]);
+        let level = OzyLevel::from_bytes(&bytes, "forest").unwrap();
+        assert_eq!(level.meshes.len(), 2);
+        assert_eq!(level.meshes[0].path, "models/tree.ozy");
+        assert_eq!(level.meshes[0].instances[1], glm::translation(&glm::vec3(1.0, 0.0, 0.0)));
+        assert!(level.meshes[1].instances.is_empty());
+        assert_eq!(level.collision_path, "models/forest.ozt");
+
+        assert!(OzyLevel::from_bytes(&[], "empty").unwrap().meshes.is_empty());
+    }
+
+    #[test]
+    fn level_rejects_truncation_inside_an_entry() {
+        let bytes = level_bytes(&[("tree.ozy", 1), ("rock.ozy", 1)]);
+        let first_entry = 4 + "tree.ozy".len() + 4 + 64;
+
+        //Inside the next length prefix, inside the name, inside the count and inside the matrices
+        for &cut in &[first_entry + 2, first_entry + 6, first_entry + 4 + 8 + 1, bytes.len() - 1] {
+            let e = OzyLevel::from_bytes(&bytes[..cut], "forest").unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof, "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn level_rejects_absurd_instance_count() {
+        let mut bytes = Vec::new();
+        write_pascal_strings(&mut bytes, &["tree.ozy"]).unwrap();
+        write_u32(&mut bytes, u32::MAX).unwrap();
+        let e = OzyLevel::from_bytes(&bytes, "forest").unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    fn test_mesh(index_offset: u32) -> OzyMesh {
+        let width = 8;
+        let height = 4;
+        let mipmap_count = routines::calculate_mipcount(width, height);
+        let byte_count = bc7_mip_chain_size(width, height, mipmap_count);
+        let texture = OzyImage {
+            width,
+            height,
+            mipmap_count,
+            bc7_bytes: (0..byte_count).map(|i| { i as u8 }).collect()
+        };
+
+        let material = OzyMaterial {
+            base_color: [0.25, 0.5, 0.75, 1.0],
+            emissive_factor: [1.0, 0.0, 0.5],
+            base_roughness: 0.3,
+            base_metalness: 0.9,
+            color_bc7_idx: Some(0),
+            normal_bc7_idx: None,
+            arm_bc7_idx: Some(0),
+            emissive_bc7_idx: None
+        };
+
+        let primitive = OzyPrimitive {
+            indices: vec![index_offset, index_offset + 1, index_offset + 2],
+            vertex_positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            vertex_normals: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            vertex_tangents: vec![1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, -1.0],
+            vertex_uvs: vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            material_idx: 0
+        };
+
+        OzyMesh {
+            name: String::from("prop"),
+            textures: vec![texture],
+            materials: vec![material],
+            primitives: vec![primitive]
+        }
+    }
+
+    fn round_trip(mesh: &OzyMesh) -> OzyMesh {
+        let mut bytes = Vec::new();
+        mesh.write_to(&mut bytes).unwrap();
+        let from_bytes = OzyMesh::from_bytes(&bytes, &mesh.name).unwrap();
+        let from_reader = OzyMesh::from_reader(&mut std::io::Cursor::new(&bytes), &mesh.name).unwrap();
+        assert_eq!(from_bytes, from_reader);
+
+        //Writing what was read must reproduce the same bytes
+        let mut rewritten = Vec::new();
+        from_bytes.write_to(&mut rewritten).unwrap();
+        assert_eq!(bytes, rewritten);
+        from_bytes
+    }
+
+    #[test]
+    fn mesh_round_trips_with_u16_range_indices() {
+        let mesh = test_mesh(0);
+        assert_eq!(round_trip(&mesh), mesh);
+    }
+
+    #[test]
+    fn mesh_round_trips_with_u32_range_indices() {
+        let mesh = test_mesh(u16::MAX as u32 + 10);
+        assert_eq!(round_trip(&mesh), mesh);
+    }
+
+    #[test]
+    fn mesh_round_trips_without_textures() {
+        let mut mesh = test_mesh(0);
+        mesh.textures.clear();
+        mesh.materials[0].color_bc7_idx = None;
+        mesh.materials[0].arm_bc7_idx = None;
+        assert_eq!(round_trip(&mesh), mesh);
+    }
+
+    #[test]
+    fn mesh_writer_rejects_wrong_bc7_length() {
+        let mut mesh = test_mesh(0);
+        mesh.textures[0].bc7_bytes.pop();
+        let e = mesh.write_to(&mut Vec::new()).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+
+        mesh.textures[0].bc7_bytes.extend_from_slice(&[0, 0]);
+        assert!(mesh.write_to(&mut Vec::new()).is_err());
+    }
+
+    fn obsolete_bytes(index_type: IndexType) -> Vec<u8> {
+        let mut bytes = vec![0u8];
+        write_pascal_strings(&mut bytes, &["grass"]).unwrap();
+        bytes.push(1);
+        write_f32_data(&mut bytes, &[0.5, 0.0]).unwrap();
+        let vertices = [1.0f32; 14];
+        write_u32(&mut bytes, (vertices.len() * mem::size_of::<f32>()) as u32).unwrap();
+        write_f32_data(&mut bytes, &vertices).unwrap();
+        write_u32(&mut bytes, (3 * mem::size_of::<u16>()) as u32).unwrap();
+        for i in 0..3u32 {
+            match index_type {
+                IndexType::U16 => { bytes.extend_from_slice(&(i as u16).to_le_bytes()); }
+                IndexType::U32 => { write_u32(&mut bytes, i).unwrap(); }
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn headerless_mesh_loads_as_version_0() {
+        let mesh = test_mesh(0);
+        let mut bytes = Vec::new();
+        mesh.write_to(&mut bytes).unwrap();
+        let body = &bytes[8..];
+        assert_eq!(OzyMesh::from_bytes(body, "prop").unwrap(), mesh);
+        match AnyOzyMesh::from_bytes(body, "prop").unwrap() {
+            AnyOzyMesh::Current(m) => { assert_eq!(m, mesh); }
+            AnyOzyMesh::Obsolete(_) => { panic!("headerless OzyMesh detected as obsolete"); }
+        }
+
+        //Trailing bytes mean it wasn't really a headerless OzyMesh
+        let mut padded = body.to_vec();
+        padded.push(0);
+        assert!(matches!(OzyMesh::from_bytes(&padded, "prop"), Err(OzyError::UnrecognizedFormat)));
+    }
+
+    #[test]
+    fn truncated_headerless_mesh_is_truncated() {
+        let mesh = test_mesh(0);
+        let mut bytes = Vec::new();
+        mesh.write_to(&mut bytes).unwrap();
+        let body = &bytes[8..];
+
+        //Once the counts have been read it's clearly a headerless OzyMesh, so the real error comes through
+        for len in 12..body.len() {
+            match OzyMesh::from_bytes(&body[..len], "prop") {
+                Err(OzyError::Truncated(section)) => { assert_ne!(section, OzySection::Header); }
+                other => { panic!("{} bytes: expected Truncated, got {:?}", len, other); }
+            }
+        }
+        assert!(matches!(AnyOzyMesh::from_bytes(&body[..body.len() - 1], "prop"), Err(OzyError::Truncated(_))));
+
+        //Too short to even hold the counts
+        for len in 4..12 {
+            assert!(matches!(OzyMesh::from_bytes(&body[..len], "prop"), Err(OzyError::UnrecognizedFormat)));
+        }
+    }
+
+    #[test]
+    fn explicit_version_0_header_is_accepted() {
+        let mesh = test_mesh(0);
+        let mut bytes = Vec::new();
+        mesh.write_to(&mut bytes).unwrap();
+        bytes[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(OzyMesh::from_bytes(&bytes, "prop").unwrap(), mesh);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut bytes = Vec::new();
+        test_mesh(0).write_to(&mut bytes).unwrap();
+        bytes[4..8].copy_from_slice(&(OzyMesh::VERSION + 1).to_le_bytes());
+        assert!(matches!(OzyMesh::from_bytes(&bytes, "prop"), Err(OzyError::UnsupportedVersion(v)) if v == OzyMesh::VERSION + 1));
+    }
+
+    #[test]
+    fn obsolete_meshes_are_detected() {
+        for &index_type in [IndexType::U16, IndexType::U32].iter() {
+            let bytes = obsolete_bytes(index_type);
+            assert!(matches!(OzyMesh::from_bytes(&bytes, "grass"), Err(OzyError::UnrecognizedFormat)));
+            match AnyOzyMesh::from_bytes(&bytes, "grass").unwrap() {
+                AnyOzyMesh::Obsolete(m) => {
+                    assert_eq!(m.texture_name, "grass");
+                    assert_eq!(m.vertex_array.indices, vec![0, 1, 2]);
+                }
+                AnyOzyMesh::Current(_) => { panic!("obsolete mesh detected as current"); }
+            }
+        }
+
+        assert!(matches!(AnyOzyMesh::from_bytes(&[7; 5], "junk"), Err(OzyError::UnrecognizedFormat)));
+    }
+}
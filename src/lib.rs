@@ -3,8 +3,11 @@ extern crate nalgebra_glm as glm;
 extern crate ispc_texcomp as ispc;
 
 pub mod collision;
+pub mod dds;
 pub mod io;
+pub mod loader;
 pub mod structs;
+pub mod texcomp;
 pub mod glutil;
 pub mod prims;
 pub mod render;
@@ -0,0 +1,355 @@
+use gl::types::*;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use crate::collision::MeshCollision;
+use crate::glutil::{self, ColorSpace, Texture};
+use crate::io::OzyMesh;
+use crate::render::{FallbackTextures, GpuMesh};
+
+//Anything a decoder or a finisher can fail with
+pub type LoadError = Box<dyn Error + Send + Sync>;
+
+type Job = Box<dyn FnOnce() + Send>;
+type Decoded = Result<Box<dyn Any + Send>, LoadError>;
+type Finisher = Box<dyn FnOnce(Decoded) -> bool>;
+
+pub enum LoadState<T> {
+	Pending,
+	Ready(Rc<T>),
+	Failed(Rc<LoadError>)
+}
+
+impl<T> Clone for LoadState<T> {
+	fn clone(&self) -> Self {
+		match self {
+			LoadState::Pending => { LoadState::Pending }
+			LoadState::Ready(asset) => { LoadState::Ready(asset.clone()) }
+			LoadState::Failed(e) => { LoadState::Failed(e.clone()) }
+		}
+	}
+}
+
+//Shared slot that AssetLoader::poll() fills in once the asset has been decoded and uploaded
+pub struct LoadHandle<T> {
+	state: Rc<RefCell<LoadState<T>>>
+}
+
+impl<T> Clone for LoadHandle<T> {
+	fn clone(&self) -> Self {
+		LoadHandle { state: self.state.clone() }
+	}
+}
+
+impl<T> LoadHandle<T> {
+	fn new() -> Self {
+		LoadHandle { state: Rc::new(RefCell::new(LoadState::Pending)) }
+	}
+
+	pub fn state(&self) -> LoadState<T> {
+		self.state.borrow().clone()
+	}
+
+	pub fn get(&self) -> Option<Rc<T>> {
+		match &*self.state.borrow() {
+			LoadState::Ready(asset) => { Some(asset.clone()) }
+			_ => { None }
+		}
+	}
+
+	pub fn error(&self) -> Option<Rc<LoadError>> {
+		match &*self.state.borrow() {
+			LoadState::Failed(e) => { Some(e.clone()) }
+			_ => { None }
+		}
+	}
+
+	pub fn is_pending(&self) -> bool {
+		matches!(*self.state.borrow(), LoadState::Pending)
+	}
+
+	//True if both handles refer to the same request
+	pub fn same_request(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.state, &other.state)
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+	pub requested: usize,
+	pub finished: usize,		//Includes failures
+	pub failed: usize
+}
+
+impl LoadProgress {
+	pub fn pending(&self) -> usize {
+		self.requested - self.finished
+	}
+
+	//In [0, 1] for loading screens. Nothing requested counts as done
+	pub fn fraction(&self) -> f32 {
+		if self.requested == 0 { 1.0 } else { self.finished as f32 / self.requested as f32 }
+	}
+}
+
+//Decodes files on a pool of worker threads and hands the results back to the thread that calls poll()
+//Requests for the same path and asset type that are still in flight share a single handle
+pub struct AssetLoader {
+	job_sender: Option<mpsc::Sender<Job>>,
+	result_sender: mpsc::Sender<(u64, Decoded)>,
+	result_receiver: mpsc::Receiver<(u64, Decoded)>,
+	workers: Vec<thread::JoinHandle<()>>,
+	cancelled: Arc<AtomicBool>,		//Set on drop so the workers skip whatever is still queued
+	in_flight: HashMap<(TypeId, String), Box<dyn Any>>,
+	finishers: HashMap<u64, ((TypeId, String), Finisher)>,
+	next_id: u64,
+	progress: LoadProgress
+}
+
+impl AssetLoader {
+	pub fn new(worker_count: usize) -> Self {
+		let (job_sender, job_receiver) = mpsc::channel::<Job>();
+		let (result_sender, result_receiver) = mpsc::channel();
+		let job_receiver = Arc::new(Mutex::new(job_receiver));
+		let cancelled = Arc::new(AtomicBool::new(false));
+
+		let workers = (0..usize::max(worker_count, 1)).map(|i| {
+			let receiver = job_receiver.clone();
+			let cancelled = cancelled.clone();
+			thread::Builder::new().name(format!("ozy-loader-{}", i)).spawn(move || {
+				loop {
+					//The lock is released before the job runs
+					let job = match receiver.lock() {
+						Ok(r) => { r.recv() }
+						Err(_) => { break; }
+					};
+					match job {
+						Ok(_) if cancelled.load(Ordering::Acquire) => { break; }
+						Ok(job) => { job(); }
+						Err(_) => { break; }		//The loader has been dropped
+					}
+				}
+			}).expect("Failed to spawn loader thread")
+		}).collect();
+
+		AssetLoader {
+			job_sender: Some(job_sender),
+			result_sender,
+			result_receiver,
+			workers,
+			cancelled,
+			in_flight: HashMap::new(),
+			finishers: HashMap::new(),
+			next_id: 0,
+			progress: LoadProgress::default()
+		}
+	}
+
+	//One worker per core, leaving one for the render thread
+	pub fn with_default_workers() -> Self {
+		let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+		Self::new(cores - 1)
+	}
+
+	//decode() runs on a worker thread, finish() runs inside poll() and is where GL calls belong
+	pub fn request<D, T, Dec, Fin>(&mut self, path: &str, decode: Dec, finish: Fin) -> LoadHandle<T>
+	where
+		D: Send + 'static,
+		T: 'static,
+		Dec: FnOnce(&str) -> Result<D, LoadError> + Send + 'static,
+		Fin: FnOnce(D) -> Result<T, LoadError> + 'static
+	{
+		self.request_keyed(path, path, decode, finish)
+	}
+
+	//request() with a dedupe key other than the path, for assets whose result also depends on load options
+	pub fn request_keyed<D, T, Dec, Fin>(&mut self, key: &str, path: &str, decode: Dec, finish: Fin) -> LoadHandle<T>
+	where
+		D: Send + 'static,
+		T: 'static,
+		Dec: FnOnce(&str) -> Result<D, LoadError> + Send + 'static,
+		Fin: FnOnce(D) -> Result<T, LoadError> + 'static
+	{
+		let key = (TypeId::of::<T>(), String::from(key));
+		if let Some(handle) = self.in_flight.get(&key).and_then(|h| h.downcast_ref::<LoadHandle<T>>()) {
+			return handle.clone();
+		}
+
+		let id = self.next_id;
+		self.next_id += 1;
+		let handle = LoadHandle::new();
+		self.in_flight.insert(key.clone(), Box::new(handle.clone()));
+		self.progress.requested += 1;
+
+		let slot = handle.clone();
+		let finisher: Finisher = Box::new(move |decoded| {
+			let result = decoded.and_then(|data| {
+				match data.downcast::<D>() {
+					Ok(data) => { finish(*data) }
+					Err(_) => { Err(LoadError::from("Decoded asset had an unexpected type")) }
+				}
+			});
+			let state = match result {
+				Ok(asset) => { LoadState::Ready(Rc::new(asset)) }
+				Err(e) => { LoadState::Failed(Rc::new(e)) }
+			};
+			let succeeded = matches!(state, LoadState::Ready(_));
+			*slot.state.borrow_mut() = state;
+			succeeded
+		});
+		self.finishers.insert(id, (key, finisher));
+
+		let sender = self.result_sender.clone();
+		let path = String::from(path);
+		let job: Job = Box::new(move || {
+			let decoded = match panic::catch_unwind(AssertUnwindSafe(|| { decode(&path) })) {
+				Ok(Ok(data)) => { Ok(Box::new(data) as Box<dyn Any + Send>) }
+				Ok(Err(e)) => { Err(e) }
+				Err(_) => { Err(LoadError::from(format!("Decoder panicked while loading \"{}\"", path))) }
+			};
+			let _ = sender.send((id, decoded));
+		});
+
+		//The workers only exit once job_sender is dropped, so this can't fail while self is alive
+		if let Some(sender) = &self.job_sender {
+			let _ = sender.send(job);
+		}
+		handle
+	}
+
+	//Resolves every request whose decode has finished. Call once per frame on the thread that owns the GL context
+	//Returns the number of handles resolved
+	pub fn poll(&mut self) -> usize {
+		self.poll_limited(usize::MAX)
+	}
+
+	//Like poll() but resolves at most max_count requests, to spread uploads over several frames
+	pub fn poll_limited(&mut self, max_count: usize) -> usize {
+		let mut count = 0;
+		while count < max_count {
+			let (id, decoded) = match self.result_receiver.try_recv() {
+				Ok(result) => { result }
+				Err(_) => { break; }
+			};
+			if self.resolve(id, decoded) {
+				count += 1;
+			}
+		}
+		count
+	}
+
+	//Blocks until every outstanding request has been resolved
+	pub fn finish_all(&mut self) {
+		while !self.finishers.is_empty() {
+			match self.result_receiver.recv() {
+				Ok((id, decoded)) => { self.resolve(id, decoded); }
+				Err(_) => { break; }
+			}
+		}
+	}
+
+	fn resolve(&mut self, id: u64, decoded: Decoded) -> bool {
+		match self.finishers.remove(&id) {
+			Some((key, finish)) => {
+				self.in_flight.remove(&key);
+				if !finish(decoded) {
+					self.progress.failed += 1;
+				}
+				self.progress.finished += 1;
+				true
+			}
+			None => { false }
+		}
+	}
+
+	pub fn progress(&self) -> LoadProgress {
+		self.progress
+	}
+
+	pub fn is_idle(&self) -> bool {
+		self.finishers.is_empty()
+	}
+
+	//Restarts the counters for the next loading screen. Outstanding requests are counted again
+	pub fn reset_progress(&mut self) {
+		let outstanding = self.finishers.len();
+		self.progress = LoadProgress {
+			requested: outstanding,
+			finished: 0,
+			failed: 0
+		};
+	}
+
+	pub fn request_mesh_data(&mut self, path: &str) -> LoadHandle<OzyMesh> {
+		self.request(path, |p| { Ok(OzyMesh::load(p)?) }, Ok)
+	}
+
+	pub fn request_mesh(&mut self, path: &str, fallbacks: Rc<FallbackTextures>) -> LoadHandle<GpuMesh> {
+		self.request(path, |p| { Ok(OzyMesh::load(p)?) }, move |mesh| {
+			unsafe { Ok(GpuMesh::new(&mesh, &fallbacks)) }
+		})
+	}
+
+	pub fn request_texture(&mut self, path: &str, parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> LoadHandle<Texture> {
+		let key = format!("{}|{:?}|{:?}", path, color_space, parameters);
+		let parameters = parameters.to_vec();
+		self.request_keyed(&key, path, move |p| { Ok(glutil::image_data_from_path(p, color_space)?) }, move |image_data| {
+			unsafe { Ok(Texture::from_raw(glutil::load_texture_from_data(image_data, &parameters))) }
+		})
+	}
+
+	pub fn request_collision(&mut self, path: &str) -> LoadHandle<MeshCollision> {
+		self.request(path, |p| { Ok(MeshCollision::load(p)?) }, Ok)
+	}
+}
+
+impl Drop for AssetLoader {
+	fn drop(&mut self) {
+		//Each worker finishes the job it's running and exits without starting any of the queued ones
+		self.cancelled.store(true, Ordering::Release);
+		self.job_sender = None;
+		for worker in self.workers.drain(..) {
+			let _ = worker.join();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::atomic::AtomicUsize;
+	use std::time::Duration;
+
+	#[test]
+	fn requests_resolve_on_poll() {
+		let mut loader = AssetLoader::new(2);
+		let doubled = loader.request("21", |p| { Ok(p.parse::<u32>()?) }, |n| { Ok(n * 2) });
+		let failed = loader.request("x", |p| { Ok(p.parse::<u32>()?) }, Ok);
+		assert!(doubled.is_pending());
+		assert!(doubled.same_request(&loader.request("21", |_| { Ok(0u32) }, Ok)));
+
+		loader.finish_all();
+		assert_eq!(doubled.get().as_deref(), Some(&42));
+		assert!(failed.error().is_some());
+		assert_eq!(loader.progress(), LoadProgress { requested: 2, finished: 2, failed: 1 });
+	}
+
+	#[test]
+	fn dropping_skips_queued_jobs() {
+		let started = Arc::new(AtomicUsize::new(0));
+		let mut loader = AssetLoader::new(1);
+		loader.request("slow", |_| { thread::sleep(Duration::from_millis(200)); Ok(()) }, Ok);
+		for i in 0..100 {
+			let started = started.clone();
+			loader.request(&i.to_string(), move |_| { started.fetch_add(1, Ordering::SeqCst); Ok(()) }, Ok);
+		}
+		drop(loader);
+		assert_eq!(started.load(Ordering::SeqCst), 0);
+	}
+}
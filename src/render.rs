@@ -1,8 +1,14 @@
 use gl::types::*;
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::hash::Hash;
 use std::ptr;
+use std::rc::Rc;
+use std::os::raw::c_void;
 use crate::{glutil};
-use glutil::ColorSpace;
+use crate::io::{OzyError, OzyMesh, OzyPrimitive};
+use crate::structs::ImageData;
+use glutil::{AttributeType, ColorSpace, IndexData, IndexedVertexArray, Program, ShaderError, Texture, VertexLayout};
 
 const DEFAULT_TEX_PARAMS: [(GLenum, GLenum); 4] = [
 	(gl::TEXTURE_WRAP_S, gl::REPEAT),
@@ -94,39 +100,362 @@ pub struct StaticGeometry {
     pub index_count: GLsizei
 }
 
+//A cache of loaded assets handed out as reference-counted handles
+//Dropping an asset's last handle frees it, so GL objects wrapped in Texture, Program, GpuMesh etc. are deleted then
+//The cache itself holds one reference, which unload() and evict_unused() give up
+pub struct AssetCache<K, A> {
+	map: HashMap<K, Rc<A>>
+}
+
+impl<K: Eq + Hash + Clone, A> Default for AssetCache<K, A> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<K: Eq + Hash + Clone, A> AssetCache<K, A> {
+	pub fn new() -> Self {
+		AssetCache {
+			map: HashMap::new()
+		}
+	}
+
+	pub fn get(&self, key: &K) -> Option<Rc<A>> {
+		self.map.get(key).cloned()
+	}
+
+	//Returns the cached asset for key, or loads and caches it. Failed loads aren't cached
+	pub fn fetch_with<E, F: FnOnce(&K) -> Result<A, E>>(&mut self, key: K, load: F) -> Result<Rc<A>, E> {
+		if let Some(asset) = self.map.get(&key) {
+			return Ok(asset.clone());
+		}
+		let asset = Rc::new(load(&key)?);
+		self.map.insert(key, asset.clone());
+		Ok(asset)
+	}
+
+	//Replaces whatever was cached under key
+	pub fn insert(&mut self, key: K, asset: A) -> Rc<A> {
+		let asset = Rc::new(asset);
+		self.map.insert(key, asset.clone());
+		asset
+	}
+
+	pub fn contains(&self, key: &K) -> bool {
+		self.map.contains_key(key)
+	}
+
+	//Drops the cache's reference. The asset lives on until any outstanding handles are dropped
+	pub fn unload(&mut self, key: &K) -> bool {
+		self.map.remove(key).is_some()
+	}
+
+	//Drops every asset that nothing outside the cache holds a handle to, returning how many were freed
+	pub fn evict_unused(&mut self) -> usize {
+		let before = self.map.len();
+		self.map.retain(|_, asset| Rc::strong_count(asset) > 1);
+		before - self.map.len()
+	}
+
+	pub fn clear(&mut self) {
+		self.map.clear();
+	}
+
+	pub fn len(&self) -> usize { self.map.len() }
+
+	pub fn is_empty(&self) -> bool { self.map.is_empty() }
+
+	pub fn keys(&self) -> impl Iterator<Item = &K> {
+		self.map.keys()
+	}
+}
+
+//The same image loaded with different parameters or color space is a different texture
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureKey {
+	pub path: String,
+	pub parameters: Vec<(GLenum, GLenum)>,
+	pub color_space: ColorSpace
+}
+
+impl TextureKey {
+	pub fn new(path: &str, parameters: &[(GLenum, GLenum)], color_space: ColorSpace) -> Self {
+		TextureKey {
+			path: String::from(path),
+			parameters: parameters.to_vec(),
+			color_space
+		}
+	}
+}
+
+//Shader programs are keyed by their (stage, path) list
+pub type ProgramKey = Vec<(GLenum, String)>;
+
+impl AssetCache<ProgramKey, Program> {
+	pub fn fetch_program(&mut self, sources: &[(GLenum, &str)]) -> Result<Rc<Program>, ShaderError> {
+		let key = sources.iter().map(|(stage, path)| (*stage, String::from(*path))).collect();
+		self.fetch_with(key, |_| { Program::from_files(sources) })
+	}
+}
+
+impl AssetCache<String, GpuMesh> {
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn fetch_mesh(&mut self, path: &str, fallbacks: &Rc<FallbackTextures>) -> Result<Rc<GpuMesh>, OzyError> {
+		self.fetch_with(String::from(path), |p| { Ok(GpuMesh::new(&OzyMesh::load(p)?, fallbacks)) })
+	}
+}
+
 pub struct TextureKeeper {
-    map: HashMap<String, u32>
+	pub textures: AssetCache<TextureKey, Texture>,
+	pub material_path_template: String		//{name} and {map} are replaced by fetch_material()'s arguments
+}
+
+impl Default for TextureKeeper {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl TextureKeeper {
-    pub fn new() -> Self {
-        TextureKeeper {
-            map: HashMap::new()
-        }
-    }
+	pub const DEFAULT_MATERIAL_PATH_TEMPLATE: &'static str = "materials/{name}/{map}.png";
 
-	pub fn fetch_texture(&mut self, path: &str, tex_params: &[(GLenum, GLenum)], color_space: ColorSpace) -> GLuint {
-		match self.map.get(path) {
-			Some(t) => { *t }
-			None => {
-				let name = glutil::load_texture(path, tex_params, color_space);
-				self.map.insert(String::from(path), name);
-				name
+	pub fn new() -> Self {
+		Self::with_material_path_template(Self::DEFAULT_MATERIAL_PATH_TEMPLATE)
+	}
+
+	pub fn with_material_path_template(template: &str) -> Self {
+		TextureKeeper {
+			textures: AssetCache::new(),
+			material_path_template: String::from(template)
+		}
+	}
+
+	//Images that fail to load are replaced with a placeholder, which is cached like any other texture
+	pub fn fetch_texture(&mut self, path: &str, tex_params: &[(GLenum, GLenum)], color_space: ColorSpace) -> Rc<Texture> {
+		let result: Result<_, Infallible> = self.textures.fetch_with(TextureKey::new(path, tex_params, color_space), |key| {
+			match Texture::from_path(&key.path, &key.parameters, key.color_space) {
+				Ok(texture) => { Ok(texture) }
+				Err(_) => { unsafe { Ok(Texture::from_raw(glutil::load_texture_from_data(ImageData::placeholder(), &key.parameters))) } }
 			}
+		});
+		match result {
+			Ok(texture) => { texture }
+			Err(never) => { match never {} }
 		}
 	}
 
-    pub fn fetch_material(&mut self, name: &str, map_type: &str, tex_params: &[(GLenum, GLenum)], color_space: ColorSpace) -> GLuint {
-		let texture_path = format!("materials/{}/{}.png", name, map_type);
-		match self.map.get(&texture_path) {
-			Some(t) => { *t }
-			None => {
-				let name = glutil::load_texture(&texture_path, tex_params, color_space);
-				self.map.insert(texture_path, name);
-				name
+	//fetch_texture() that doesn't cache anything when the image can't be loaded
+	pub fn try_fetch_texture(&mut self, path: &str, tex_params: &[(GLenum, GLenum)], color_space: ColorSpace) -> Result<Rc<Texture>, image::ImageError> {
+		self.textures.fetch_with(TextureKey::new(path, tex_params, color_space), |key| {
+			Texture::from_path(&key.path, &key.parameters, key.color_space)
+		})
+	}
+
+	pub fn material_path(&self, name: &str, map_type: &str) -> String {
+		self.material_path_template.replace("{name}", name).replace("{map}", map_type)
+	}
+
+	pub fn fetch_material(&mut self, name: &str, map_type: &str, tex_params: &[(GLenum, GLenum)], color_space: ColorSpace) -> Rc<Texture> {
+		let path = self.material_path(name, map_type);
+		self.fetch_texture(&path, tex_params, color_space)
+	}
+
+	pub fn unload(&mut self, path: &str, tex_params: &[(GLenum, GLenum)], color_space: ColorSpace) -> bool {
+		self.textures.unload(&TextureKey::new(path, tex_params, color_space))
+	}
+
+	pub fn evict_unused(&mut self) -> usize {
+		self.textures.evict_unused()
+	}
+}
+
+//Vertex attribute locations used by GpuMesh, matching mapped.vert
+pub const POSITION_ATTRIBUTE: GLuint = 0;
+pub const TANGENT_ATTRIBUTE: GLuint = 1;
+pub const BITANGENT_ATTRIBUTE: GLuint = 2;
+pub const NORMAL_ATTRIBUTE: GLuint = 3;
+pub const UV_ATTRIBUTE: GLuint = 4;
+
+const OZY_TEX_PARAMS: [(GLenum, GLenum); 4] = [
+	(gl::TEXTURE_WRAP_S, gl::REPEAT),
+	(gl::TEXTURE_WRAP_T, gl::REPEAT),
+	(gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR),
+	(gl::TEXTURE_MAG_FILTER, gl::LINEAR)
+];
+
+//1x1 textures bound in place of a material's missing maps
+pub struct FallbackTextures {
+	pub white: Texture,			//Used for missing color, arm, and emissive maps
+	pub flat_normal: Texture		//Tangent-space (0, 0, 1)
+}
+
+impl FallbackTextures {
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn new() -> Self {
+		FallbackTextures {
+			white: Self::solid_color([0xFF, 0xFF, 0xFF, 0xFF]),
+			flat_normal: Self::solid_color([0x80, 0x80, 0xFF, 0xFF])
+		}
+	}
+
+	unsafe fn solid_color(color: [u8; 4]) -> Texture {
+		let tex = Texture::new();
+		tex.bind(gl::TEXTURE_2D);
+		glutil::apply_texture_parameters(gl::TEXTURE_2D, &DEFAULT_TEX_PARAMS);
+		gl::TexStorage2D(gl::TEXTURE_2D, 1, gl::RGBA8, 1, 1);
+		gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, 1, 1, gl::RGBA, gl::UNSIGNED_BYTE, color.as_ptr() as *const c_void);
+		tex
+	}
+}
+
+//An OzyMaterial with its texture indices resolved to GL texture names
+#[derive(Clone, Debug)]
+pub struct GpuMaterial {
+	pub base_color: [f32; 4],
+	pub emissive_factor: [f32; 3],
+	pub base_roughness: f32,
+	pub base_metalness: f32,
+	pub color_texture: GLuint,
+	pub normal_texture: GLuint,
+	pub arm_texture: GLuint,
+	pub emissive_texture: GLuint
+}
+
+#[derive(Debug)]
+pub struct GpuPrimitive {
+	pub vertex_array: IndexedVertexArray,		//Owns the vertex and index buffers
+	pub material_idx: usize
+}
+
+//OzyPrimitive only stores tangents, with the handedness of the tangent frame in w, so the bitangents are rebuilt as cross(N, T) * w
+//A tangent without a w component is treated as right-handed
+pub fn compute_bitangents(normals: &[f32], tangents: &[f32], vertex_count: usize) -> Vec<f32> {
+	if vertex_count == 0 || normals.len() < vertex_count * 3 || tangents.len() < vertex_count * 3 { return Vec::new(); }
+	let tangent_components = usize::min(tangents.len() / vertex_count, 4);
+
+	let mut bitangents = Vec::with_capacity(vertex_count * 3);
+	for i in 0..vertex_count {
+		let n = glm::vec3(normals[3 * i], normals[3 * i + 1], normals[3 * i + 2]);
+		let t = &tangents[tangent_components * i..];
+		let handedness = if tangent_components == 4 && t[3] < 0.0 { -1.0 } else { 1.0 };
+		let b = glm::cross(&n, &glm::vec3(t[0], t[1], t[2])) * handedness;
+		bitangents.extend_from_slice(&[b.x, b.y, b.z]);
+	}
+	bitangents
+}
+
+impl GpuPrimitive {
+	//Each vertex attribute gets its own buffer, the same way OzyPrimitive stores them
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn new(primitive: &OzyPrimitive) -> Self {
+		//Component counts are derived from the positions, which are always three floats per vertex
+		let vertex_count = usize::max(primitive.vertex_positions.len() / 3, 1);
+		let bitangents = compute_bitangents(&primitive.vertex_normals, &primitive.vertex_tangents, vertex_count);
+		let attributes = [
+			(POSITION_ATTRIBUTE, &primitive.vertex_positions),
+			(NORMAL_ATTRIBUTE, &primitive.vertex_normals),
+			(TANGENT_ATTRIBUTE, &primitive.vertex_tangents),
+			(BITANGENT_ATTRIBUTE, &bitangents),
+			(UV_ATTRIBUTE, &primitive.vertex_uvs)
+		];
+
+		let mut layout = VertexLayout::new();
+		let mut buffers = Vec::with_capacity(attributes.len());
+		for (location, data) in attributes.iter() {
+			let components = usize::min(data.len() / vertex_count, 4);
+			if components == 0 { continue; }
+
+			layout = layout.next_buffer().attribute(*location, components as GLint, AttributeType::F32);
+			buffers.push(glutil::gpu_bytes(data));
+		}
+
+		let vertex_array = glutil::create_vertex_array(&layout, &buffers, IndexData::U32(&primitive.indices));
+		gl::BindVertexArray(0);
+
+		GpuPrimitive {
+			vertex_array,
+			material_idx: primitive.material_idx as usize
+		}
+	}
+
+	/// # Safety
+	/// The GL context this primitive belongs to must be current, with a program bound that reads its attributes.
+	pub unsafe fn draw(&self) {
+		self.vertex_array.draw(gl::TRIANGLES);
+	}
+}
+
+//An OzyMesh that has been uploaded to the GPU
+//Materials may refer to the fallback textures, so the mesh holds a reference to keep them alive
+pub struct GpuMesh {
+	pub name: String,
+	pub textures: Vec<Option<Texture>>,		//Indexed like OzyMesh::textures, with None for any that failed to upload
+	pub materials: Vec<GpuMaterial>,
+	pub primitives: Vec<GpuPrimitive>,
+	pub fallbacks: Rc<FallbackTextures>
+}
+
+impl GpuMesh {
+	/// # Safety
+	/// A GL context must be current on the calling thread.
+	pub unsafe fn new(mesh: &OzyMesh, fallbacks: &Rc<FallbackTextures>) -> Self {
+		//Color and emissive maps are sRGB, everything else is linear data
+		let mut color_spaces = vec![ColorSpace::Linear; mesh.textures.len()];
+		for material in &mesh.materials {
+			for idx in [material.color_bc7_idx, material.emissive_bc7_idx].iter().flatten() {
+				if let Some(space) = color_spaces.get_mut(*idx as usize) {
+					*space = ColorSpace::Gamma;
+				}
 			}
-        }
-    }
+		}
+
+		//A texture that fails to upload is treated like a missing one, so its materials get the fallbacks
+		let textures: Vec<Option<Texture>> = mesh.textures.iter().zip(color_spaces.iter()).enumerate().map(|(i, (image, space))| {
+			match glutil::load_ozy_image(image, &OZY_TEX_PARAMS, *space) {
+				Ok(texture) => { Some(texture) }
+				Err(e) => {
+					println!("Couldn't upload texture {} of \"{}\": {}", i, mesh.name, e);
+					None
+				}
+			}
+		}).collect();
+
+		let resolve = |idx: Option<u32>, fallback: GLuint| {
+			match idx.and_then(|i| textures.get(i as usize)).and_then(|t| t.as_ref()) {
+				Some(texture) => { texture.name }
+				None => { fallback }
+			}
+		};
+		let materials = mesh.materials.iter().map(|material| {
+			GpuMaterial {
+				base_color: material.base_color,
+				emissive_factor: material.emissive_factor,
+				base_roughness: material.base_roughness,
+				base_metalness: material.base_metalness,
+				color_texture: resolve(material.color_bc7_idx, fallbacks.white.name),
+				normal_texture: resolve(material.normal_bc7_idx, fallbacks.flat_normal.name),
+				arm_texture: resolve(material.arm_bc7_idx, fallbacks.white.name),
+				emissive_texture: resolve(material.emissive_bc7_idx, fallbacks.white.name)
+			}
+		}).collect();
+
+		let primitives = mesh.primitives.iter().map(|p| { GpuPrimitive::new(p) }).collect();
+
+		GpuMesh {
+			name: mesh.name.clone(),
+			textures,
+			materials,
+			primitives,
+			fallbacks: fallbacks.clone()
+		}
+	}
+
+	pub fn material(&self, primitive: &GpuPrimitive) -> Option<&GpuMaterial> {
+		self.materials.get(primitive.material_idx)
+	}
 }
 
 pub struct Framebuffer {
@@ -137,6 +466,8 @@ pub struct Framebuffer {
 }
 
 impl Framebuffer {
+    /// # Safety
+    /// The GL context this framebuffer belongs to must be current.
     pub unsafe fn bind(&self) {
         gl::BindFramebuffer(gl::FRAMEBUFFER, self.name);
         gl::Viewport(0, 0, self.size.0, self.size.1);
@@ -154,15 +485,19 @@ impl Drop for Framebuffer {
 }
 
 //A framebuffer object with color and depth attachments
+//Shadow targets only have a depth attachment, which is stored in texture
 pub struct RenderTarget {
     pub framebuffer: Framebuffer,
-    pub texture: GLuint,
-	pub color_attachment_view: GLuint,
+    pub texture: Texture,
+	pub depth_texture: Option<Texture>,
+	pub color_attachment_view: Option<Texture>,
 	pub msaa_samples: GLint,
 	pub color_buffer_internal_format: GLenum,
 }
 
 impl RenderTarget {
+    /// # Safety
+    /// A GL context must be current on the calling thread.
     pub unsafe fn new(size: (GLint, GLint), color_buffer_internal_format: GLenum) -> Self {
         let mut fbo = 0;
 		let mut texs = [0; 2];
@@ -234,13 +569,16 @@ impl RenderTarget {
 
 		RenderTarget {
 			framebuffer: f_buffer,
-			texture: color_tex,
-			color_attachment_view,
+			texture: Texture::from_raw(color_tex),
+			depth_texture: Some(Texture::from_raw(depth_tex)),
+			color_attachment_view: Some(Texture::from_raw(color_attachment_view)),
 			msaa_samples: 1,
 			color_buffer_internal_format
 		}
     }
 	
+	/// # Safety
+	/// A GL context must be current on the calling thread.
 	pub unsafe fn new_multisampled(size: (GLint, GLint), samples: GLint, color_buffer_internal_format: GLenum) -> Self {
         let mut fbo = 0;
 		let mut texs = [0; 2];
@@ -316,13 +654,16 @@ impl RenderTarget {
 
 		RenderTarget {
 			framebuffer: f_buffer,
-			texture: color_tex,
-			color_attachment_view,
+			texture: Texture::from_raw(color_tex),
+			depth_texture: Some(Texture::from_raw(depth_tex)),
+			color_attachment_view: Some(Texture::from_raw(color_attachment_view)),
 			msaa_samples: samples,
 			color_buffer_internal_format
 		}
     }
 
+    /// # Safety
+    /// A GL context must be current on the calling thread.
     pub unsafe fn new_shadow(size: (GLint, GLint)) -> Self {
         let mut shadow_framebuffer = 0;
 		let mut shadow_texture = 0;
@@ -364,22 +705,51 @@ impl RenderTarget {
 
 		RenderTarget {
 			framebuffer,
-			texture: shadow_texture,
-			color_attachment_view: 0,
+			texture: Texture::from_raw(shadow_texture),
+			depth_texture: None,
+			color_attachment_view: None,
 			msaa_samples: 1,
 			color_buffer_internal_format: 0
 		}
 	}
 
+    /// # Safety
+    /// The GL context this render target belongs to must be current.
     pub unsafe fn bind(&self) { self.framebuffer.bind(); }
 
+    //The old framebuffer and its textures are deleted when the old value is dropped by the assignment
+    /// # Safety
+    /// The GL context this render target belongs to must be current.
     pub unsafe fn resize(&mut self, size: (u32, u32)) {
-		let old_name = self.framebuffer.name;
-		*self = if self.msaa_samples == 1 {
+		*self = if self.depth_texture.is_none() {
+			Self::new_shadow((size.0 as GLint, size.1 as GLint))
+		} else if self.msaa_samples == 1 {
 			Self::new((size.0 as GLint, size.1 as GLint), self.color_buffer_internal_format)
 		} else {
 			Self::new_multisampled((size.0 as GLint, size.1 as GLint), self.msaa_samples, self.color_buffer_internal_format)
 		};
-        gl::DeleteFramebuffers(1, &old_name);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn bitangents_follow_tangent_handedness() {
+		let normals = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+		let tangents = [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, -1.0];
+		assert_eq!(compute_bitangents(&normals, &tangents, 2), vec![0.0, 1.0, 0.0, 0.0, -1.0, 0.0]);
+
+		//Three component tangents are right-handed
+		let tangents = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+		assert_eq!(compute_bitangents(&normals, &tangents, 2), vec![0.0, 1.0, 0.0, 0.0, 1.0, 0.0]);
+	}
+
+	#[test]
+	fn bitangents_need_normals_and_tangents() {
+		assert!(compute_bitangents(&[], &[1.0, 0.0, 0.0, 1.0], 1).is_empty());
+		assert!(compute_bitangents(&[0.0, 0.0, 1.0], &[], 1).is_empty());
+		assert!(compute_bitangents(&[], &[], 0).is_empty());
+	}
+}
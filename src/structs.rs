@@ -50,7 +50,22 @@ pub struct ImageData {
 	pub width: i32,
 	pub height: i32,
 	pub format: GLenum,
-	pub internal_format: GLenum
+	pub internal_format: GLenum,
+	pub data_type: GLenum			//UNSIGNED_BYTE, UNSIGNED_SHORT, or FLOAT, with data in native byte order
+}
+
+impl ImageData {
+	//1x1 opaque black, for when an image couldn't be loaded
+	pub fn placeholder() -> Self {
+		ImageData {
+			data: vec![0, 0, 0, 0xFF],
+			width: 1,
+			height: 1,
+			format: gl::RGBA,
+			internal_format: gl::SRGB8_ALPHA8,
+			data_type: gl::UNSIGNED_BYTE
+		}
+	}
 }
 
 //A wrapper for the useful Vec<Option<T>> pattern
@@ -0,0 +1,532 @@
+use image::DynamicImage;
+use crate::dds::{DDSFormat, DDSTexture, DXGI_FORMAT};
+use crate::glutil::ColorSpace;
+use crate::io::OzyImage;
+use crate::routines;
+use crate::structs::ImageData;
+
+//Block compression formats that ispc-texcomp can encode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockFormat {
+    BC1,        //RGB with 1-bit alpha
+    BC3,        //RGBA
+    BC4,        //R
+    BC5,        //RG, for tangent-space normal maps
+    BC6H,       //Unsigned HDR RGB
+    BC7         //RGB(A), what OzyMesh uses for everything
+}
+
+impl BlockFormat {
+    pub fn output_size(&self, width: u32, height: u32) -> usize {
+        match self {
+            BlockFormat::BC1 => { ispc::bc1::calc_output_size(width, height) }
+            BlockFormat::BC3 => { ispc::bc3::calc_output_size(width, height) }
+            BlockFormat::BC4 => { ispc::bc4::calc_output_size(width, height) }
+            BlockFormat::BC5 => { ispc::bc5::calc_output_size(width, height) }
+            BlockFormat::BC6H => { ispc::bc6h::calc_output_size(width, height) }
+            BlockFormat::BC7 => { ispc::bc7::calc_output_size(width, height) }
+        }
+    }
+
+    pub fn dxgi_format(&self, color_space: ColorSpace) -> DXGI_FORMAT {
+        let gamma = color_space == ColorSpace::Gamma;
+        match self {
+            BlockFormat::BC1 => { if gamma { DXGI_FORMAT::BC1_UNORM_SRGB } else { DXGI_FORMAT::BC1_UNORM } }
+            BlockFormat::BC3 => { if gamma { DXGI_FORMAT::BC3_UNORM_SRGB } else { DXGI_FORMAT::BC3_UNORM } }
+            BlockFormat::BC4 => { DXGI_FORMAT::BC4_UNORM }
+            BlockFormat::BC5 => { DXGI_FORMAT::BC5_UNORM }
+            BlockFormat::BC6H => { DXGI_FORMAT::BC6H_UF16 }
+            BlockFormat::BC7 => { if gamma { DXGI_FORMAT::BC7_UNORM_SRGB } else { DXGI_FORMAT::BC7_UNORM } }
+        }
+    }
+}
+
+//Trades encoding time for quality in the formats that have encoder settings (BC6H and BC7)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionQuality {
+    Fast,
+    Basic,
+    Slow
+}
+
+//An RGBA image with f32 channels, which is what mip chains are generated from
+//Pixels are stored in whatever encoding the source used, so 8-bit color images are usually still gamma-encoded
+#[derive(Clone, Debug)]
+pub struct RgbaImage32F {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<f32>
+}
+
+impl RgbaImage32F {
+    pub fn new(width: u32, height: u32, pixels: Vec<f32>) -> Self {
+        assert_eq!(pixels.len(), 4 * width as usize * height as usize, "RgbaImage32F::new(): pixel data is the wrong size");
+        RgbaImage32F {
+            width,
+            height,
+            pixels
+        }
+    }
+
+    pub fn open(path: &str) -> Result<Self, image::ImageError> {
+        Ok(Self::from_dynamic_image(&image::open(path)?))
+    }
+
+    pub fn from_dynamic_image(image: &DynamicImage) -> Self {
+        let im = image.to_rgba32f();
+        let (width, height) = im.dimensions();
+        Self::new(width, height, im.into_raw())
+    }
+
+    //Understands what glutil::image_data_from_path() produces, i.e. 8-bit, 16-bit, and f32 components
+    pub fn from_image_data(image: &ImageData) -> Option<Self> {
+        let channels = match image.format {
+            gl::RED => { 1 }
+            gl::RG => { 2 }
+            gl::RGB => { 3 }
+            gl::RGBA => { 4 }
+            _ => { return None; }
+        };
+
+        let components: Vec<f32> = match image.data_type {
+            gl::UNSIGNED_BYTE => { image.data.iter().map(|v| *v as f32 / 255.0).collect() }
+            gl::UNSIGNED_SHORT => {
+                image.data.chunks_exact(2).map(|b| u16::from_ne_bytes([b[0], b[1]]) as f32 / 65535.0).collect()
+            }
+            gl::FLOAT => {
+                image.data.chunks_exact(4).map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect()
+            }
+            _ => { return None; }
+        };
+
+        let pixel_count = image.width as usize * image.height as usize;
+        if components.len() != pixel_count * channels {
+            return None;
+        }
+
+        let mut pixels = Vec::with_capacity(4 * pixel_count);
+        for texel in components.chunks_exact(channels) {
+            let mut rgba = [0.0, 0.0, 0.0, 1.0];
+            rgba[..channels].copy_from_slice(texel);
+            pixels.extend_from_slice(&rgba);
+        }
+        Some(Self::new(image.width as u32, image.height as u32, pixels))
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.pixels.chunks_exact(4).any(|p| { p[3] < 1.0 })
+    }
+
+    fn texel(&self, x: u32, y: u32) -> &[f32] {
+        let i = 4 * (y as usize * self.width as usize + x as usize);
+        &self.pixels[i..i + 4]
+    }
+
+    //Box-filters the image down to the next mip level
+    //Gamma-encoded color is averaged in linear space so that mips don't darken
+    pub fn downsample(&self, color_space: ColorSpace) -> Self {
+        let (width, height) = routines::mip_resolution(self.width, self.height, 1);
+        let mut pixels = Vec::with_capacity(4 * width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 4];
+                for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                    let texel = self.texel(u32::min(2 * x + sx, self.width - 1), u32::min(2 * y + sy, self.height - 1));
+                    for c in 0..4 {
+                        sum[c] += if c < 3 && color_space == ColorSpace::Gamma { srgb_to_linear(texel[c]) } else { texel[c] };
+                    }
+                }
+
+                for (c, s) in sum.iter().enumerate() {
+                    let average = s / 4.0;
+                    pixels.push(if c < 3 && color_space == ColorSpace::Gamma { linear_to_srgb(average) } else { average });
+                }
+            }
+        }
+        Self::new(width, height, pixels)
+    }
+
+    //The full chain of mips down to 1x1, starting with a copy of this image
+    pub fn mip_chain(&self, color_space: ColorSpace) -> Vec<Self> {
+        let mipmap_count = routines::calculate_mipcount(self.width, self.height);
+        let mut mips = Vec::with_capacity(mipmap_count as usize);
+        mips.push(self.clone());
+        for i in 1..mipmap_count as usize {
+            let mip = mips[i - 1].downsample(color_space);
+            mips.push(mip);
+        }
+        mips
+    }
+
+    //The encoders work on whole 4x4 blocks, so the image is padded out to a multiple of four by repeating its edges
+    fn padded_dimensions(&self) -> (u32, u32) {
+        (u32::max(4, (self.width + 3) & !3), u32::max(4, (self.height + 3) & !3))
+    }
+
+    fn padded_texels(&self) -> impl Iterator<Item = &[f32]> {
+        let (padded_width, padded_height) = self.padded_dimensions();
+        (0..padded_height).flat_map(move |y| {
+            (0..padded_width).map(move |x| { self.texel(u32::min(x, self.width - 1), u32::min(y, self.height - 1)) })
+        })
+    }
+
+    fn padded_rgba8(&self) -> Vec<u8> {
+        self.padded_texels().flat_map(|texel| {
+            texel.iter().map(|c| { (f32::clamp(*c, 0.0, 1.0) * 255.0).round() as u8 }).collect::<Vec<u8>>()
+        }).collect()
+    }
+
+    //BC6H takes RGBA half floats
+    fn padded_rgba16f(&self) -> Vec<u8> {
+        self.padded_texels().flat_map(|texel| {
+            texel.iter().flat_map(|c| { f32_to_f16(*c).to_le_bytes().to_vec() }).collect::<Vec<u8>>()
+        }).collect()
+    }
+}
+
+//A block-compressed image with its mip chain stored contiguously, largest mip first
+#[derive(Clone, Debug)]
+pub struct CompressedImage {
+    pub format: BlockFormat,
+    pub color_space: ColorSpace,
+    pub width: u32,
+    pub height: u32,
+    pub mipmap_count: u32,
+    pub bytes: Vec<u8>
+}
+
+impl CompressedImage {
+    //OzyMesh only stores BC7 textures, so anything else returns None
+    pub fn into_ozy_image(self) -> Option<OzyImage> {
+        if self.format != BlockFormat::BC7 {
+            return None;
+        }
+
+        Some(OzyImage {
+            width: self.width,
+            height: self.height,
+            mipmap_count: self.mipmap_count,
+            bc7_bytes: self.bytes
+        })
+    }
+
+    pub fn to_dds(&self) -> DDSTexture {
+        DDSTexture {
+            format: DDSFormat::Dxgi(self.format.dxgi_format(self.color_space)),
+            width: self.width,
+            height: self.height,
+            depth: 1,
+            mipmap_count: self.mipmap_count,
+            array_size: 1,
+            is_cubemap: false,
+            data: self.bytes.clone()
+        }
+    }
+}
+
+pub fn compress_mip(mip: &RgbaImage32F, format: BlockFormat, quality: CompressionQuality, has_alpha: bool) -> Vec<u8> {
+    let (width, height) = mip.padded_dimensions();
+
+    let compressed = if format == BlockFormat::BC6H {
+        let data = mip.padded_rgba16f();
+        let surface = ispc::RgbaSurface {
+            data: &data,
+            width,
+            height,
+            stride: width * 8
+        };
+        let settings = match quality {
+            CompressionQuality::Fast => { ispc::bc6h::very_fast_settings() }
+            CompressionQuality::Basic => { ispc::bc6h::basic_settings() }
+            CompressionQuality::Slow => { ispc::bc6h::slow_settings() }
+        };
+        ispc::bc6h::compress_blocks(&settings, &surface)
+    } else {
+        let data = mip.padded_rgba8();
+        let surface = ispc::RgbaSurface {
+            data: &data,
+            width,
+            height,
+            stride: width * 4
+        };
+        match format {
+            BlockFormat::BC1 => { ispc::bc1::compress_blocks(&surface) }
+            BlockFormat::BC3 => { ispc::bc3::compress_blocks(&surface) }
+            BlockFormat::BC4 => { ispc::bc4::compress_blocks(&surface) }
+            BlockFormat::BC5 => { ispc::bc5::compress_blocks(&surface) }
+            _ => {
+                let settings = match (quality, has_alpha) {
+                    (CompressionQuality::Fast, false) => { ispc::bc7::opaque_very_fast_settings() }
+                    (CompressionQuality::Basic, false) => { ispc::bc7::opaque_basic_settings() }
+                    (CompressionQuality::Slow, false) => { ispc::bc7::opaque_slow_settings() }
+                    (CompressionQuality::Fast, true) => { ispc::bc7::alpha_very_fast_settings() }
+                    (CompressionQuality::Basic, true) => { ispc::bc7::alpha_basic_settings() }
+                    (CompressionQuality::Slow, true) => { ispc::bc7::alpha_slow_settings() }
+                };
+                ispc::bc7::compress_blocks(&settings, &surface)
+            }
+        }
+    };
+
+    //Padding never adds blocks, so this is exactly the size the unpadded mip should take up
+    debug_assert_eq!(compressed.len(), format.output_size(mip.width, mip.height));
+    compressed
+}
+
+//Compresses every level of an already-generated mip chain
+pub fn compress_mip_chain(mips: &[RgbaImage32F], format: BlockFormat, color_space: ColorSpace, quality: CompressionQuality) -> CompressedImage {
+    let has_alpha = mips.iter().any(|mip| { mip.has_alpha() });
+    let mut bytes = Vec::new();
+    for mip in mips.iter() {
+        bytes.extend_from_slice(&compress_mip(mip, format, quality, has_alpha));
+    }
+
+    CompressedImage {
+        format,
+        color_space,
+        width: mips[0].width,
+        height: mips[0].height,
+        mipmap_count: mips.len() as u32,
+        bytes
+    }
+}
+
+pub fn compress_image(image: &RgbaImage32F, format: BlockFormat, color_space: ColorSpace, quality: CompressionQuality) -> CompressedImage {
+    compress_mip_chain(&image.mip_chain(color_space), format, color_space, quality)
+}
+
+//Loads any image the image crate can open and compresses it into something that can be put in OzyMesh::textures
+pub fn ozy_image_from_path(path: &str, color_space: ColorSpace, quality: CompressionQuality) -> Result<OzyImage, image::ImageError> {
+    let image = RgbaImage32F::open(path)?;
+    let compressed = compress_image(&image, BlockFormat::BC7, color_space, quality);
+    Ok(OzyImage {
+        width: compressed.width,
+        height: compressed.height,
+        mipmap_count: compressed.mipmap_count,
+        bc7_bytes: compressed.bytes
+    })
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        f32::powf((c + 0.055) / 1.055, 2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * f32::powf(c, 1.0 / 2.4) - 0.055
+    }
+}
+
+//IEEE 754 binary32 to binary16 with round-to-nearest-even
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7FFFFF;
+
+    //NaN and infinity
+    if exponent == 0xFF {
+        return sign | 0x7C00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1F {
+        //Too big, so it becomes infinity
+        sign | 0x7C00
+    } else if half_exponent <= 0 {
+        //Subnormal or zero
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x800000;
+        let shift = (14 - half_exponent) as u32;
+        let half_mantissa = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = remainder > halfway || (remainder == halfway && half_mantissa & 1 != 0);
+        sign | (half_mantissa + round_up as u32) as u16
+    } else {
+        let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+        let remainder = mantissa & 0x1FFF;
+        let round_up = remainder > 0x1000 || (remainder == 0x1000 && half & 1 != 0);
+        //A carry out of the mantissa correctly bumps the exponent, all the way to infinity if need be
+        sign | (half + round_up as u32) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f16_to_f64(half: u16) -> f64 {
+        let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((half >> 10) & 0x1F) as i32;
+        let mantissa = (half & 0x3FF) as f64;
+        match exponent {
+            0 => { sign * mantissa * f64::powi(2.0, -24) }
+            0x1F => { if mantissa == 0.0 { sign * f64::INFINITY } else { f64::NAN } }
+            _ => { sign * (1.0 + mantissa / 1024.0) * f64::powi(2.0, exponent - 15) }
+        }
+    }
+
+    fn gradient(width: u32, height: u32) -> RgbaImage32F {
+        let mut pixels = Vec::with_capacity(4 * width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let u = x as f32 / width as f32;
+                let v = y as f32 / height as f32;
+                pixels.extend_from_slice(&[u, v, 1.0 - u, 0.5 + 0.5 * u * v]);
+            }
+        }
+        RgbaImage32F::new(width, height, pixels)
+    }
+
+    #[test]
+    fn f32_to_f16_exact_values() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3C00);
+        assert_eq!(f32_to_f16(-2.0), 0xC000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7BFF);
+    }
+
+    #[test]
+    fn f32_to_f16_rounds_to_nearest_even() {
+        let ulp = f32::powi(2.0, -10);
+        //Exactly halfway, so the even mantissa wins in both directions
+        assert_eq!(f32_to_f16(1.0 + 0.5 * ulp), 0x3C00);
+        assert_eq!(f32_to_f16(1.0 + 1.5 * ulp), 0x3C02);
+        //Anything past halfway rounds up
+        assert_eq!(f32_to_f16(1.0 + 0.5 * ulp + f32::powi(2.0, -20)), 0x3C01);
+        assert_eq!(f32_to_f16(1.0 + 0.25 * ulp), 0x3C00);
+
+        //Rounding up out of the mantissa carries into the exponent
+        assert_eq!(f32_to_f16(2.0 - 0.25 * ulp), 0x4000);
+
+        //65520 is halfway between the largest half and the next power of two
+        assert_eq!(f32_to_f16(65519.0), 0x7BFF);
+        assert_eq!(f32_to_f16(65520.0), 0x7C00);
+        assert_eq!(f32_to_f16(1.0e6), 0x7C00);
+        assert_eq!(f32_to_f16(-1.0e6), 0xFC00);
+    }
+
+    #[test]
+    fn f32_to_f16_subnormals() {
+        let smallest = f32::powi(2.0, -24);
+        assert_eq!(f32_to_f16(smallest), 0x0001);
+        assert_eq!(f32_to_f16(-smallest), 0x8001);
+        assert_eq!(f32_to_f16(1023.0 * smallest), 0x03FF);
+        assert_eq!(f32_to_f16(f32::powi(2.0, -14)), 0x0400);
+
+        //Half of the smallest subnormal ties to zero, anything above it rounds up
+        assert_eq!(f32_to_f16(0.5 * smallest), 0x0000);
+        assert_eq!(f32_to_f16(0.75 * smallest), 0x0001);
+        assert_eq!(f32_to_f16(1.5 * smallest), 0x0002);
+        assert_eq!(f32_to_f16(2.5 * smallest), 0x0002);
+        assert_eq!(f32_to_f16(0.25 * smallest), 0x0000);
+        assert_eq!(f32_to_f16(-0.25 * smallest), 0x8000);
+        assert_eq!(f32_to_f16(f32::MIN_POSITIVE), 0x0000);
+
+        //Rounding the largest subnormal up gives the smallest normal
+        assert_eq!(f32_to_f16(1023.5 * smallest), 0x0400);
+    }
+
+    #[test]
+    fn f32_to_f16_inf_and_nan() {
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7C00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xFC00);
+
+        for nan in [f32::NAN, -f32::NAN, f32::from_bits(0x7F80_0001)].iter() {
+            let half = f32_to_f16(*nan);
+            assert_eq!(half & 0x7C00, 0x7C00);
+            assert_ne!(half & 0x3FF, 0);
+        }
+    }
+
+    #[test]
+    fn f32_to_f16_round_trips_every_half() {
+        for half in 0..=u16::MAX {
+            let value = f16_to_f64(half);
+            if value.is_nan() {
+                continue;
+            }
+            assert_eq!(f32_to_f16(value as f32), half, "{:#06x}", half);
+        }
+    }
+
+    #[test]
+    fn mip_chain_dimensions() {
+        let cases = [
+            vec![(5, 3), (2, 1), (1, 1)],
+            vec![(1, 7), (1, 3), (1, 1)],
+            vec![(8, 2), (4, 1), (2, 1), (1, 1)],
+            vec![(13, 7), (6, 3), (3, 1), (1, 1)],
+            vec![(1, 1)]
+        ];
+
+        for expected in cases.iter() {
+            let (width, height) = expected[0];
+            let image = gradient(width, height);
+            let downsampled = image.downsample(ColorSpace::Linear);
+            assert_eq!((downsampled.width, downsampled.height), expected[usize::min(1, expected.len() - 1)]);
+
+            for color_space in [ColorSpace::Linear, ColorSpace::Gamma].iter() {
+                let mips = image.mip_chain(*color_space);
+                let dimensions: Vec<(u32, u32)> = mips.iter().map(|mip| { (mip.width, mip.height) }).collect();
+                assert_eq!(&dimensions, expected);
+                for mip in mips.iter() {
+                    assert_eq!(mip.pixels.len(), 4 * mip.width as usize * mip.height as usize);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn downsample_averages() {
+        let image = RgbaImage32F::new(2, 2, vec![
+            0.0, 0.0, 0.0, 0.0,
+            1.0, 1.0, 1.0, 1.0,
+            1.0, 0.0, 1.0, 0.0,
+            0.0, 1.0, 0.0, 1.0
+        ]);
+
+        let linear = image.downsample(ColorSpace::Linear);
+        assert_eq!(linear.pixels, vec![0.5; 4]);
+
+        //Gamma-encoded color is averaged in linear space, but alpha never is
+        let gamma = image.downsample(ColorSpace::Gamma);
+        for c in 0..3 {
+            assert!((gamma.pixels[c] - linear_to_srgb(0.5)).abs() < 1.0e-5);
+        }
+        assert_eq!(gamma.pixels[3], 0.5);
+    }
+
+    #[test]
+    fn compressed_sizes_match_output_size() {
+        let formats = [BlockFormat::BC1, BlockFormat::BC3, BlockFormat::BC4, BlockFormat::BC5, BlockFormat::BC6H, BlockFormat::BC7];
+        let mips = gradient(13, 7).mip_chain(ColorSpace::Linear);
+        for format in formats.iter() {
+            let compressed = compress_mip_chain(&mips, *format, ColorSpace::Linear, CompressionQuality::Fast);
+            let expected: usize = mips.iter().map(|mip| { format.output_size(mip.width, mip.height) }).sum();
+            assert_eq!(compressed.format, *format);
+            assert_eq!((compressed.width, compressed.height), (13, 7));
+            assert_eq!(compressed.mipmap_count, mips.len() as u32);
+            assert_eq!(compressed.bytes.len(), expected, "{:?}", format);
+        }
+
+        //Every level rounds up to whole 4x4 blocks
+        assert_eq!(BlockFormat::BC1.output_size(13, 7), 4 * 2 * 8);
+        assert_eq!(BlockFormat::BC4.output_size(1, 1), 8);
+        for format in [BlockFormat::BC3, BlockFormat::BC5, BlockFormat::BC6H, BlockFormat::BC7].iter() {
+            assert_eq!(format.output_size(13, 7), 4 * 2 * 16);
+            assert_eq!(format.output_size(1, 1), 16);
+        }
+    }
+}